@@ -0,0 +1,181 @@
+use chrono::{DateTime, Utc};
+
+use crate::execution::order_types::{ExecType, Liquidity, OrdStatus, Side};
+use crate::market_data::market_data_message::parse_transact_time;
+use crate::utils::decimal_util::{average_price, Price, Qty};
+use crate::utils::message_util::parse_fields;
+
+
+/// An ExecutionReport (8) with the fields the order manager needs
+#[derive(Debug, Clone)]
+pub struct ExecutionReport {
+    pub cl_ord_id: String,
+    /// OrigClOrdID (41), set on cancels and replaces
+    pub orig_cl_ord_id: Option<String>,
+    pub order_id: Option<String>,
+    pub exec_type: ExecType,
+    pub ord_status: OrdStatus,
+    pub symbol: String,
+    pub side: Option<Side>,
+    pub order_qty: Option<Qty>,
+    pub price: Option<Price>,
+    pub cum_qty: Qty,
+    pub leaves_qty: Option<Qty>,
+    /// AvgPx (6), or CumQuoteQty (25017) over CumQty when Binance leaves it out
+    pub avg_px: Option<Price>,
+    pub text: Option<String>,
+    pub transact_time: Option<DateTime<Utc>>,
+    /// LastPx (31), the price of this fill
+    pub last_px: Option<Price>,
+    /// LastQty (32), the quantity of this fill
+    pub last_qty: Option<Qty>,
+    /// Exchange fee from the MiscFees group (137 with 139=4), else Commission (12)
+    pub commission: Option<Qty>,
+    /// MiscFeeCurr (138) of that fee, else CommissionAsset (13)
+    pub commission_asset: Option<String>,
+    /// TradeID (1003)
+    pub trade_id: Option<u64>,
+    pub liquidity: Option<Liquidity>,
+}
+
+impl ExecutionReport {
+    /// The fill this report carries, None unless it is a Trade (150=F) with a price and quantity.
+    /// `side` stands in when the report leaves out Side (54).
+    pub fn fill(&self, side: Side) -> Option<Fill> {
+        if self.exec_type != ExecType::Trade {
+            return None;
+        }
+        Some(Fill {
+            cl_ord_id: self.cl_ord_id.clone(),
+            order_id: self.order_id.clone(),
+            symbol: self.symbol.clone(),
+            side: self.side.unwrap_or(side),
+            price: self.last_px?,
+            qty: self.last_qty.filter(|qty| qty.is_positive())?,
+            commission: self.commission.unwrap_or_default(),
+            commission_asset: self.commission_asset.clone(),
+            trade_id: self.trade_id,
+            liquidity: self.liquidity,
+            cum_qty: self.cum_qty,
+            time: self.transact_time,
+        })
+    }
+}
+
+/// One execution of an order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fill {
+    pub cl_ord_id: String,
+    pub order_id: Option<String>,
+    pub symbol: String,
+    pub side: Side,
+    pub price: Price,
+    pub qty: Qty,
+    pub commission: Qty,
+    pub commission_asset: Option<String>,
+    pub trade_id: Option<u64>,
+    pub liquidity: Option<Liquidity>,
+    /// CumQty of the order including this fill
+    pub cum_qty: Qty,
+    pub time: Option<DateTime<Utc>>,
+}
+
+impl Fill {
+    /// Identifies the fill across repeated reports, e.g. after a reconnect
+    pub fn key(&self) -> String {
+        match self.trade_id {
+            Some(trade_id) => format!("{}:{}", self.symbol, trade_id),
+            // Without a TradeID, the order's CumQty after the fill is unique to it
+            None => format!("{}:{}", self.cl_ord_id, self.cum_qty),
+        }
+    }
+}
+
+pub fn parse_execution_report(message: &str) -> anyhow::Result<ExecutionReport> {
+    let fields = parse_fields(message);
+
+    let mut cl_ord_id = None;
+    let mut exec_type = None;
+    let mut ord_status = None;
+    let mut symbol = None;
+    let mut report = ExecutionReport {
+        cl_ord_id: String::new(),
+        orig_cl_ord_id: None,
+        order_id: None,
+        exec_type: ExecType::New,
+        ord_status: OrdStatus::New,
+        symbol: String::new(),
+        side: None,
+        order_qty: None,
+        price: None,
+        cum_qty: Qty::ZERO,
+        leaves_qty: None,
+        avg_px: None,
+        text: None,
+        transact_time: None,
+        last_px: None,
+        last_qty: None,
+        commission: None,
+        commission_asset: None,
+        trade_id: None,
+        liquidity: None,
+    };
+    let mut cum_quote_qty: Option<Price> = None;
+    // MiscFees entries as (MiscFeeAmt, MiscFeeCurr, MiscFeeType), each starting at its 137
+    let mut misc_fees: Vec<(Qty, Option<String>, Option<String>)> = Vec::new();
+
+    for (tag, value) in fields {
+        match tag {
+            "35" if value != "8" => anyhow::bail!("Not an ExecutionReport: 35={}", value),
+            "11" => cl_ord_id = Some(value.to_string()),
+            "41" => report.orig_cl_ord_id = Some(value.to_string()),
+            "37" => report.order_id = Some(value.to_string()),
+            "150" => exec_type = ExecType::from_fix(value),
+            "39" => ord_status = OrdStatus::from_fix(value),
+            "55" => symbol = Some(value.to_string()),
+            "54" => report.side = Side::from_fix(value),
+            "38" => report.order_qty = Some(value.parse()?),
+            "44" => report.price = Some(value.parse()?),
+            "14" => report.cum_qty = value.parse()?,
+            "151" => report.leaves_qty = Some(value.parse()?),
+            "6" => report.avg_px = Some(value.parse()?),
+            "25017" => cum_quote_qty = Some(value.parse()?),
+            "58" => report.text = Some(value.to_string()),
+            "60" => report.transact_time = Some(parse_transact_time(value)?),
+            "31" => report.last_px = Some(value.parse()?),
+            "32" => report.last_qty = Some(value.parse()?),
+            "12" => report.commission = Some(value.parse()?),
+            "13" => report.commission_asset = Some(value.to_string()),
+            "137" => misc_fees.push((value.parse()?, None, None)),
+            "138" => {
+                if let Some(fee) = misc_fees.last_mut() {
+                    fee.1 = Some(value.to_string());
+                }
+            }
+            "139" => {
+                if let Some(fee) = misc_fees.last_mut() {
+                    fee.2 = Some(value.to_string());
+                }
+            }
+            "1003" => report.trade_id = Some(value.parse()?),
+            "1057" => report.liquidity = Liquidity::from_aggressor_indicator(value),
+            _ => {}
+        }
+    }
+
+    report.cl_ord_id = cl_ord_id.ok_or_else(|| anyhow::anyhow!("ExecutionReport without a ClOrdID (11)"))?;
+    report.exec_type = exec_type.ok_or_else(|| anyhow::anyhow!("ExecutionReport without a known ExecType (150)"))?;
+    report.ord_status = ord_status.ok_or_else(|| anyhow::anyhow!("ExecutionReport without a known OrdStatus (39)"))?;
+    report.symbol = symbol.ok_or_else(|| anyhow::anyhow!("ExecutionReport without a Symbol (55)"))?;
+
+    // Binance reports its fee as MiscFeeType EXCHANGE_FEES (4)
+    if let Some((amount, currency, _)) = misc_fees.into_iter().find(|fee| fee.2.as_deref() == Some("4")) {
+        report.commission = Some(amount);
+        report.commission_asset = currency;
+    }
+    if report.avg_px.is_none() {
+        report.avg_px = cum_quote_qty.and_then(|quote| average_price(quote, report.cum_qty));
+    }
+
+    Ok(report)
+}
@@ -1 +1,6 @@
-pub mod order_execution_client;
\ No newline at end of file
+pub mod execution_report;
+pub mod order_execution_client;
+pub mod order_gateway;
+pub mod order_manager;
+pub mod order_types;
+pub mod position_tracker;
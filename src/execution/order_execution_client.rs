@@ -1,24 +1,33 @@
 use std::env;
 use chrono::Utc;
 use futures_util::{SinkExt, StreamExt};
-use log::{debug, info};
+use log::{debug, error, info, warn};
 use uuid::Uuid;
 use std::sync::Arc;
+use tokio::sync::mpsc::UnboundedReceiver;
 use tokio::sync::Mutex;
 
-use crate::utils::key_util::load_signing_key;
+use crate::execution::execution_report::{parse_execution_report, ExecutionReport};
+use crate::execution::order_gateway::{GatewayRequest, OrderError, PendingRequests};
+use crate::execution::order_manager::OrderUpdateError;
+use crate::execution::order_types::OrdStatus;
+use crate::utils::key_util::SessionCredentials;
 use crate::utils::message_util::{
     compute_raw_data,
     build_logon_message,
-    build_new_order_single,
-    build_order_cancel_request,
     extract_field,
 };
-use crate::utils::connection_util::connect_fix_endpoint;
+use crate::utils::connection_util::{connect_fix_endpoint, ConnectionConfig};
+use crate::utils::redact_util::redact_fix;
+use crate::strategy;
 use crate::types::{StrategyState,};
 
 
-pub async fn start_order_entry_session(strategy: Arc<Mutex<StrategyState>>) -> anyhow::Result<()> {
+pub async fn start_order_entry_session(
+    strategy: Arc<Mutex<StrategyState>>,
+    credentials: SessionCredentials,
+    mut requests: UnboundedReceiver<GatewayRequest>,
+) -> anyhow::Result<()> {
     dotenvy::dotenv().ok();
 
     let sender_comp_id = Uuid::new_v4()
@@ -27,23 +36,20 @@ pub async fn start_order_entry_session(strategy: Arc<Mutex<StrategyState>>) -> a
         .to_string();
 
     let target_comp_id = env::var("BINANCE_TARGET_COMP_ID")?;
-    let username = env::var("BINANCE_API_KEY")?;
-    let signing_key = load_signing_key()?;
 
     let sending_time = Utc::now().format("%Y%m%d-%H:%M:%S%.3f").to_string();
     let msg_seq_num = 1;
 
     let raw_data = compute_raw_data(
-        &signing_key,
+        &credentials.signing_key,
         &sender_comp_id,
         &target_comp_id,
         msg_seq_num,
         &sending_time,
     );
 
-    let hostname = env::var("BINANCE_OE_HOSTNAME").unwrap();
-    let port: u16 = env::var("BINANCE_PORT").unwrap().parse()?;
-    let mut framed = connect_fix_endpoint(&hostname, port).await?;
+    let connection_config = ConnectionConfig::from_env("OE")?;
+    let mut framed = connect_fix_endpoint(&connection_config).await?;
 
     // Send logon
     let logon_msg = build_logon_message(
@@ -52,103 +58,130 @@ pub async fn start_order_entry_session(strategy: Arc<Mutex<StrategyState>>) -> a
         msg_seq_num,
         &sending_time,
         &raw_data,
-        &username,
+        &credentials.api_key,
     );
     framed.send(logon_msg).await?;
     info!("Sent order-entry logon");
 
-    // Wait for logon acknowledgment
+    // Wait for logon acknowledgment, then serve gateway requests until the session ends
     let mut seq = 2;
-    while let Some(msg) = framed.next().await {
-        let msg = msg?;
-        debug!("Received: {}", msg.replace('\x01', "|"));
+    let mut logged_on = false;
+    let mut pending = PendingRequests::default();
+    loop {
+        let msg = tokio::select! {
+            Some(request) = requests.recv() => {
+                if !logged_on {
+                    request.refuse(OrderError::NotLoggedOn);
+                    continue;
+                }
+                // Cancels still go out, they only reduce exposure
+                if let Some(order) = request.request.order() {
+                    if strategy.lock().await.stale_symbols.contains(&order.symbol) {
+                        let symbol = order.symbol.clone();
+                        request.refuse(OrderError::StaleMarketData(symbol));
+                        continue;
+                    }
+                }
+                let message = request.request.to_fix(&sender_comp_id, &target_comp_id, seq);
+                debug!("Sending: {}", redact_fix(&message));
+                if let Err(e) = framed.send(message).await {
+                    request.refuse(OrderError::SessionClosed);
+                    pending.fail_all(OrderError::SessionClosed);
+                    strategy.lock().await.oe_logon_ready = false;
+                    return Err(e.into());
+                }
+                info!("Sent {:?}", request.request);
+                strategy.lock().await.orders.on_request(&request.request, Utc::now());
+                pending.insert(request, seq);
+                seq += 1;
+                continue;
+            }
+            msg = framed.next() => match msg {
+                Some(msg) => msg,
+                None => break,
+            },
+        };
+
+        let msg = match msg {
+            Ok(msg) => msg,
+            Err(e) => {
+                pending.fail_all(OrderError::SessionClosed);
+                strategy.lock().await.oe_logon_ready = false;
+                return Err(e.into());
+            }
+        };
+        debug!("Received: {}", redact_fix(&msg));
+        let failed = pending.on_message(&msg);
+
         if extract_field(&msg, "35").as_deref() == Some("A") {
             info!("Order entry logon successful");
 
-            let mut state = strategy.lock().await;
-            state.oe_logon_ready = true; // Mark order entry session as ready
-
-            // Generate a unique ClOrdID for the order
-            let orig_cl_ord_id = Uuid::new_v4().to_string();
-
-            // Send a NewOrderSingle
-            let order_msg = build_new_order_single(
-                &sender_comp_id,
-                &target_comp_id,
-                seq,
-                "BTCUSDT",
-                "BUY",       // or "SELL"
-                0.0001,      // quantity
-                100000.0,    // limit price
-                &orig_cl_ord_id,
-            );
-            framed.send(order_msg).await?;
-            info!("Sent NewOrderSingle | ClOrdID = {}", orig_cl_ord_id);
-            seq += 1;
-
-            // Wait a bit before canceling
-            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
-
-            // Generate a cancel ClOrdID
-            let cancel_cl_ord_id = Uuid::new_v4().to_string();
-
-            // Send OrderCancelRequest
-            let cancel_msg = build_order_cancel_request(
-                &sender_comp_id,
-                &target_comp_id,
-                seq,
-                "BTCUSDT",
-                &cancel_cl_ord_id,
-                &orig_cl_ord_id,
-            );
-            framed.send(cancel_msg).await?;
-            info!(
-                "Sent OrderCancelRequest | OrigClOrdID = {}, CancelClOrdID = {}",
-                orig_cl_ord_id, cancel_cl_ord_id
-            );
-            seq += 1;
+            logged_on = true;
+            strategy.lock().await.oe_logon_ready = true; // Mark order entry session as ready
         }
         else if extract_field(&msg, "35").as_deref() == Some("8") {
-            let exec_type = extract_field(&msg, "150").unwrap_or_default();
-            if exec_type.is_empty() {
-                info!("ExecutionReport received | ExecType is missing");
-                debug!("Raw ExecutionReport: {}", msg.replace('\x01', "|"));
-            } else {
-                debug!("ExecutionReport | ExecType = {}", exec_type);
-                handle_execution_report(&msg);
+            match parse_execution_report(&msg) {
+                Ok(report) => handle_execution_report(&mut *strategy.lock().await, &report),
+                Err(e) => {
+                    warn!("Failed to parse ExecutionReport: {}", e);
+                    debug!("Raw ExecutionReport: {}", redact_fix(&msg));
+                }
             }
         }
+
+        // Rejected cancels and replaces leave the order as it was
+        if let Some((request, error)) = failed {
+            strategy.lock().await.orders.on_request_failed(&request, &error.to_string(), Utc::now());
+        }
     }
 
+    pending.fail_all(OrderError::SessionClosed);
+    strategy.lock().await.oe_logon_ready = false;
     Ok(())
 }
 
-fn handle_execution_report(message: &str) {
-    let cl_ord_id = extract_field(message, "11").unwrap_or_default();
-    let exec_type = extract_field(message, "150").unwrap_or_default();
-    let _ord_status = extract_field(message, "39").unwrap_or_default();
-    let symbol = extract_field(message, "55").unwrap_or_default();
-    let side = extract_field(message, "54").unwrap_or_default();
-    let qty = extract_field(message, "38").unwrap_or_default();
-    let price = extract_field(message, "44").unwrap_or_default();
-    let text = extract_field(message, "58").unwrap_or_default(); // error reason (if any)
-
-    match exec_type.as_str() {
-        "0" => log::info!(
-            "Order Accepted | Symbol: {} | Side: {} | Qty: {} | Price: {} | ClOrdID: {}",
-            symbol, side, qty, price, cl_ord_id
-        ),
-        "4" => log::info!(
-            "Order Canceled | Symbol: {} | ClOrdID: {}",
-            symbol, cl_ord_id
-        ),
-        "8" => log::error!(
-            "Order Rejected | ClOrdID: {} | Reason: {}",
-            cl_ord_id, text
-        ),
-        _ => log::warn!(
-            "Unknown ExecType received | ExecType: {} | ClOrdID: {}",
-            exec_type, cl_ord_id
-        ),
+fn handle_execution_report(strategy: &mut StrategyState, report: &ExecutionReport) {
+    let update = match strategy.orders.on_execution_report(report, Utc::now()) {
+        Ok(update) => update,
+        Err(e @ OrderUpdateError::DuplicateFill { .. }) => {
+            debug!("Skipping ExecutionReport | {}", e);
+            return;
+        }
+        Err(e) => {
+            warn!("Ignoring ExecutionReport {:?} | ClOrdID: {} | {}", report.exec_type, report.cl_ord_id, e);
+            return;
+        }
+    };
+    let (previous, order, fill) = (update.previous, update.order, update.fill);
+
+    let avg_px = order.avg_px.map(|avg_px| avg_px.to_string()).unwrap_or_else(|| "-".to_string());
+    if let Some(e) = update.inconsistency {
+        warn!(
+            "Order state not updated by ExecutionReport {:?} | ClOrdID: {} | {}{}",
+            report.exec_type, report.cl_ord_id, e,
+            if fill.is_some() { " | Fill recorded" } else { "" }
+        );
+    } else if order.status == OrdStatus::Rejected {
+        error!(
+            "Order Rejected | {} {} {} | ClOrdID: {} | Reason: {}",
+            order.side, order.qty, order.symbol, order.cl_ord_id,
+            order.reject_reason.as_deref().unwrap_or("no reason given")
+        );
+    } else if previous != order.status {
+        info!(
+            "Order {} -> {} | {} {} {} | CumQty: {} | LeavesQty: {} | AvgPx: {} | ClOrdID: {}",
+            previous, order.status, order.side, order.qty, order.symbol,
+            order.cum_qty, order.leaves_qty, avg_px, order.cl_ord_id
+        );
+    } else {
+        debug!(
+            "Order {} ({:?}) | CumQty: {} | LeavesQty: {} | AvgPx: {} | ClOrdID: {}",
+            order.status, report.exec_type, order.cum_qty, order.leaves_qty, avg_px, order.cl_ord_id
+        );
+    }
+
+    if let Some(fill) = fill {
+        strategy.positions.on_fill(&fill);
+        strategy::on_fill(&fill, strategy);
     }
 }
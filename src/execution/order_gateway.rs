@@ -0,0 +1,523 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use log::warn;
+use tokio::sync::{mpsc, oneshot};
+use uuid::Uuid;
+
+use crate::execution::order_types::{OrdType, Side, TimeInForce};
+use crate::utils::decimal_util::{Price, Qty};
+use crate::utils::message_util::{
+    build_new_order_single,
+    build_order_cancel_replace_request,
+    build_order_cancel_request,
+    extract_field,
+};
+
+// ErrorCode (25016) of an order the matching engine refused
+const NEW_ORDER_REJECTED: &str = "-2010";
+const WOULD_CROSS_TEXT: &str = "Order would immediately match and take.";
+
+
+/// An order to place, `NewOrder::new` checks it before it can reach the wire
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NewOrder {
+    pub symbol: String,
+    pub side: Side,
+    pub qty: Qty,
+    pub ord_type: OrdType,
+    /// Only sent for the types that take one, the others must keep GTC
+    pub time_in_force: TimeInForce,
+    pub cl_ord_id: String,
+}
+
+impl NewOrder {
+    /// An order with a fresh ClOrdID, or why it cannot be placed
+    pub fn new(symbol: &str, side: Side, qty: Qty, ord_type: OrdType) -> Result<Self, OrderError> {
+        let order = Self {
+            symbol: symbol.to_string(),
+            side,
+            qty,
+            ord_type,
+            time_in_force: TimeInForce::GoodTillCancel,
+            cl_ord_id: Uuid::new_v4().to_string(),
+        };
+        order.check()?;
+        Ok(order)
+    }
+
+    pub fn limit(symbol: &str, side: Side, qty: Qty, price: Price) -> Result<Self, OrderError> {
+        Self::new(symbol, side, qty, OrdType::Limit { price })
+    }
+
+    pub fn market(symbol: &str, side: Side, qty: Qty) -> Result<Self, OrderError> {
+        Self::new(symbol, side, qty, OrdType::Market)
+    }
+
+    /// A limit order that never takes liquidity, it fails with `OrderError::WouldCross` instead
+    pub fn post_only(symbol: &str, side: Side, qty: Qty, price: Price) -> Result<Self, OrderError> {
+        Self::new(symbol, side, qty, OrdType::LimitMaker { price })
+    }
+
+    /// The same order with IOC or FOK instead of GTC
+    pub fn with_time_in_force(mut self, time_in_force: TimeInForce) -> Result<Self, OrderError> {
+        self.time_in_force = time_in_force;
+        self.check()?;
+        Ok(self)
+    }
+
+    pub fn is_post_only(&self) -> bool {
+        matches!(self.ord_type, OrdType::LimitMaker { .. })
+    }
+
+    /// Limit price, None for orders filled at the market
+    pub fn price(&self) -> Option<Price> {
+        self.ord_type.price()
+    }
+
+    fn check(&self) -> Result<(), OrderError> {
+        if self.symbol.is_empty() {
+            return Err(OrderError::Invalid("no symbol".to_string()));
+        }
+        if !self.qty.is_positive() {
+            return Err(OrderError::Invalid(format!("quantity {} is not positive", self.qty)));
+        }
+        if self.ord_type.price().is_some_and(|price| !price.is_positive()) {
+            return Err(OrderError::Invalid(format!("{} has a price that is not positive", self.ord_type)));
+        }
+        if self.ord_type.trigger_price().is_some_and(|trigger_price| !trigger_price.is_positive()) {
+            return Err(OrderError::Invalid(format!("{} has a trigger price that is not positive", self.ord_type)));
+        }
+        if !self.ord_type.accepts_time_in_force() && self.time_in_force != TimeInForce::GoodTillCancel {
+            return Err(OrderError::Invalid(format!("{} takes no time in force, got {}", self.ord_type.as_str(), self.time_in_force)));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OrderRequest {
+    New(NewOrder),
+    Cancel {
+        symbol: String,
+        cl_ord_id: String,
+        orig_cl_ord_id: String,
+    },
+    /// Cancels `orig_cl_ord_id` and places `order` in its place
+    Amend {
+        orig_cl_ord_id: String,
+        cancel_cl_ord_id: String,
+        order: NewOrder,
+    },
+}
+
+impl OrderRequest {
+    /// ClOrdID the exchange acknowledges the request with
+    pub fn cl_ord_id(&self) -> &str {
+        match self {
+            Self::New(order) | Self::Amend { order, .. } => &order.cl_ord_id,
+            Self::Cancel { cl_ord_id, .. } => cl_ord_id,
+        }
+    }
+
+    /// The order a new or amend request places, None for a cancel
+    pub fn order(&self) -> Option<&NewOrder> {
+        match self {
+            Self::New(order) | Self::Amend { order, .. } => Some(order),
+            Self::Cancel { .. } => None,
+        }
+    }
+
+    // Orders built as struct literals skipped NewOrder::new
+    fn validate(&self) -> Result<(), OrderError> {
+        match self {
+            Self::New(order) | Self::Amend { order, .. } => order.check(),
+            Self::Cancel { .. } => Ok(()),
+        }
+    }
+
+    // A post-only order that would have crossed gets its own error, so the strategy can re-quote.
+    // Binance shares its ErrorCode with other rejects such as a lack of funds, the documented
+    // Text tells them apart and an unknown one is logged.
+    fn rejection(&self, message: &str) -> OrderError {
+        let reason = extract_field(message, "58").unwrap_or_else(|| "no reason given".to_string());
+        let post_only = match self {
+            Self::New(order) | Self::Amend { order, .. } => order.is_post_only(),
+            Self::Cancel { .. } => false,
+        };
+        if !post_only || extract_field(message, "25016").as_deref() != Some(NEW_ORDER_REJECTED) {
+            return OrderError::Rejected(reason);
+        }
+        if reason == WOULD_CROSS_TEXT {
+            OrderError::WouldCross(reason)
+        } else {
+            warn!("Post-only order {} rejected with {}: {}", self.cl_ord_id(), NEW_ORDER_REJECTED, reason);
+            OrderError::Rejected(reason)
+        }
+    }
+
+    /// The FIX message for this request
+    pub fn to_fix(&self, sender_comp_id: &str, target_comp_id: &str, seq_num: i32) -> String {
+        match self {
+            Self::New(order) => build_new_order_single(
+                sender_comp_id,
+                target_comp_id,
+                seq_num,
+                &order.symbol,
+                order.side,
+                order.qty,
+                &order.ord_type,
+                order.time_in_force,
+                &order.cl_ord_id,
+            ),
+            Self::Cancel { symbol, cl_ord_id, orig_cl_ord_id } => build_order_cancel_request(
+                sender_comp_id,
+                target_comp_id,
+                seq_num,
+                symbol,
+                cl_ord_id,
+                orig_cl_ord_id,
+            ),
+            Self::Amend { orig_cl_ord_id, cancel_cl_ord_id, order } => build_order_cancel_replace_request(
+                sender_comp_id,
+                target_comp_id,
+                seq_num,
+                &order.symbol,
+                order.side,
+                order.qty,
+                &order.ord_type,
+                order.time_in_force,
+                cancel_cl_ord_id,
+                orig_cl_ord_id,
+                &order.cl_ord_id,
+            ),
+        }
+    }
+}
+
+/// The exchange accepted the request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrderAck {
+    pub cl_ord_id: String,
+    /// OrderID (37)
+    pub order_id: Option<String>,
+    /// ExecType (150)
+    pub exec_type: String,
+    /// OrdStatus (39)
+    pub ord_status: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OrderError {
+    /// Rejected by the exchange, with its reason
+    Rejected(String),
+    /// A post-only order rejected because it would have taken liquidity
+    WouldCross(String),
+    /// Refused before sending
+    Invalid(String),
+    /// Refused before sending because the symbol's market data is stale or settling
+    StaleMarketData(String),
+    /// The order entry session is not logged on
+    NotLoggedOn,
+    /// The order entry session ended before an answer arrived
+    SessionClosed,
+}
+
+impl fmt::Display for OrderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Rejected(reason) => write!(f, "rejected: {}", reason),
+            Self::WouldCross(reason) => write!(f, "post-only order would cross: {}", reason),
+            Self::Invalid(reason) => write!(f, "invalid order: {}", reason),
+            Self::StaleMarketData(symbol) => write!(f, "market data for {} is not fresh", symbol),
+            Self::NotLoggedOn => write!(f, "order entry session not logged on"),
+            Self::SessionClosed => write!(f, "order entry session closed"),
+        }
+    }
+}
+
+impl std::error::Error for OrderError {}
+
+type Reply = oneshot::Sender<Result<OrderAck, OrderError>>;
+
+/// A request on its way to the order entry session
+#[derive(Debug)]
+pub struct GatewayRequest {
+    pub request: OrderRequest,
+    reply: Reply,
+}
+
+impl GatewayRequest {
+    /// Answers the request without sending it
+    pub fn refuse(self, error: OrderError) {
+        let _ = self.reply.send(Err(error));
+    }
+}
+
+/// Resolves with the exchange's acknowledgement or rejection of one request
+#[derive(Debug)]
+pub struct OrderTicket {
+    cl_ord_id: String,
+    reply: oneshot::Receiver<Result<OrderAck, OrderError>>,
+}
+
+impl OrderTicket {
+    pub fn cl_ord_id(&self) -> &str {
+        &self.cl_ord_id
+    }
+}
+
+impl Future for OrderTicket {
+    type Output = Result<OrderAck, OrderError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.reply)
+            .poll(cx)
+            .map(|reply| reply.unwrap_or(Err(OrderError::SessionClosed)))
+    }
+}
+
+/// Submits, cancels and amends orders on the order entry session
+#[derive(Debug, Clone)]
+pub struct OrderGateway {
+    requests: mpsc::UnboundedSender<GatewayRequest>,
+}
+
+impl OrderGateway {
+    /// Queues the request, the ticket resolves once the exchange answered
+    pub fn send(&self, request: OrderRequest) -> OrderTicket {
+        let (reply, receiver) = oneshot::channel();
+        let ticket = OrderTicket {
+            cl_ord_id: request.cl_ord_id().to_string(),
+            reply: receiver,
+        };
+
+        let request = GatewayRequest { request, reply };
+        if let Err(invalid) = request.request.validate() {
+            request.refuse(invalid);
+        } else if let Err(mpsc::error::SendError(request)) = self.requests.send(request) {
+            request.refuse(OrderError::SessionClosed);
+        }
+        ticket
+    }
+
+    pub fn submit(&self, order: NewOrder) -> OrderTicket {
+        self.send(OrderRequest::New(order))
+    }
+
+    pub fn cancel(&self, symbol: &str, orig_cl_ord_id: &str) -> OrderTicket {
+        self.send(OrderRequest::Cancel {
+            symbol: symbol.to_string(),
+            cl_ord_id: Uuid::new_v4().to_string(),
+            orig_cl_ord_id: orig_cl_ord_id.to_string(),
+        })
+    }
+
+    pub fn amend(&self, orig_cl_ord_id: &str, order: NewOrder) -> OrderTicket {
+        self.send(OrderRequest::Amend {
+            orig_cl_ord_id: orig_cl_ord_id.to_string(),
+            cancel_cl_ord_id: Uuid::new_v4().to_string(),
+            order,
+        })
+    }
+}
+
+pub fn order_gateway_channel() -> (OrderGateway, mpsc::UnboundedReceiver<GatewayRequest>) {
+    let (requests, receiver) = mpsc::unbounded_channel();
+    (OrderGateway { requests }, receiver)
+}
+
+#[derive(Debug)]
+struct PendingRequest {
+    request: OrderRequest,
+    reply: Reply,
+    seq_num: i32,
+}
+
+/// Requests sent on the order entry session that wait for their answer
+#[derive(Debug, Default)]
+pub struct PendingRequests {
+    next_id: u64,
+    requests: HashMap<u64, PendingRequest>,
+    by_cl_ord_id: HashMap<String, u64>,
+    by_seq_num: HashMap<i32, u64>,
+}
+
+impl PendingRequests {
+    /// Tracks a request sent with `seq_num`
+    pub fn insert(&mut self, request: GatewayRequest, seq_num: i32) {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.by_cl_ord_id.insert(request.request.cl_ord_id().to_string(), id);
+        // A failed cancel-replace is reported against the cancel's ClOrdID
+        if let OrderRequest::Amend { cancel_cl_ord_id, .. } = &request.request {
+            self.by_cl_ord_id.insert(cancel_cl_ord_id.clone(), id);
+        }
+        self.by_seq_num.insert(seq_num, id);
+        self.requests.insert(
+            id,
+            PendingRequest {
+                request: request.request,
+                reply: request.reply,
+                seq_num,
+            },
+        );
+    }
+
+    pub fn len(&self) -> usize {
+        self.requests.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty()
+    }
+
+    // Answers the request, a failed one is handed back
+    fn resolve(&mut self, id: u64, result: Result<OrderAck, OrderError>) -> Option<(OrderRequest, OrderError)> {
+        let pending = self.requests.remove(&id)?;
+        self.by_cl_ord_id.remove(pending.request.cl_ord_id());
+        if let OrderRequest::Amend { cancel_cl_ord_id, .. } = &pending.request {
+            self.by_cl_ord_id.remove(cancel_cl_ord_id);
+        }
+        self.by_seq_num.remove(&pending.seq_num);
+        let failure = result.as_ref().err().map(|error| (pending.request, error.clone()));
+        // The caller may have dropped its ticket
+        let _ = pending.reply.send(result);
+        failure
+    }
+
+    fn reject(&mut self, id: u64, message: &str) -> Option<(OrderRequest, OrderError)> {
+        let error = self.requests.get(&id)?.request.rejection(message);
+        self.resolve(id, Err(error))
+    }
+
+    /// Answers the request an ExecutionReport (8), OrderCancelReject (9), Reject (3) or
+    /// BusinessMessageReject (j) refers to and hands it back if it failed. Other messages are ignored.
+    pub fn on_message(&mut self, message: &str) -> Option<(OrderRequest, OrderError)> {
+        match extract_field(message, "35").as_deref() {
+            Some("8") => {
+                let cl_ord_id = extract_field(message, "11")?;
+                let id = self.by_cl_ord_id.get(&cl_ord_id).copied()?;
+                let exec_type = extract_field(message, "150").unwrap_or_default();
+                if exec_type == "8" {
+                    return self.reject(id, message);
+                }
+
+                // A cancel is acknowledged by its Canceled report, an order by the first report on
+                // its own ClOrdID, not the cancel half of a cancel-replace
+                let pending = self.requests.get(&id)?;
+                let acknowledged = match &pending.request {
+                    OrderRequest::Cancel { .. } => exec_type == "4",
+                    request => cl_ord_id == request.cl_ord_id(),
+                };
+                if acknowledged {
+                    let ack = OrderAck {
+                        cl_ord_id,
+                        order_id: extract_field(message, "37"),
+                        exec_type,
+                        ord_status: extract_field(message, "39"),
+                    };
+                    self.resolve(id, Ok(ack));
+                }
+                None
+            }
+            Some("9") => {
+                let id = extract_field(message, "11").and_then(|cl_ord_id| self.by_cl_ord_id.get(&cl_ord_id).copied())?;
+                self.reject(id, message)
+            }
+            Some("3") | Some("j") => {
+                let id = extract_field(message, "45")
+                    .and_then(|seq_num| seq_num.parse::<i32>().ok())
+                    .and_then(|seq_num| self.by_seq_num.get(&seq_num).copied())?;
+                self.reject(id, message)
+            }
+            _ => None,
+        }
+    }
+
+    /// Fails every request still waiting, e.g. when the session ends
+    pub fn fail_all(&mut self, error: OrderError) {
+        let ids: Vec<u64> = self.requests.keys().copied().collect();
+        for id in ids {
+            self.resolve(id, Err(error.clone()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn price(value: &str) -> Price {
+        value.parse().unwrap()
+    }
+
+    fn qty(value: &str) -> Qty {
+        value.parse().unwrap()
+    }
+
+    fn invalid(result: Result<NewOrder, OrderError>) -> String {
+        match result {
+            Err(OrderError::Invalid(reason)) => reason,
+            other => panic!("expected an invalid order, got {:?}", other),
+        }
+    }
+
+    fn reject(fields: &str) -> String {
+        format!("8=FIX.4.4|35=8|11=order-1|150=8|39=8|{}|", fields).replace('|', "\x01")
+    }
+
+    fn post_only() -> OrderRequest {
+        let mut order = NewOrder::post_only("BTCUSDT", Side::Buy, qty("1"), price("100")).unwrap();
+        order.cl_ord_id = "order-1".to_string();
+        OrderRequest::New(order)
+    }
+
+    #[test]
+    fn check_refuses_invalid_orders() {
+        assert!(invalid(NewOrder::market("", Side::Buy, qty("1"))).contains("no symbol"));
+        assert!(invalid(NewOrder::market("BTCUSDT", Side::Buy, Qty::ZERO)).contains("quantity"));
+        assert!(invalid(NewOrder::limit("BTCUSDT", Side::Buy, qty("1"), price("-1"))).contains("price"));
+        let stop = OrdType::StopLoss { trigger_price: Price::ZERO };
+        assert!(invalid(NewOrder::new("BTCUSDT", Side::Sell, qty("1"), stop)).contains("trigger price"));
+
+        let market = NewOrder::market("BTCUSDT", Side::Buy, qty("1")).unwrap();
+        assert!(invalid(market.with_time_in_force(TimeInForce::ImmediateOrCancel)).contains("no time in force"));
+        let limit = NewOrder::limit("BTCUSDT", Side::Buy, qty("1"), price("100")).unwrap();
+        assert!(limit.with_time_in_force(TimeInForce::FillOrKill).is_ok());
+    }
+
+    #[tokio::test]
+    async fn struct_literal_orders_are_checked_on_send() {
+        let (gateway, mut requests) = order_gateway_channel();
+        let mut order = NewOrder::market("BTCUSDT", Side::Buy, qty("1")).unwrap();
+        order.qty = Qty::ZERO;
+
+        assert!(matches!(gateway.submit(order).await, Err(OrderError::Invalid(_))));
+        assert!(requests.try_recv().is_err());
+    }
+
+    #[test]
+    fn crossing_post_only_is_would_cross() {
+        let message = reject("25016=-2010|58=Order would immediately match and take.");
+        assert!(matches!(post_only().rejection(&message), OrderError::WouldCross(_)));
+    }
+
+    #[test]
+    fn other_rejects_stay_rejected() {
+        let funds = reject("25016=-2010|58=Account has insufficient balance for requested action.");
+        assert!(matches!(post_only().rejection(&funds), OrderError::Rejected(_)));
+
+        let other_code = reject("25016=-1013|58=Order would immediately match and take.");
+        assert!(matches!(post_only().rejection(&other_code), OrderError::Rejected(_)));
+
+        // Only post-only orders can cross
+        let limit = OrderRequest::New(NewOrder::limit("BTCUSDT", Side::Buy, qty("1"), price("100")).unwrap());
+        let message = reject("25016=-2010|58=Order would immediately match and take.");
+        assert_eq!(limit.rejection(&message), OrderError::Rejected(WOULD_CROSS_TEXT.to_string()));
+    }
+}
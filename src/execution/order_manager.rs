@@ -0,0 +1,529 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+
+use crate::execution::execution_report::{ExecutionReport, Fill};
+use crate::execution::order_gateway::{NewOrder, OrderRequest};
+use crate::execution::order_types::{OrdStatus, Side};
+use crate::utils::decimal_util::{Price, Qty};
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Order {
+    pub cl_ord_id: String,
+    /// OrderID (37), known once the exchange acknowledged the order
+    pub order_id: Option<String>,
+    pub symbol: String,
+    pub side: Side,
+    pub qty: Qty,
+    pub price: Option<Price>,
+    pub status: OrdStatus,
+    pub cum_qty: Qty,
+    pub leaves_qty: Qty,
+    pub avg_px: Option<Price>,
+    /// ClOrdID of the order this one cancel-replaces
+    pub replaces: Option<String>,
+    pub reject_reason: Option<String>,
+    pub updated_at: DateTime<Utc>,
+    // Status to return to when a pending cancel or replace fails
+    prior_status: Option<OrdStatus>,
+}
+
+impl Order {
+    fn from_request(order: &NewOrder, now: DateTime<Utc>) -> Self {
+        Self {
+            cl_ord_id: order.cl_ord_id.clone(),
+            order_id: None,
+            symbol: order.symbol.clone(),
+            side: order.side,
+            qty: order.qty,
+            price: order.price(),
+            status: OrdStatus::PendingNew,
+            cum_qty: Qty::ZERO,
+            leaves_qty: order.qty,
+            avg_px: None,
+            replaces: None,
+            reject_reason: None,
+            updated_at: now,
+            prior_status: None,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.status.is_open()
+    }
+
+    // Marks a pending cancel or replace, unless the order can no longer take one
+    fn begin_pending(&mut self, pending: OrdStatus, now: DateTime<Utc>) {
+        if self.status.can_become(pending) && self.status != pending {
+            self.prior_status = Some(self.status);
+            self.status = pending;
+            self.updated_at = now;
+        }
+    }
+
+    // Returns a failed pending cancel or replace to the status before it
+    fn end_pending(&mut self, pending: OrdStatus, now: DateTime<Utc>) {
+        if self.status == pending {
+            if let Some(prior) = self.prior_status.take() {
+                self.status = prior;
+                self.updated_at = now;
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OrderUpdateError {
+    /// A report for an order that was never sent and carries too little to track it
+    UnknownOrder(String),
+    InvalidTransition {
+        cl_ord_id: String,
+        from: OrdStatus,
+        to: OrdStatus,
+    },
+    /// CumQty went down
+    CumQtyDecreased {
+        cl_ord_id: String,
+        from: Qty,
+        to: Qty,
+    },
+    /// A fill that was already applied, e.g. reported again after a reconnect
+    DuplicateFill {
+        cl_ord_id: String,
+        trade_id: Option<u64>,
+    },
+}
+
+impl fmt::Display for OrderUpdateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownOrder(cl_ord_id) => write!(f, "unknown order {}", cl_ord_id),
+            Self::InvalidTransition { cl_ord_id, from, to } => {
+                write!(f, "order {} cannot go from {} to {}", cl_ord_id, from, to)
+            }
+            Self::CumQtyDecreased { cl_ord_id, from, to } => {
+                write!(f, "order {} CumQty went down from {} to {}", cl_ord_id, from, to)
+            }
+            Self::DuplicateFill { cl_ord_id, trade_id } => {
+                write!(f, "order {} fill {:?} was already applied", cl_ord_id, trade_id)
+            }
+        }
+    }
+}
+
+impl std::error::Error for OrderUpdateError {}
+
+/// An ExecutionReport applied to its order
+#[derive(Debug)]
+pub struct OrderUpdate<'a> {
+    /// Status before the report
+    pub previous: OrdStatus,
+    pub order: &'a Order,
+    /// The new fill the report carried, recorded even when the order could not take the report
+    pub fill: Option<Fill>,
+    /// Why the report was not applied to the order, which is left unchanged
+    pub inconsistency: Option<OrderUpdateError>,
+}
+
+/// Tracks every order by ClOrdID and OrderID through its lifecycle
+#[derive(Debug, Clone, Default)]
+pub struct OrderManager {
+    orders: HashMap<String, Order>,
+    by_order_id: HashMap<String, String>,
+    /// ClOrdID of a cancel, or the cancel half of a cancel-replace, to the order it targets
+    cancels: HashMap<String, String>,
+    /// Keys of the fills applied to each order. They are dropped once the order reaches a
+    /// terminal state, its CumQty tells repeated fills apart from then on.
+    fills: HashMap<String, HashSet<String>>,
+}
+
+impl OrderManager {
+    /// Called once a request was sent
+    pub fn on_request(&mut self, request: &OrderRequest, now: DateTime<Utc>) {
+        match request {
+            OrderRequest::New(order) => self.insert_new(order, None, now),
+            OrderRequest::Cancel { cl_ord_id, orig_cl_ord_id, .. } => {
+                self.cancels.insert(cl_ord_id.clone(), orig_cl_ord_id.clone());
+                if let Some(orig) = self.orders.get_mut(orig_cl_ord_id) {
+                    orig.begin_pending(OrdStatus::PendingCancel, now);
+                }
+            }
+            OrderRequest::Amend { orig_cl_ord_id, cancel_cl_ord_id, order } => {
+                self.cancels.insert(cancel_cl_ord_id.clone(), orig_cl_ord_id.clone());
+                if let Some(orig) = self.orders.get_mut(orig_cl_ord_id) {
+                    orig.begin_pending(OrdStatus::PendingReplace, now);
+                }
+                self.insert_new(order, Some(orig_cl_ord_id.clone()), now);
+            }
+        }
+    }
+
+    fn insert_new(&mut self, order: &NewOrder, replaces: Option<String>, now: DateTime<Utc>) {
+        let mut order = Order::from_request(order, now);
+        order.replaces = replaces;
+        self.orders.insert(order.cl_ord_id.clone(), order);
+    }
+
+    /// Called when the exchange or the session refused a request, e.g. a rejected cancel
+    pub fn on_request_failed(&mut self, request: &OrderRequest, reason: &str, now: DateTime<Utc>) {
+        let (new_order, target) = match request {
+            OrderRequest::New(order) => (Some(&order.cl_ord_id), None),
+            OrderRequest::Cancel { orig_cl_ord_id, .. } => (None, Some((orig_cl_ord_id, OrdStatus::PendingCancel))),
+            OrderRequest::Amend { orig_cl_ord_id, order, .. } => {
+                (Some(&order.cl_ord_id), Some((orig_cl_ord_id, OrdStatus::PendingReplace)))
+            }
+        };
+
+        if let Some(order) = new_order.and_then(|cl_ord_id| self.orders.get_mut(cl_ord_id)) {
+            if order.status == OrdStatus::PendingNew {
+                order.status = OrdStatus::Rejected;
+                order.leaves_qty = Qty::ZERO;
+                order.reject_reason = Some(reason.to_string());
+                order.updated_at = now;
+            }
+        }
+        if let Some((orig_cl_ord_id, pending)) = target {
+            if let Some(orig) = self.orders.get_mut(orig_cl_ord_id) {
+                orig.end_pending(pending, now);
+            }
+        }
+    }
+
+    // The tracked order a report is about, a cancel's report names the cancel's own ClOrdID
+    fn find_key(&self, report: &ExecutionReport) -> Option<String> {
+        if let Some(target) = self.cancels.get(&report.cl_ord_id) {
+            return Some(target.clone());
+        }
+        if self.orders.contains_key(&report.cl_ord_id) {
+            return Some(report.cl_ord_id.clone());
+        }
+        if let Some(orig) = report.orig_cl_ord_id.as_ref().filter(|orig| self.orders.contains_key(*orig)) {
+            return Some(orig.clone());
+        }
+        report
+            .order_id
+            .as_ref()
+            .and_then(|order_id| self.by_order_id.get(order_id))
+            .cloned()
+    }
+
+    /// Applies an ExecutionReport to its order. Orders placed outside this manager are picked up
+    /// from their first report, fills already applied are refused. A new fill is recorded even
+    /// if the report does not fit the order's state, since the exchange did fill.
+    pub fn on_execution_report(
+        &mut self,
+        report: &ExecutionReport,
+        now: DateTime<Utc>,
+    ) -> Result<OrderUpdate<'_>, OrderUpdateError> {
+        let (key, adopted) = match self.find_key(report) {
+            Some(key) => (key, false),
+            None => {
+                let side = report.side.ok_or_else(|| OrderUpdateError::UnknownOrder(report.cl_ord_id.clone()))?;
+                let order = Order {
+                    cl_ord_id: report.cl_ord_id.clone(),
+                    order_id: None,
+                    symbol: report.symbol.clone(),
+                    side,
+                    qty: report.order_qty.unwrap_or_default(),
+                    price: report.price,
+                    status: report.ord_status,
+                    cum_qty: Qty::ZERO,
+                    leaves_qty: report.order_qty.unwrap_or_default(),
+                    avg_px: None,
+                    replaces: None,
+                    reject_reason: None,
+                    updated_at: now,
+                    prior_status: None,
+                };
+                self.orders.insert(order.cl_ord_id.clone(), order);
+                (report.cl_ord_id.clone(), true)
+            }
+        };
+
+        let Some(order) = self.orders.get_mut(&key) else {
+            return Err(OrderUpdateError::UnknownOrder(key));
+        };
+        let previous = order.status;
+
+        let fill = report.fill(order.side);
+        if let Some(fill) = &fill {
+            let applied = self.fills.get(&key).is_some_and(|keys| keys.contains(&fill.key()));
+            if applied || (previous.is_terminal() && report.cum_qty <= order.cum_qty) {
+                return Err(OrderUpdateError::DuplicateFill {
+                    cl_ord_id: key,
+                    trade_id: fill.trade_id,
+                });
+            }
+            self.fills.entry(key.clone()).or_default().insert(fill.key());
+        }
+
+        // A repeated report, e.g. after a reconnect, changes nothing
+        let repeated = previous == report.ord_status && order.cum_qty == report.cum_qty;
+        let inconsistency = if repeated || adopted {
+            None
+        } else if !previous.can_become(report.ord_status) {
+            Some(OrderUpdateError::InvalidTransition {
+                cl_ord_id: key.clone(),
+                from: previous,
+                to: report.ord_status,
+            })
+        } else if report.cum_qty < order.cum_qty {
+            Some(OrderUpdateError::CumQtyDecreased {
+                cl_ord_id: key.clone(),
+                from: order.cum_qty,
+                to: report.cum_qty,
+            })
+        } else {
+            None
+        };
+        if inconsistency.is_some() {
+            return Ok(OrderUpdate {
+                previous,
+                order: &self.orders[&key],
+                fill,
+                inconsistency,
+            });
+        }
+
+        order.status = report.ord_status;
+        if !matches!(order.status, OrdStatus::PendingCancel | OrdStatus::PendingReplace) {
+            order.prior_status = None;
+        }
+        if let Some(order_id) = &report.order_id {
+            order.order_id = Some(order_id.clone());
+            self.by_order_id.insert(order_id.clone(), key.clone());
+        }
+        if let Some(qty) = report.order_qty {
+            order.qty = qty;
+        }
+        if report.price.is_some() {
+            order.price = report.price;
+        }
+        order.cum_qty = report.cum_qty;
+        order.leaves_qty = match report.leaves_qty {
+            Some(leaves_qty) => leaves_qty,
+            None if order.status.is_terminal() => Qty::ZERO,
+            None => order.qty - order.cum_qty,
+        };
+        if report.avg_px.is_some() {
+            order.avg_px = report.avg_px;
+        }
+        if order.status == OrdStatus::Rejected {
+            order.reject_reason = report.text.clone();
+        }
+        order.updated_at = now;
+
+        if order.status.is_terminal() {
+            self.cancels.retain(|_, target| *target != key);
+            self.fills.remove(&key);
+        }
+        Ok(OrderUpdate {
+            previous,
+            order: &self.orders[&key],
+            fill,
+            inconsistency: None,
+        })
+    }
+
+    pub fn get(&self, cl_ord_id: &str) -> Option<&Order> {
+        self.orders.get(cl_ord_id)
+    }
+
+    pub fn get_by_order_id(&self, order_id: &str) -> Option<&Order> {
+        self.by_order_id.get(order_id).and_then(|cl_ord_id| self.orders.get(cl_ord_id))
+    }
+
+    /// Open orders of a symbol, of one side or both
+    pub fn open_orders(&self, symbol: &str, side: Option<Side>) -> Vec<&Order> {
+        self.orders
+            .values()
+            .filter(|order| order.is_open() && order.symbol == symbol)
+            .filter(|order| side.is_none_or(|side| order.side == side))
+            .collect()
+    }
+
+    pub fn all_open_orders(&self) -> Vec<&Order> {
+        self.orders.values().filter(|order| order.is_open()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execution::execution_report::parse_execution_report;
+
+    fn report(fields: &str) -> ExecutionReport {
+        parse_execution_report(&format!("8=FIX.4.4|35=8|55=BTCUSDT|{}|", fields).replace('|', "\x01")).unwrap()
+    }
+
+    fn placed(manager: &mut OrderManager, now: DateTime<Utc>) -> String {
+        let order = NewOrder::limit("BTCUSDT", Side::Buy, "2".parse().unwrap(), "100".parse().unwrap()).unwrap();
+        let cl_ord_id = order.cl_ord_id.clone();
+        manager.on_request(&OrderRequest::New(order), now);
+        manager
+            .on_execution_report(&report(&format!("11={}|37=1|150=0|39=0|54=1|38=2|44=100|14=0|151=2", cl_ord_id)), now)
+            .unwrap();
+        cl_ord_id
+    }
+
+    fn trade(cl_ord_id: &str, trade_id: u64, last_qty: &str, cum_qty: &str, status: &str) -> ExecutionReport {
+        report(&format!(
+            "11={}|37=1|150=F|39={}|54=1|38=2|31=100|32={}|14={}|1003={}",
+            cl_ord_id, status, last_qty, cum_qty, trade_id
+        ))
+    }
+
+    #[test]
+    fn lifecycle_to_filled() {
+        let now = Utc::now();
+        let mut manager = OrderManager::default();
+        let cl_ord_id = placed(&mut manager, now);
+        assert_eq!(manager.get_by_order_id("1").unwrap().status, OrdStatus::New);
+        assert_eq!(manager.open_orders("BTCUSDT", Some(Side::Buy)).len(), 1);
+
+        let update = manager.on_execution_report(&trade(&cl_ord_id, 7, "0.5", "0.5", "1"), now).unwrap();
+        assert_eq!(update.previous, OrdStatus::New);
+        assert_eq!(update.order.leaves_qty, "1.5".parse().unwrap());
+        assert_eq!(update.fill.unwrap().qty, "0.5".parse().unwrap());
+
+        let update = manager.on_execution_report(&trade(&cl_ord_id, 8, "1.5", "2", "2"), now).unwrap();
+        assert_eq!(update.order.status, OrdStatus::Filled);
+        assert_eq!(update.order.leaves_qty, Qty::ZERO);
+        assert!(update.inconsistency.is_none());
+        assert!(manager.all_open_orders().is_empty());
+        assert!(manager.fills.is_empty());
+    }
+
+    #[test]
+    fn repeated_fills_are_refused() {
+        let now = Utc::now();
+        let mut manager = OrderManager::default();
+        let cl_ord_id = placed(&mut manager, now);
+
+        manager.on_execution_report(&trade(&cl_ord_id, 7, "0.5", "0.5", "1"), now).unwrap();
+        let err = manager.on_execution_report(&trade(&cl_ord_id, 7, "0.5", "0.5", "1"), now).unwrap_err();
+        assert_eq!(err, OrderUpdateError::DuplicateFill { cl_ord_id: cl_ord_id.clone(), trade_id: Some(7) });
+
+        // Once terminal, CumQty tells a resent fill apart
+        manager.on_execution_report(&trade(&cl_ord_id, 8, "1.5", "2", "2"), now).unwrap();
+        let err = manager.on_execution_report(&trade(&cl_ord_id, 8, "1.5", "2", "2"), now).unwrap_err();
+        assert!(matches!(err, OrderUpdateError::DuplicateFill { trade_id: Some(8), .. }));
+    }
+
+    #[test]
+    fn fill_is_recorded_when_state_does_not_fit() {
+        let now = Utc::now();
+        let mut manager = OrderManager::default();
+        let cl_ord_id = placed(&mut manager, now);
+        manager
+            .on_execution_report(&report(&format!("11={}|37=1|150=4|39=4|54=1|38=2|14=0", cl_ord_id)), now)
+            .unwrap();
+
+        // The exchange filled before the cancel, the report arrives late
+        let update = manager.on_execution_report(&trade(&cl_ord_id, 9, "1", "1", "1"), now).unwrap();
+        assert!(update.fill.is_some());
+        assert!(matches!(update.inconsistency, Some(OrderUpdateError::InvalidTransition { .. })));
+        assert_eq!(update.order.status, OrdStatus::Canceled);
+        assert!(manager.on_execution_report(&trade(&cl_ord_id, 9, "1", "1", "1"), now).is_err());
+    }
+
+    #[test]
+    fn cum_qty_must_not_decrease() {
+        let now = Utc::now();
+        let mut manager = OrderManager::default();
+        let cl_ord_id = placed(&mut manager, now);
+        manager.on_execution_report(&trade(&cl_ord_id, 7, "1", "1", "1"), now).unwrap();
+
+        let update = manager
+            .on_execution_report(&report(&format!("11={}|37=1|150=0|39=1|54=1|38=2|14=0.5", cl_ord_id)), now)
+            .unwrap();
+        assert!(matches!(update.inconsistency, Some(OrderUpdateError::CumQtyDecreased { .. })));
+        assert_eq!(update.order.cum_qty, "1".parse().unwrap());
+    }
+
+    #[test]
+    fn failed_cancel_restores_the_order() {
+        let now = Utc::now();
+        let mut manager = OrderManager::default();
+        let cl_ord_id = placed(&mut manager, now);
+        let cancel = OrderRequest::Cancel {
+            symbol: "BTCUSDT".to_string(),
+            cl_ord_id: "cancel-1".to_string(),
+            orig_cl_ord_id: cl_ord_id.clone(),
+        };
+
+        manager.on_request(&cancel, now);
+        assert_eq!(manager.get(&cl_ord_id).unwrap().status, OrdStatus::PendingCancel);
+        manager.on_request_failed(&cancel, "unknown order", now);
+        assert_eq!(manager.get(&cl_ord_id).unwrap().status, OrdStatus::New);
+
+        // A cancel's report names the cancel's ClOrdID
+        manager.on_request(&cancel, now);
+        let update = manager
+            .on_execution_report(&report(&format!("11=cancel-1|41={}|37=1|150=4|39=4|54=1|14=0", cl_ord_id)), now)
+            .unwrap();
+        assert_eq!(update.order.cl_ord_id, cl_ord_id);
+        assert_eq!(update.order.status, OrdStatus::Canceled);
+        assert!(manager.cancels.is_empty());
+    }
+
+    #[test]
+    fn amend_replaces_the_order() {
+        let now = Utc::now();
+        let mut manager = OrderManager::default();
+        let cl_ord_id = placed(&mut manager, now);
+        let order = NewOrder::limit("BTCUSDT", Side::Buy, "2".parse().unwrap(), "99".parse().unwrap()).unwrap();
+        let new_cl_ord_id = order.cl_ord_id.clone();
+
+        manager.on_request(
+            &OrderRequest::Amend {
+                orig_cl_ord_id: cl_ord_id.clone(),
+                cancel_cl_ord_id: "cancel-1".to_string(),
+                order,
+            },
+            now,
+        );
+        assert_eq!(manager.get(&cl_ord_id).unwrap().status, OrdStatus::PendingReplace);
+        assert_eq!(manager.get(&new_cl_ord_id).unwrap().replaces.as_deref(), Some(cl_ord_id.as_str()));
+
+        manager
+            .on_execution_report(&report(&format!("11=cancel-1|41={}|37=1|150=4|39=4|54=1|14=0", cl_ord_id)), now)
+            .unwrap();
+        manager
+            .on_execution_report(&report(&format!("11={}|37=2|150=0|39=0|54=1|38=2|44=99|14=0", new_cl_ord_id)), now)
+            .unwrap();
+        assert_eq!(manager.all_open_orders().len(), 1);
+        assert_eq!(manager.get_by_order_id("2").unwrap().price, Some("99".parse().unwrap()));
+    }
+
+    #[test]
+    fn rejected_request() {
+        let now = Utc::now();
+        let mut manager = OrderManager::default();
+        let order = NewOrder::market("BTCUSDT", Side::Sell, "1".parse().unwrap()).unwrap();
+        let request = OrderRequest::New(order.clone());
+        manager.on_request(&request, now);
+        manager.on_request_failed(&request, "insufficient balance", now);
+
+        let order = manager.get(&order.cl_ord_id).unwrap();
+        assert_eq!(order.status, OrdStatus::Rejected);
+        assert_eq!(order.reject_reason.as_deref(), Some("insufficient balance"));
+        assert!(!order.is_open());
+    }
+
+    #[test]
+    fn unknown_orders_are_adopted() {
+        let now = Utc::now();
+        let mut manager = OrderManager::default();
+        let update = manager
+            .on_execution_report(&report("11=web-1|37=5|150=0|39=0|54=2|38=1|44=101|14=0"), now)
+            .unwrap();
+        assert_eq!(update.order.side, Side::Sell);
+        assert_eq!(manager.open_orders("BTCUSDT", Some(Side::Sell)).len(), 1);
+
+        let err = manager.on_execution_report(&report("11=web-2|150=0|39=0|14=0"), now).unwrap_err();
+        assert_eq!(err, OrderUpdateError::UnknownOrder("web-2".to_string()));
+    }
+}
@@ -0,0 +1,314 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::utils::decimal_util::Price;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+impl Side {
+    /// Side (54)
+    pub fn from_fix(value: &str) -> Option<Self> {
+        match value {
+            "1" => Some(Self::Buy),
+            "2" => Some(Self::Sell),
+            _ => None,
+        }
+    }
+
+    pub fn fix_code(&self) -> &'static str {
+        match self {
+            Self::Buy => "1",
+            Self::Sell => "2",
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Buy => "BUY",
+            Self::Sell => "SELL",
+        }
+    }
+}
+
+impl fmt::Display for Side {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+impl FromStr for Side {
+    type Err = anyhow::Error;
+
+    fn from_str(side: &str) -> anyhow::Result<Self> {
+        match side.trim().to_ascii_uppercase().as_str() {
+            "BUY" => Ok(Self::Buy),
+            "SELL" => Ok(Self::Sell),
+            _ => anyhow::bail!("Invalid side '{}', expected BUY or SELL", side),
+        }
+    }
+}
+
+/// Order type with the prices it needs. Stop and take-profit orders trigger when the last
+/// trade price reaches `trigger_price`, a stop-loss against the order's side, a take-profit with it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrdType {
+    Market,
+    Limit { price: Price },
+    /// Market order once triggered
+    StopLoss { trigger_price: Price },
+    /// Limit order at `price` once triggered
+    StopLimit { price: Price, trigger_price: Price },
+    TakeProfit { trigger_price: Price },
+    TakeProfitLimit { price: Price, trigger_price: Price },
+    /// Limit order the exchange rejects if it would take liquidity
+    LimitMaker { price: Price },
+}
+
+impl OrdType {
+    /// Limit price, None for orders filled at the market
+    pub fn price(&self) -> Option<Price> {
+        match self {
+            Self::Limit { price }
+            | Self::StopLimit { price, .. }
+            | Self::TakeProfitLimit { price, .. }
+            | Self::LimitMaker { price } => Some(*price),
+            Self::Market | Self::StopLoss { .. } | Self::TakeProfit { .. } => None,
+        }
+    }
+
+    pub fn trigger_price(&self) -> Option<Price> {
+        match self {
+            Self::StopLoss { trigger_price }
+            | Self::StopLimit { trigger_price, .. }
+            | Self::TakeProfit { trigger_price }
+            | Self::TakeProfitLimit { trigger_price, .. } => Some(*trigger_price),
+            Self::Market | Self::Limit { .. } | Self::LimitMaker { .. } => None,
+        }
+    }
+
+    /// OrdType (40)
+    pub fn fix_code(&self) -> &'static str {
+        match self {
+            Self::Market => "1",
+            Self::Limit { .. } | Self::LimitMaker { .. } => "2",
+            Self::StopLoss { .. } | Self::TakeProfit { .. } => "3",
+            Self::StopLimit { .. } | Self::TakeProfitLimit { .. } => "4",
+        }
+    }
+
+    /// TriggerPriceDirection (1109): a buy stop triggers on the way up, a buy take-profit on the way down
+    pub fn trigger_direction(&self, side: Side) -> Option<&'static str> {
+        let stop = match self {
+            Self::StopLoss { .. } | Self::StopLimit { .. } => true,
+            Self::TakeProfit { .. } | Self::TakeProfitLimit { .. } => false,
+            Self::Market | Self::Limit { .. } | Self::LimitMaker { .. } => return None,
+        };
+        Some(if stop == (side == Side::Buy) { "U" } else { "D" })
+    }
+
+    /// Whether the type takes a TimeInForce (59), Binance refuses one on the others
+    pub fn accepts_time_in_force(&self) -> bool {
+        matches!(self, Self::Limit { .. } | Self::StopLimit { .. } | Self::TakeProfitLimit { .. })
+    }
+
+    /// Binance's name for the type
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Market => "MARKET",
+            Self::Limit { .. } => "LIMIT",
+            Self::StopLoss { .. } => "STOP_LOSS",
+            Self::StopLimit { .. } => "STOP_LOSS_LIMIT",
+            Self::TakeProfit { .. } => "TAKE_PROFIT",
+            Self::TakeProfitLimit { .. } => "TAKE_PROFIT_LIMIT",
+            Self::LimitMaker { .. } => "LIMIT_MAKER",
+        }
+    }
+}
+
+impl fmt::Display for OrdType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())?;
+        if let Some(price) = self.price() {
+            write!(f, " @ {}", price)?;
+        }
+        if let Some(trigger_price) = self.trigger_price() {
+            write!(f, " triggered at {}", trigger_price)?;
+        }
+        Ok(())
+    }
+}
+
+/// TimeInForce (59)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum TimeInForce {
+    /// Rests until filled or canceled
+    #[default]
+    GoodTillCancel,
+    /// Fills what it can at once, the rest is canceled
+    ImmediateOrCancel,
+    /// Fills completely at once or not at all
+    FillOrKill,
+}
+
+impl TimeInForce {
+    pub fn fix_code(&self) -> &'static str {
+        match self {
+            Self::GoodTillCancel => "1",
+            Self::ImmediateOrCancel => "3",
+            Self::FillOrKill => "4",
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::GoodTillCancel => "GTC",
+            Self::ImmediateOrCancel => "IOC",
+            Self::FillOrKill => "FOK",
+        }
+    }
+}
+
+impl fmt::Display for TimeInForce {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+impl FromStr for TimeInForce {
+    type Err = anyhow::Error;
+
+    fn from_str(time_in_force: &str) -> anyhow::Result<Self> {
+        match time_in_force.trim().to_ascii_uppercase().as_str() {
+            "GTC" => Ok(Self::GoodTillCancel),
+            "IOC" => Ok(Self::ImmediateOrCancel),
+            "FOK" => Ok(Self::FillOrKill),
+            _ => anyhow::bail!("Invalid time in force '{}', expected GTC, IOC or FOK", time_in_force),
+        }
+    }
+}
+
+/// OrdStatus (39)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OrdStatus {
+    /// Sent, not yet acknowledged
+    PendingNew,
+    New,
+    PartiallyFilled,
+    Filled,
+    Canceled,
+    /// Cancel-replaced by another order
+    Replaced,
+    PendingCancel,
+    PendingReplace,
+    Rejected,
+    Expired,
+}
+
+impl OrdStatus {
+    pub fn from_fix(value: &str) -> Option<Self> {
+        match value {
+            "0" => Some(Self::New),
+            "1" => Some(Self::PartiallyFilled),
+            "2" => Some(Self::Filled),
+            "4" => Some(Self::Canceled),
+            "5" => Some(Self::Replaced),
+            "6" => Some(Self::PendingCancel),
+            "8" => Some(Self::Rejected),
+            "A" => Some(Self::PendingNew),
+            "C" => Some(Self::Expired),
+            "E" => Some(Self::PendingReplace),
+            _ => None,
+        }
+    }
+
+    /// No further reports are expected
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, Self::Filled | Self::Canceled | Self::Replaced | Self::Rejected | Self::Expired)
+    }
+
+    /// Resting on the book or on its way there
+    pub fn is_open(&self) -> bool {
+        !self.is_terminal()
+    }
+
+    /// Whether an order may move from this status to `next`. An open status may repeat,
+    /// e.g. several partial fills, a terminal one may not.
+    pub fn can_become(&self, next: OrdStatus) -> bool {
+        use OrdStatus::*;
+
+        if *self == next {
+            return !self.is_terminal();
+        }
+        match self {
+            PendingNew => matches!(next, New | PartiallyFilled | Filled | Canceled | Rejected | Expired),
+            New | PartiallyFilled => matches!(
+                next,
+                PartiallyFilled | Filled | Canceled | Replaced | PendingCancel | PendingReplace | Expired
+            ),
+            // A rejected cancel or replace returns the order to New or PartiallyFilled
+            PendingCancel => matches!(next, New | PartiallyFilled | Filled | Canceled | Expired),
+            PendingReplace => matches!(next, New | PartiallyFilled | Filled | Canceled | Replaced | Expired),
+            Filled | Canceled | Replaced | Rejected | Expired => false,
+        }
+    }
+}
+
+impl fmt::Display for OrdStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+/// ExecType (150)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ExecType {
+    New,
+    Canceled,
+    Replaced,
+    PendingCancel,
+    Rejected,
+    PendingNew,
+    Expired,
+    PendingReplace,
+    Trade,
+}
+
+impl ExecType {
+    pub fn from_fix(value: &str) -> Option<Self> {
+        match value {
+            "0" => Some(Self::New),
+            "4" => Some(Self::Canceled),
+            "5" => Some(Self::Replaced),
+            "6" => Some(Self::PendingCancel),
+            "8" => Some(Self::Rejected),
+            "A" => Some(Self::PendingNew),
+            "C" => Some(Self::Expired),
+            "E" => Some(Self::PendingReplace),
+            "F" => Some(Self::Trade),
+            _ => None,
+        }
+    }
+}
+
+/// Whether a fill added or took liquidity
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Liquidity {
+    Maker,
+    Taker,
+}
+
+impl Liquidity {
+    /// AggressorIndicator (1057), Y when the order took liquidity
+    pub fn from_aggressor_indicator(value: &str) -> Option<Self> {
+        match value {
+            "Y" => Some(Self::Taker),
+            "N" => Some(Self::Maker),
+            _ => None,
+        }
+    }
+}
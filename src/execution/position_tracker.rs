@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+
+use crate::execution::execution_report::Fill;
+use crate::execution::order_types::Side;
+use crate::utils::decimal_util::{average_price, notional, Price, Qty};
+
+
+/// Net position of one symbol, built from fills
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Position {
+    /// Positive when long, negative when short
+    pub qty: Qty,
+    /// Average entry price of the open quantity, None when flat
+    pub avg_px: Option<Price>,
+    /// Quote amount realized by closing trades, before commissions
+    pub realized_pnl: Price,
+    /// Commission paid per asset
+    pub commissions: HashMap<String, Qty>,
+    pub fills: u64,
+}
+
+impl Position {
+    pub fn is_flat(&self) -> bool {
+        self.qty.is_zero()
+    }
+
+    fn apply(&mut self, fill: &Fill) {
+        let open = abs(self.qty);
+        let adds = self.qty.is_zero() || (self.qty.is_positive() == (fill.side == Side::Buy));
+
+        if adds {
+            let cost = self.avg_px.map(|avg_px| notional(avg_px, open)).unwrap_or_default() + notional(fill.price, fill.qty);
+            self.avg_px = average_price(cost, open + fill.qty);
+        } else {
+            let avg_px = self.avg_px.unwrap_or(fill.price);
+            let closed = open.min(fill.qty);
+            let gain = if self.qty.is_positive() { fill.price - avg_px } else { avg_px - fill.price };
+            self.realized_pnl += notional(gain, closed);
+            // A fill larger than the position flips it, the rest opens at the fill price
+            self.avg_px = if fill.qty > open { Some(fill.price) } else if fill.qty == open { None } else { Some(avg_px) };
+        }
+
+        match fill.side {
+            Side::Buy => self.qty += fill.qty,
+            Side::Sell => self.qty -= fill.qty,
+        }
+        if !fill.commission.is_zero() {
+            let asset = fill.commission_asset.clone().unwrap_or_default();
+            *self.commissions.entry(asset).or_default() += fill.commission;
+        }
+        self.fills += 1;
+    }
+}
+
+fn abs(qty: Qty) -> Qty {
+    if qty.is_positive() { qty } else { Qty::ZERO - qty }
+}
+
+/// Positions per symbol, the caller hands in each fill once
+#[derive(Debug, Clone, Default)]
+pub struct PositionTracker {
+    positions: HashMap<String, Position>,
+}
+
+impl PositionTracker {
+    pub fn on_fill(&mut self, fill: &Fill) -> &Position {
+        let position = self.positions.entry(fill.symbol.clone()).or_default();
+        position.apply(fill);
+        position
+    }
+
+    pub fn get(&self, symbol: &str) -> Option<&Position> {
+        self.positions.get(symbol)
+    }
+
+    pub fn positions(&self) -> impl Iterator<Item = (&str, &Position)> {
+        self.positions.iter().map(|(symbol, position)| (symbol.as_str(), position))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fill(side: Side, qty: &str, price: &str, commission: &str) -> Fill {
+        Fill {
+            cl_ord_id: "1".to_string(),
+            order_id: None,
+            symbol: "BTCUSDT".to_string(),
+            side,
+            price: price.parse().unwrap(),
+            qty: qty.parse().unwrap(),
+            commission: commission.parse().unwrap(),
+            commission_asset: Some("BNB".to_string()),
+            trade_id: None,
+            liquidity: None,
+            cum_qty: Qty::ZERO,
+            time: None,
+        }
+    }
+
+    fn qty(value: &str) -> Qty {
+        value.parse().unwrap()
+    }
+
+    fn px(value: &str) -> Price {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn average_entry_price() {
+        let mut tracker = PositionTracker::default();
+        tracker.on_fill(&fill(Side::Buy, "1", "100", "0.01"));
+        let position = tracker.on_fill(&fill(Side::Buy, "3", "200", "0.02"));
+
+        assert_eq!(position.qty, qty("4"));
+        assert_eq!(position.avg_px, Some(px("175")));
+        assert_eq!(position.realized_pnl, Price::ZERO);
+        assert_eq!(position.commissions["BNB"], qty("0.03"));
+        assert_eq!(position.fills, 2);
+    }
+
+    #[test]
+    fn closing_realizes_pnl() {
+        let mut tracker = PositionTracker::default();
+        tracker.on_fill(&fill(Side::Buy, "2", "100", "0"));
+        let position = tracker.on_fill(&fill(Side::Sell, "0.5", "110", "0"));
+        assert_eq!(position.qty, qty("1.5"));
+        assert_eq!(position.avg_px, Some(px("100")));
+        assert_eq!(position.realized_pnl, px("5"));
+
+        let position = tracker.on_fill(&fill(Side::Sell, "1.5", "90", "0"));
+        assert!(position.is_flat());
+        assert_eq!(position.avg_px, None);
+        assert_eq!(position.realized_pnl, px("-10"));
+        assert!(position.commissions.is_empty());
+    }
+
+    #[test]
+    fn short_position_flips_to_long() {
+        let mut tracker = PositionTracker::default();
+        tracker.on_fill(&fill(Side::Sell, "1", "100", "0"));
+        assert_eq!(tracker.get("BTCUSDT").unwrap().qty, qty("-1"));
+
+        let position = tracker.on_fill(&fill(Side::Buy, "3", "90", "0"));
+        assert_eq!(position.qty, qty("2"));
+        assert_eq!(position.avg_px, Some(px("90")));
+        assert_eq!(position.realized_pnl, px("10"));
+    }
+
+    #[test]
+    fn positions_per_symbol() {
+        let mut tracker = PositionTracker::default();
+        tracker.on_fill(&fill(Side::Buy, "1", "100", "0"));
+        let mut eth = fill(Side::Sell, "2", "3000", "0");
+        eth.symbol = "ETHUSDT".to_string();
+        tracker.on_fill(&eth);
+
+        assert_eq!(tracker.positions().count(), 2);
+        assert_eq!(tracker.get("ETHUSDT").unwrap().qty, qty("-2"));
+        assert!(tracker.get("SOLUSDT").is_none());
+    }
+}
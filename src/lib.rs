@@ -0,0 +1,5 @@
+pub mod types;
+pub mod utils;
+pub mod market_data;
+pub mod execution;
+pub mod strategy;
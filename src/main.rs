@@ -1,13 +1,23 @@
-mod types;
-mod utils;
-mod market_data;
-mod execution;
-
+use std::collections::{HashMap, HashSet};
 use std::io::Write;
+use std::path::PathBuf;
 use std::sync::Arc;
+use chrono::{DateTime, Utc};
 use tokio::sync::Mutex;
 
-use crate::types::{StrategyState,};
+use kraken_ws_rust_bot::execution::order_manager::OrderManager;
+use kraken_ws_rust_bot::execution::position_tracker::PositionTracker;
+use kraken_ws_rust_bot::market_data::candle_aggregator::CandleAggregator;
+use kraken_ws_rust_bot::market_data::freshness_monitor::{FreshnessConfig, FreshnessMonitor};
+use kraken_ws_rust_bot::market_data::market_data_bus::{BackpressurePolicy, MarketDataBus};
+use kraken_ws_rust_bot::market_data::pipeline::MarketDataPipeline;
+use kraken_ws_rust_bot::market_data::recorder::{spawn_recorder, RecorderConfig};
+use kraken_ws_rust_bot::market_data::replay::{run_replay, ReplayConfig, ReplaySpeed};
+use kraken_ws_rust_bot::market_data::subscription::StreamKind;
+use kraken_ws_rust_bot::types::{StrategyState,};
+use kraken_ws_rust_bot::utils::decimal_util::Price;
+use kraken_ws_rust_bot::utils::key_util::SessionCredentials;
+use kraken_ws_rust_bot::{execution, market_data, strategy, utils};
 
 
 #[tokio::main]
@@ -15,37 +25,203 @@ async fn main() {
     dotenvy::dotenv().ok();
     init_logger();
 
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("keygen") {
+        let out_prefix = args.get(2).map(String::as_str).unwrap_or("binance_ed25519");
+        if let Err(e) = utils::key_util::keygen(out_prefix) {
+            log::error!("keygen failed: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if args.get(1).map(String::as_str) == Some("replay") {
+        if let Err(e) = replay(&args[2..]).await {
+            log::error!("replay failed: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     log::info!("Binance FIX Trading Bot Starting...");
 
+    // Load credentials up front so prompts don't interleave with session output. Prompts,
+    // secret commands and key decryption block, so they run off the runtime's worker threads.
+    let (md_credentials, oe_credentials) = match tokio::task::spawn_blocking(load_credentials).await {
+        Ok(Ok(credentials)) => credentials,
+        Ok(Err(e)) => {
+            log::error!("{}", e);
+            return;
+        }
+        Err(e) => {
+            log::error!("Credential loading stopped: {}", e);
+            return;
+        }
+    };
+
+    for (session, credentials) in [("MD", &md_credentials), ("OE", &oe_credentials)] {
+        if let Err(e) = credentials.self_check(session) {
+            log::error!("Key self-check failed: {}", e);
+            return;
+        }
+    }
+
+    let symbols = match market_data::symbol_registry::SymbolRegistry::from_env() {
+        Ok(symbols) => symbols,
+        Err(e) => {
+            log::error!("Invalid symbol configuration: {}", e);
+            return;
+        }
+    };
+    let freshness = match FreshnessConfig::from_env() {
+        Ok(config) => config,
+        Err(e) => {
+            log::error!("Invalid market data freshness configuration: {}", e);
+            return;
+        }
+    };
+    let recorder = match RecorderConfig::from_env() {
+        Ok(config) => config.map(spawn_recorder),
+        Err(e) => {
+            log::error!("Invalid market data recorder configuration: {}", e);
+            return;
+        }
+    };
+    let (md_subscriptions, md_commands) = market_data::subscription_manager::subscription_channel();
+    let md_bus = match MarketDataBus::from_env() {
+        Ok(bus) => bus,
+        Err(e) => {
+            log::error!("Invalid market data bus configuration: {}", e);
+            return;
+        }
+    };
+    let strategy_policy = match std::env::var("BINANCE_MD_BUS_POLICY") {
+        Ok(policy) => match BackpressurePolicy::parse(&policy) {
+            Ok(policy) => policy,
+            Err(e) => {
+                log::error!("Invalid BINANCE_MD_BUS_POLICY: {}", e);
+                return;
+            }
+        },
+        Err(_) => BackpressurePolicy::DropOldest,
+    };
+
     let strategy_state = Arc::new(Mutex::new(StrategyState {
-        reference_price: 100000.0,
+        reference_price: Price::new(100000, 0),
         active_order_id: None,
+        active_order_symbol: None,
         side: None,
         oe_logon_ready: false,
+        orders: OrderManager::default(),
+        positions: PositionTracker::default(),
+        top_of_book: HashMap::new(),
+        stale_symbols: HashSet::new(),
+        cancel_on_stale: freshness.cancel_resting,
+        symbols,
+        md_subscriptions,
     }));
 
     // Clone shared state for each task
-    let price_state = Arc::clone(&strategy_state);
     let order_state = Arc::clone(&strategy_state);
 
+    // The strategy consumes the market data bus on its own task and orders through the order entry session
+    let (order_gateway, order_requests) = execution::order_gateway::order_gateway_channel();
+    tokio::spawn(strategy::run_strategy(
+        md_bus.subscribe("strategy", strategy_policy),
+        Arc::clone(&strategy_state),
+        order_gateway,
+    ));
+
     // Spawn Market Data Session
+    let md_recorder = recorder.clone();
     tokio::spawn(async move {
-        if let Err(e) = market_data::market_data_client::start_market_data_client(price_state).await {
+        if let Err(e) = market_data::market_data_client::start_market_data_client(
+            md_credentials,
+            freshness,
+            md_commands,
+            md_bus,
+            md_recorder,
+        ).await {
             log::error!("FIX market data stream failed: {}", e);
         }
     });
 
     // Spawn Order Entry Session
     tokio::spawn(async move {
-        if let Err(e) = execution::order_execution_client::start_order_entry_session(order_state).await {
+        if let Err(e) = execution::order_execution_client::start_order_entry_session(order_state, oe_credentials, order_requests).await {
             log::error!("FIX order entry session failed: {}", e);
         }
     });
 
-    // Keep the app alive
-    loop {
-        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    // Keep the app alive until interrupted, then complete the captures so they stay readable
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        log::error!("Cannot listen for ctrl-c: {}", e);
+        loop {
+            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        }
+    }
+    log::info!("Shutting down");
+    if let Some(recorder) = &recorder {
+        recorder.finish().await;
+    }
+}
+
+fn load_credentials() -> anyhow::Result<(SessionCredentials, SessionCredentials)> {
+    let md = SessionCredentials::load("MD").map_err(|e| anyhow::anyhow!("Failed to load market data credentials: {}", e))?;
+    let oe = SessionCredentials::load("OE").map_err(|e| anyhow::anyhow!("Failed to load order entry credentials: {}", e))?;
+    Ok((md, oe))
+}
+
+// replay [--speed realtime|max|Nx] [--from RFC3339] [--to RFC3339] PATH...
+async fn replay(args: &[String]) -> anyhow::Result<()> {
+    let mut config = ReplayConfig {
+        paths: Vec::new(),
+        speed: ReplaySpeed::AsFastAsPossible,
+        from: None,
+        to: None,
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| anyhow::anyhow!("{} needs a value", arg));
+        match arg.as_str() {
+            "--speed" => config.speed = ReplaySpeed::parse(value()?)?,
+            "--from" => config.from = Some(DateTime::parse_from_rfc3339(value()?)?.with_timezone(&Utc)),
+            "--to" => config.to = Some(DateTime::parse_from_rfc3339(value()?)?.with_timezone(&Utc)),
+            path => config.paths.push(PathBuf::from(path)),
+        }
     }
+    if config.paths.is_empty() {
+        anyhow::bail!("usage: replay [--speed realtime|max|Nx] [--from RFC3339] [--to RFC3339] PATH...");
+    }
+
+    let trade_symbols: Vec<String> = market_data::subscription::subscriptions_from_env()?
+        .into_iter()
+        .filter(|subscription| subscription.kind == StreamKind::Trades)
+        .map(|subscription| subscription.symbol)
+        .collect();
+    let freshness = FreshnessConfig::from_env()?;
+    let cancel_on_stale = freshness.cancel_resting;
+    let mut pipeline = MarketDataPipeline::new(CandleAggregator::from_env(&trade_symbols)?, FreshnessMonitor::new(freshness));
+
+    // Nobody listens for subscription changes, orders are simulated against a ready session
+    let (md_subscriptions, _md_commands) = market_data::subscription_manager::subscription_channel();
+    let mut state = StrategyState {
+        reference_price: Price::new(100000, 0),
+        active_order_id: None,
+        active_order_symbol: None,
+        side: None,
+        oe_logon_ready: true,
+        orders: OrderManager::default(),
+        positions: PositionTracker::default(),
+        top_of_book: HashMap::new(),
+        stale_symbols: HashSet::new(),
+        cancel_on_stale,
+        symbols: market_data::symbol_registry::SymbolRegistry::from_env()?,
+        md_subscriptions,
+    };
+
+    run_replay(&config, &mut pipeline, &mut state).await?;
+    Ok(())
 }
 
 fn init_logger() {
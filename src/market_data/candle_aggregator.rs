@@ -0,0 +1,472 @@
+use std::env;
+use std::fmt;
+
+use chrono::{DateTime, Duration, Utc};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::market_data::market_data_event::Trade;
+use crate::utils::config_util::session_var_parse;
+use crate::utils::decimal_util::{notional, qty_for_notional, Price, Qty, Rounding};
+
+const DEFAULT_GRACE_MS: i64 = 2_000;
+const MAX_INTERVAL_DAYS: i64 = 366;
+
+
+// Serialized in its config form, e.g. "5m" or "vol=10"
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub enum BarSpec {
+    /// Clock aligned bars, e.g. every minute on the minute
+    Time(Duration),
+    /// Closes once the traded quantity reaches the threshold, a trade crossing it is split
+    Volume(Qty),
+    /// Closes once the traded quote amount reaches the threshold, a trade crossing it is split
+    Dollar(Price),
+}
+
+impl BarSpec {
+    /// Parses `1s`, `1m`, `5m`, `1h`, `1d`, `vol=QTY` or `dollar=AMOUNT`
+    pub fn parse(spec: &str) -> anyhow::Result<Self> {
+        let spec = spec.trim().to_ascii_lowercase();
+        if let Some(qty) = spec.strip_prefix("vol=") {
+            let qty: Qty = qty.parse()?;
+            if !qty.is_positive() {
+                anyhow::bail!("Volume bar size must be positive in '{}'", spec);
+            }
+            return Ok(Self::Volume(qty));
+        }
+        if let Some(amount) = spec.strip_prefix("dollar=") {
+            let amount: Price = amount.parse()?;
+            if !amount.is_positive() {
+                anyhow::bail!("Dollar bar size must be positive in '{}'", spec);
+            }
+            return Ok(Self::Dollar(amount));
+        }
+
+        let split = spec.find(|c: char| !c.is_ascii_digit()).unwrap_or(spec.len());
+        let (count, unit) = spec.split_at(split);
+        let count: i64 = count
+            .parse()
+            .map_err(|_| anyhow::anyhow!("Invalid bar spec '{}', expected e.g. 1m, vol=10 or dollar=100000", spec))?;
+        if count <= 0 {
+            anyhow::bail!("Bar interval must be positive in '{}'", spec);
+        }
+        let interval = match unit {
+            "s" => Duration::try_seconds(count),
+            "m" => Duration::try_minutes(count),
+            "h" => Duration::try_hours(count),
+            "d" => Duration::try_days(count),
+            _ => anyhow::bail!("Unknown bar interval unit in '{}', expected s, m, h or d", spec),
+        };
+        let interval = interval
+            .filter(|interval| *interval <= Duration::days(MAX_INTERVAL_DAYS))
+            .ok_or_else(|| anyhow::anyhow!("Bar interval too long in '{}', at most {}d", spec, MAX_INTERVAL_DAYS))?;
+        Ok(Self::Time(interval))
+    }
+}
+
+impl fmt::Display for BarSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Time(interval) => {
+                let seconds = interval.num_seconds();
+                match seconds {
+                    s if s % 86_400 == 0 => write!(f, "{}d", s / 86_400),
+                    s if s % 3_600 == 0 => write!(f, "{}h", s / 3_600),
+                    s if s % 60 == 0 => write!(f, "{}m", s / 60),
+                    s => write!(f, "{}s", s),
+                }
+            }
+            Self::Volume(qty) => write!(f, "vol={}", qty),
+            Self::Dollar(amount) => write!(f, "dollar={}", amount),
+        }
+    }
+}
+
+impl From<BarSpec> for String {
+    fn from(spec: BarSpec) -> Self {
+        spec.to_string()
+    }
+}
+
+impl TryFrom<String> for BarSpec {
+    type Error = anyhow::Error;
+
+    fn try_from(spec: String) -> anyhow::Result<Self> {
+        Self::parse(&spec)
+    }
+}
+
+/// A closed OHLCV bar
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Bar {
+    pub symbol: String,
+    pub spec: BarSpec,
+    /// Bar start for time bars, first trade for volume and dollar bars
+    pub open_time: DateTime<Utc>,
+    /// Bar end for time bars, last trade for volume and dollar bars
+    pub close_time: DateTime<Utc>,
+    pub open: Price,
+    pub high: Price,
+    pub low: Price,
+    pub close: Price,
+    pub volume: Qty,
+    pub quote_volume: Price,
+    /// 0 for an empty time bar, which carries the previous close as OHLC. A trade split across
+    /// volume or dollar bars counts in each of them.
+    pub trade_count: u64,
+}
+
+impl Bar {
+    fn open(symbol: &str, spec: BarSpec, open_time: DateTime<Utc>, close_time: DateTime<Utc>, price: Price) -> Self {
+        Self {
+            symbol: symbol.to_string(),
+            spec,
+            open_time,
+            close_time,
+            open: price,
+            high: price,
+            low: price,
+            close: price,
+            volume: Qty::ZERO,
+            quote_volume: Price::ZERO,
+            trade_count: 0,
+        }
+    }
+
+    fn add_trade(&mut self, price: Price, qty: Qty) {
+        if self.trade_count == 0 {
+            self.open = price;
+            self.high = price;
+            self.low = price;
+        }
+        self.high = self.high.max(price);
+        self.low = self.low.min(price);
+        self.close = price;
+        self.volume += qty;
+        self.quote_volume += notional(price, qty);
+        self.trade_count += 1;
+    }
+}
+
+// Start of the interval containing `time`, aligned to the Unix epoch
+fn align(time: DateTime<Utc>, interval: Duration) -> DateTime<Utc> {
+    let interval_ms = interval.num_milliseconds();
+    let time_ms = time.timestamp_millis();
+    DateTime::from_timestamp_millis(time_ms - time_ms.rem_euclid(interval_ms)).unwrap_or(time)
+}
+
+/// Builds the bars of one symbol and spec
+#[derive(Debug, Clone)]
+struct BarBuilder {
+    symbol: String,
+    spec: BarSpec,
+    /// Start of the current time bar interval, set by the first trade or clock tick
+    open_time: Option<DateTime<Utc>>,
+    /// Time bar of the current interval once it has a trade, or any unfinished volume or dollar bar
+    current: Option<Bar>,
+    last_close: Option<Price>,
+    /// Trades left out of time bars that were already closed
+    late_trades: u64,
+}
+
+impl BarBuilder {
+    fn on_trade(&mut self, price: Price, qty: Qty, time: DateTime<Utc>, closed: &mut Vec<Bar>) {
+        match self.spec {
+            BarSpec::Time(interval) => {
+                self.roll(time, closed);
+                let open_time = *self.open_time.get_or_insert_with(|| align(time, interval));
+                // Its bar was already closed by the clock
+                if time < open_time {
+                    self.late_trades += 1;
+                    if self.late_trades == 1 || self.late_trades.is_multiple_of(1000) {
+                        warn!(
+                            "Late {} trade at {} left out of {} bars, {} so far",
+                            self.symbol, time, self.spec, self.late_trades
+                        );
+                    }
+                    return;
+                }
+                let bar = self
+                    .current
+                    .get_or_insert_with(|| Bar::open(&self.symbol, self.spec, open_time, open_time + interval, price));
+                bar.add_trade(price, qty);
+                self.last_close = Some(price);
+            }
+            BarSpec::Volume(_) | BarSpec::Dollar(_) => {
+                let mut remaining = qty;
+                loop {
+                    let bar = self
+                        .current
+                        .get_or_insert_with(|| Bar::open(&self.symbol, self.spec, time, time, price));
+                    // Quantity that completes the bar, rounded up so the quote amount reaches it
+                    let room = match self.spec {
+                        BarSpec::Volume(threshold) => Some(threshold - bar.volume),
+                        BarSpec::Dollar(threshold) => qty_for_notional(threshold - bar.quote_volume, price, Rounding::Up),
+                        BarSpec::Time(_) => None,
+                    };
+                    let part = room.map_or(remaining, |room| room.min(remaining));
+                    bar.add_trade(price, part);
+                    bar.close_time = time;
+                    remaining -= part;
+
+                    let complete = match self.spec {
+                        BarSpec::Volume(threshold) => bar.volume >= threshold,
+                        BarSpec::Dollar(threshold) => bar.quote_volume >= threshold,
+                        BarSpec::Time(_) => false,
+                    };
+                    if complete {
+                        closed.extend(self.current.take());
+                    }
+                    if !remaining.is_positive() {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    // Closes every time bar that ended at or before `watermark`. Empty intervals become empty
+    // bars, except before the first trade, which leaves no close to carry.
+    fn roll(&mut self, watermark: DateTime<Utc>, closed: &mut Vec<Bar>) {
+        let BarSpec::Time(interval) = self.spec else { return };
+        let Some(mut open_time) = self.open_time else {
+            self.open_time = Some(align(watermark, interval));
+            return;
+        };
+
+        while open_time + interval <= watermark {
+            let bar = self.current.take().or_else(|| {
+                self.last_close
+                    .map(|close| Bar::open(&self.symbol, self.spec, open_time, open_time + interval, close))
+            });
+            closed.extend(bar);
+            open_time += interval;
+        }
+        self.open_time = Some(open_time);
+    }
+}
+
+/// Turns trades into OHLCV bars for every configured symbol and spec.
+///
+/// Time bars run on the exchange clock: a trade's TransactTime picks its bar and closes the
+/// bars before it. The local clock only closes bars of quiet symbols, `grace` after they
+/// ended, and a trade arriving after that is left out of its time bars and counted in
+/// `late_trades`. The grace has to cover the skew between the local and the exchange clock
+/// plus the feed latency, a local clock running ahead by more closes bars early.
+#[derive(Debug, Clone, Default)]
+pub struct CandleAggregator {
+    builders: Vec<BarBuilder>,
+    grace: Duration,
+}
+
+impl CandleAggregator {
+    pub fn new(specs: &[(String, BarSpec)], grace: Duration) -> Self {
+        Self {
+            builders: specs
+                .iter()
+                .map(|(symbol, spec)| BarBuilder {
+                    symbol: symbol.clone(),
+                    spec: *spec,
+                    open_time: None,
+                    current: None,
+                    last_close: None,
+                    late_trades: 0,
+                })
+                .collect(),
+            grace,
+        }
+    }
+
+    /// Reads the bar specs from BINANCE_MD_BARS and the grace from BINANCE_MD_BAR_GRACE_MS
+    pub fn from_env(trade_symbols: &[String]) -> anyhow::Result<Self> {
+        let grace_ms: i64 = session_var_parse("MD", "BAR_GRACE_MS")?.unwrap_or(DEFAULT_GRACE_MS);
+        if grace_ms < 0 {
+            anyhow::bail!("BINANCE_MD_BAR_GRACE_MS must not be negative");
+        }
+        Ok(Self::new(&bar_specs_from_env(trade_symbols)?, Duration::milliseconds(grace_ms)))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.builders.is_empty()
+    }
+
+    pub fn has_time_bars(&self) -> bool {
+        self.builders.iter().any(|builder| matches!(builder.spec, BarSpec::Time(_)))
+    }
+
+    /// Trades left out of time bars because the clock had already closed them
+    pub fn late_trades(&self) -> u64 {
+        self.builders.iter().map(|builder| builder.late_trades).sum()
+    }
+
+    /// Adds the trade to every bar of its symbol and returns the bars it closed.
+    /// Time bars use the trade's TransactTime, falling back to `now` without one.
+    pub fn on_trade(&mut self, trade: &Trade, now: DateTime<Utc>) -> Vec<Bar> {
+        let time = trade.time.unwrap_or(now);
+        let mut closed = Vec::new();
+        for builder in self.builders.iter_mut().filter(|builder| builder.symbol == trade.symbol) {
+            builder.on_trade(trade.price, trade.qty, time, &mut closed);
+        }
+        closed
+    }
+
+    /// Closes the time bars that ended by `now` minus the grace, call regularly so quiet markets
+    /// still produce bars
+    pub fn on_time(&mut self, now: DateTime<Utc>) -> Vec<Bar> {
+        let watermark = now - self.grace;
+        let mut closed = Vec::new();
+        for builder in &mut self.builders {
+            builder.roll(watermark, &mut closed);
+        }
+        closed
+    }
+}
+
+/// Reads BINANCE_MD_BARS, a comma separated list of `[SYMBOL:]SPEC`.
+/// Specs without a symbol apply to every symbol in `trade_symbols`.
+pub fn bar_specs_from_env(trade_symbols: &[String]) -> anyhow::Result<Vec<(String, BarSpec)>> {
+    let value = env::var("BINANCE_MD_BARS").unwrap_or_default();
+
+    let mut specs = Vec::new();
+    for entry in value.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+        match entry.split_once(':') {
+            Some((symbol, spec)) => {
+                let symbol = symbol.trim().to_ascii_uppercase();
+                if !trade_symbols.contains(&symbol) {
+                    anyhow::bail!("Bars for {} need a trades subscription for it", symbol);
+                }
+                specs.push((symbol, BarSpec::parse(spec)?));
+            }
+            None => {
+                let spec = BarSpec::parse(entry)?;
+                specs.extend(trade_symbols.iter().map(|symbol| (symbol.clone(), spec)));
+            }
+        }
+    }
+
+    Ok(specs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trade(price: &str, qty: &str, time: DateTime<Utc>) -> Trade {
+        Trade {
+            md_req_id: "BTCUSDT_TRADES_1".to_string(),
+            symbol: "BTCUSDT".to_string(),
+            price: price.parse().unwrap(),
+            qty: qty.parse().unwrap(),
+            time: Some(time),
+            trade_id: None,
+            aggressor_side: None,
+        }
+    }
+
+    fn at(millis: i64) -> DateTime<Utc> {
+        // 2023-11-14T22:13:00Z, on the minute
+        DateTime::from_timestamp_millis(1_699_999_980_000 + millis).unwrap()
+    }
+
+    fn aggregator(spec: &str) -> CandleAggregator {
+        CandleAggregator::new(&[("BTCUSDT".to_string(), BarSpec::parse(spec).unwrap())], Duration::seconds(2))
+    }
+
+    // Open, high, low, close, quote volume and volume
+    fn ohlcv(bar: &Bar) -> Vec<String> {
+        let prices = [bar.open, bar.high, bar.low, bar.close, bar.quote_volume];
+        let mut values: Vec<String> = prices.iter().map(Price::to_string).collect();
+        values.push(bar.volume.to_string());
+        values
+    }
+
+    #[test]
+    fn time_bars_are_utc_aligned() {
+        let mut candles = aggregator("1m");
+        assert!(candles.on_trade(&trade("100", "1", at(15_000)), at(15_000)).is_empty());
+        assert!(candles.on_trade(&trade("103", "2", at(30_000)), at(30_000)).is_empty());
+        assert!(candles.on_trade(&trade("99", "1", at(59_999)), at(59_999)).is_empty());
+
+        // The next minute's first trade closes the bar
+        let closed = candles.on_trade(&trade("101", "1", at(60_000)), at(60_000));
+        assert_eq!(closed.len(), 1);
+        assert_eq!(closed[0].open_time, at(0));
+        assert_eq!(closed[0].close_time, at(60_000));
+        assert_eq!(ohlcv(&closed[0]), ["100", "103", "99", "99", "405", "4"]);
+        assert_eq!(closed[0].trade_count, 3);
+    }
+
+    #[test]
+    fn empty_intervals_carry_the_close() {
+        let mut candles = aggregator("1m");
+        candles.on_trade(&trade("100", "1", at(10_000)), at(10_000));
+
+        // Not closed before the grace has passed
+        assert!(candles.on_time(at(61_000)).is_empty());
+        let closed = candles.on_time(at(182_000));
+        assert_eq!(closed.len(), 3);
+        assert_eq!(closed[0].trade_count, 1);
+        for (bar, minute) in closed[1..].iter().zip(1..) {
+            assert_eq!(bar.open_time, at(60_000 * minute));
+            assert_eq!(bar.trade_count, 0);
+            assert_eq!(ohlcv(bar), ["100", "100", "100", "100", "0", "0"]);
+        }
+    }
+
+    #[test]
+    fn no_empty_bars_before_the_first_trade() {
+        let mut candles = aggregator("1m");
+        assert!(candles.on_time(at(0)).is_empty());
+        assert!(candles.on_time(at(300_000)).is_empty());
+    }
+
+    #[test]
+    fn late_trades_are_counted() {
+        let mut candles = aggregator("1m");
+        candles.on_trade(&trade("100", "1", at(10_000)), at(10_000));
+        assert_eq!(candles.on_time(at(62_000)).len(), 1);
+
+        assert!(candles.on_trade(&trade("105", "1", at(59_000)), at(62_500)).is_empty());
+        assert_eq!(candles.late_trades(), 1);
+
+        let closed = candles.on_trade(&trade("101", "1", at(120_000)), at(120_000));
+        assert_eq!(ohlcv(&closed[0]), ["100", "100", "100", "100", "0", "0"]);
+    }
+
+    #[test]
+    fn volume_bars_split_a_trade() {
+        let mut candles = aggregator("vol=10");
+        assert!(candles.on_trade(&trade("100", "4", at(0)), at(0)).is_empty());
+
+        // 6 complete the first bar, 10 the second and 9 open the third
+        let closed = candles.on_trade(&trade("101", "25", at(1_000)), at(1_000));
+        assert_eq!(closed.len(), 2);
+        assert_eq!(ohlcv(&closed[0]), ["100", "101", "100", "101", "1006", "10"]);
+        assert_eq!(closed[0].trade_count, 2);
+        assert_eq!(closed[0].open_time, at(0));
+        assert_eq!(closed[0].close_time, at(1_000));
+        assert_eq!(ohlcv(&closed[1]), ["101", "101", "101", "101", "1010", "10"]);
+
+        let closed = candles.on_trade(&trade("102", "1", at(2_000)), at(2_000));
+        assert_eq!(ohlcv(&closed[0]), ["101", "102", "101", "102", "1011", "10"]);
+        assert_eq!(closed[0].open_time, at(1_000));
+    }
+
+    #[test]
+    fn dollar_bars_split_a_trade() {
+        let mut candles = aggregator("dollar=1000");
+        let closed = candles.on_trade(&trade("300", "5", at(0)), at(0));
+
+        // 1000 / 300 rounded up to the qty decimals reaches the threshold
+        assert_eq!(closed.len(), 1);
+        assert_eq!(closed[0].volume.to_string(), "3.33333334");
+        assert!(closed[0].quote_volume >= "1000".parse().unwrap());
+
+        let closed = candles.on_trade(&trade("300", "2", at(1_000)), at(1_000));
+        assert_eq!(closed.len(), 1);
+        assert_eq!(closed[0].volume.to_string(), "3.33333334");
+        assert_eq!(closed[0].open_time, at(0));
+    }
+}
@@ -0,0 +1,599 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use log::warn;
+
+use crate::market_data::market_data_event::MarketDataEvent;
+use crate::market_data::subscription::Subscription;
+
+// Record layout: receive time (i64 nanos), kind, payload length (u32), payload, all little endian
+const RECORD_HEADER_LEN: usize = 8 + 1 + 4;
+const KIND_RAW: u8 = 0;
+const KIND_EVENT: u8 = 1;
+const KIND_SUBSCRIPTION: u8 = 2;
+// Index entry: first receive time of a block (i64 nanos) and its file offset (u64)
+const INDEX_ENTRY_LEN: usize = 16;
+// Larger lengths come from a corrupt header, not from a market data message
+const MAX_RECORD_LEN: usize = 64 * 1024 * 1024;
+
+
+#[derive(Debug, Clone)]
+pub enum CapturePayload {
+    /// The FIX message as received
+    Raw(String),
+    /// A normalized book or trade event
+    Event(MarketDataEvent),
+    /// A stream the session opened, or closed when `subscription` is None. Replay maps the
+    /// MDReqIDs of raw messages with these.
+    Subscription {
+        md_req_id: String,
+        subscription: Option<Subscription>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct CaptureRecord {
+    pub recv_time: DateTime<Utc>,
+    pub payload: CapturePayload,
+}
+
+fn to_nanos(time: DateTime<Utc>) -> i64 {
+    time.timestamp_nanos_opt().unwrap_or(i64::MAX)
+}
+
+fn encode_record(record: &CaptureRecord) -> anyhow::Result<Vec<u8>> {
+    let (kind, payload) = match &record.payload {
+        CapturePayload::Raw(message) => (KIND_RAW, message.as_bytes().to_vec()),
+        CapturePayload::Event(event) => (KIND_EVENT, serde_json::to_vec(event)?),
+        CapturePayload::Subscription { md_req_id, subscription } => {
+            (KIND_SUBSCRIPTION, serde_json::to_vec(&(md_req_id, subscription))?)
+        }
+    };
+    if payload.len() > MAX_RECORD_LEN {
+        anyhow::bail!("Capture record of {} bytes exceeds the {} byte limit", payload.len(), MAX_RECORD_LEN);
+    }
+    let len = payload.len() as u32;
+
+    let mut bytes = Vec::with_capacity(RECORD_HEADER_LEN + payload.len());
+    bytes.extend_from_slice(&to_nanos(record.recv_time).to_le_bytes());
+    bytes.push(kind);
+    bytes.extend_from_slice(&len.to_le_bytes());
+    bytes.extend_from_slice(&payload);
+    Ok(bytes)
+}
+
+// None at a clean end of file, a record cut off by a crash also ends the capture
+fn decode_record(reader: &mut impl Read) -> anyhow::Result<Option<CaptureRecord>> {
+    let mut header = [0u8; RECORD_HEADER_LEN];
+    match reader.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+
+    let nanos = i64::from_le_bytes(header[..8].try_into()?);
+    let kind = header[8];
+    let len = u32::from_le_bytes(header[9..].try_into()?) as usize;
+    if len > MAX_RECORD_LEN {
+        anyhow::bail!("Capture record of {} bytes exceeds the {} byte limit", len, MAX_RECORD_LEN);
+    }
+
+    let mut payload = vec![0u8; len];
+    match reader.read_exact(&mut payload) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+
+    let payload = match kind {
+        KIND_RAW => CapturePayload::Raw(String::from_utf8(payload)?),
+        KIND_EVENT => CapturePayload::Event(serde_json::from_slice(&payload)?),
+        KIND_SUBSCRIPTION => {
+            let (md_req_id, subscription) = serde_json::from_slice(&payload)?;
+            CapturePayload::Subscription { md_req_id, subscription }
+        }
+        other => anyhow::bail!("Unknown capture record kind {}", other),
+    };
+    Ok(Some(CaptureRecord {
+        recv_time: DateTime::from_timestamp_nanos(nanos),
+        payload,
+    }))
+}
+
+/// `<dir>/<SYMBOL>/<YYYY-MM-DD>.mdc`, with `.zst` appended when compressed
+pub fn capture_path(dir: &Path, symbol: &str, date: NaiveDate, compressed: bool) -> PathBuf {
+    let extension = if compressed { "mdc.zst" } else { "mdc" };
+    dir.join(symbol).join(format!("{}.{}", date.format("%Y-%m-%d"), extension))
+}
+
+/// The index lives next to the capture as `<capture>.idx`
+pub fn index_path(capture: &Path) -> PathBuf {
+    let mut path = capture.as_os_str().to_owned();
+    path.push(".idx");
+    PathBuf::from(path)
+}
+
+fn is_compressed(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "zst")
+}
+
+enum BlockWriter {
+    Plain(BufWriter<File>),
+    /// Every block is its own zstd frame so a reader can start decoding at any indexed offset
+    Zstd {
+        file: Option<BufWriter<File>>,
+        encoder: Option<zstd::Encoder<'static, BufWriter<File>>>,
+        level: i32,
+    },
+}
+
+/// Appends records to one capture file, starting a new indexed block every `block_nanos`.
+/// Every block starts with the streams open at that time, so a reader can start at any block.
+pub struct CaptureWriter {
+    path: PathBuf,
+    out: BlockWriter,
+    index: BufWriter<File>,
+    block_nanos: i64,
+    block_start: Option<i64>,
+    streams: Vec<(String, Subscription)>,
+}
+
+impl CaptureWriter {
+    /// Opens or appends to the capture, `zstd_level` None writes it uncompressed
+    pub fn open(path: &Path, zstd_level: Option<i32>, block_nanos: i64) -> anyhow::Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        truncate_incomplete_tail(path, zstd_level.is_some())?;
+        let open = |path: &Path| -> anyhow::Result<BufWriter<File>> {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| anyhow::anyhow!("Cannot open capture file {}: {}", path.display(), e))?;
+            // Offsets of appended blocks count from the existing end of the file
+            file.seek(SeekFrom::End(0))?;
+            Ok(BufWriter::new(file))
+        };
+
+        let file = open(path)?;
+        let out = match zstd_level {
+            Some(level) => BlockWriter::Zstd {
+                file: Some(file),
+                encoder: None,
+                level,
+            },
+            None => BlockWriter::Plain(file),
+        };
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            out,
+            index: open(&index_path(path))?,
+            block_nanos,
+            block_start: None,
+            streams: Vec::new(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// (MDReqID, subscription) of the streams open at the end of the capture so far
+    pub fn streams(&self) -> &[(String, Subscription)] {
+        &self.streams
+    }
+
+    pub fn write(&mut self, record: &CaptureRecord) -> anyhow::Result<()> {
+        let nanos = to_nanos(record.recv_time);
+        let new_block = self
+            .block_start
+            .is_none_or(|start| nanos.saturating_sub(start) >= self.block_nanos);
+        if new_block {
+            self.start_block(nanos)?;
+        }
+
+        self.write_bytes(&encode_record(record)?)?;
+        if let CapturePayload::Subscription { md_req_id, subscription } = &record.payload {
+            self.streams.retain(|(id, _)| id != md_req_id);
+            if let Some(subscription) = subscription {
+                self.streams.push((md_req_id.clone(), subscription.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> anyhow::Result<()> {
+        match &mut self.out {
+            BlockWriter::Plain(file) => file.write_all(bytes)?,
+            BlockWriter::Zstd { encoder, .. } => match encoder {
+                Some(encoder) => encoder.write_all(bytes)?,
+                None => anyhow::bail!("No open zstd frame in {}", self.path.display()),
+            },
+        }
+        Ok(())
+    }
+
+    // Ends the current block and flushes it, then indexes the next one at the end of the file.
+    // An index entry therefore never points past data that is not on disk yet.
+    fn start_block(&mut self, nanos: i64) -> anyhow::Result<()> {
+        let offset = match &mut self.out {
+            BlockWriter::Plain(file) => {
+                file.flush()?;
+                file.stream_position()?
+            }
+            BlockWriter::Zstd { file, encoder, level } => {
+                if let Some(encoder) = encoder.take() {
+                    *file = Some(encoder.finish()?);
+                }
+                let mut inner = file
+                    .take()
+                    .ok_or_else(|| anyhow::anyhow!("Capture file {} is closed", self.path.display()))?;
+                inner.flush()?;
+                let offset = inner.stream_position()?;
+                *encoder = Some(zstd::Encoder::new(inner, *level)?);
+                offset
+            }
+        };
+
+        self.index.write_all(&nanos.to_le_bytes())?;
+        self.index.write_all(&offset.to_le_bytes())?;
+        self.index.flush()?;
+        self.block_start = Some(nanos);
+
+        for (md_req_id, subscription) in self.streams.clone() {
+            let record = CaptureRecord {
+                recv_time: DateTime::from_timestamp_nanos(nanos),
+                payload: CapturePayload::Subscription {
+                    md_req_id,
+                    subscription: Some(subscription),
+                },
+            };
+            self.write_bytes(&encode_record(&record)?)?;
+        }
+        Ok(())
+    }
+
+    /// Completes the last block, a zstd frame is only readable once finished
+    pub fn finish(mut self) -> anyhow::Result<()> {
+        match &mut self.out {
+            BlockWriter::Plain(file) => file.flush()?,
+            BlockWriter::Zstd { file, encoder, .. } => {
+                if let Some(encoder) = encoder.take() {
+                    encoder.finish()?.flush()?;
+                }
+                if let Some(file) = file {
+                    file.flush()?;
+                }
+            }
+        }
+        self.index.flush()?;
+        Ok(())
+    }
+}
+
+// A crash leaves the last record, or the last zstd frame, incomplete and anything appended after
+// it unreadable. Cuts the capture back to its last complete record or frame and drops the index
+// entries of blocks that are gone.
+fn truncate_incomplete_tail(path: &Path, compressed: bool) -> anyhow::Result<()> {
+    let len = match fs::metadata(path) {
+        Ok(metadata) => metadata.len(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    let index = read_index(path)?;
+    // Indexed blocks were flushed before their entry was written, only the last one can be cut off
+    let start = index
+        .iter()
+        .map(|(_, offset)| *offset)
+        .rfind(|offset| *offset <= len)
+        .unwrap_or(0);
+
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(start))?;
+    let end = if compressed {
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        let mut complete = 0;
+        while let Ok(frame_len) = zstd::zstd_safe::find_frame_compressed_size(&bytes[complete..]) {
+            if frame_len == 0 {
+                break;
+            }
+            complete += frame_len;
+        }
+        start + complete as u64
+    } else {
+        complete_records_end(BufReader::new(file), start, len)?
+    };
+
+    if end < len {
+        warn!("Capture {} ends with an incomplete record, truncating {} bytes", path.display(), len - end);
+        OpenOptions::new().write(true).open(path)?.set_len(end)?;
+    }
+
+    let index_file = index_path(path);
+    let index_len = fs::metadata(&index_file).map(|metadata| metadata.len()).unwrap_or(0);
+    let kept: Vec<u8> = index
+        .iter()
+        .filter(|(_, offset)| *offset < end)
+        .flat_map(|(nanos, offset)| nanos.to_le_bytes().into_iter().chain(offset.to_le_bytes()))
+        .collect();
+    if kept.len() as u64 != index_len {
+        fs::write(&index_file, kept)?;
+    }
+    Ok(())
+}
+
+// Offset after the last record that is completely on disk
+fn complete_records_end(mut reader: BufReader<File>, start: u64, len: u64) -> anyhow::Result<u64> {
+    let mut end = start;
+    let mut header = [0u8; RECORD_HEADER_LEN];
+    loop {
+        match reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(end),
+            Err(e) => return Err(e.into()),
+        }
+        let payload_len = u32::from_le_bytes(header[9..].try_into()?) as usize;
+        let record_end = end + (RECORD_HEADER_LEN + payload_len) as u64;
+        if payload_len > MAX_RECORD_LEN || record_end > len {
+            return Ok(end);
+        }
+        reader.seek_relative(payload_len as i64)?;
+        end = record_end;
+    }
+}
+
+/// (first receive time in nanos, file offset) of every block of a capture
+pub fn read_index(capture: &Path) -> anyhow::Result<Vec<(i64, u64)>> {
+    let bytes = match fs::read(index_path(capture)) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(bytes
+        .chunks_exact(INDEX_ENTRY_LEN)
+        .map(|entry| {
+            let nanos = i64::from_le_bytes(entry[..8].try_into().unwrap_or_default());
+            let offset = u64::from_le_bytes(entry[8..].try_into().unwrap_or_default());
+            (nanos, offset)
+        })
+        .collect())
+}
+
+/// Reads the records of one capture file in order
+pub struct CaptureReader {
+    reader: Box<dyn Read + Send>,
+    from: Option<i64>,
+}
+
+impl CaptureReader {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        Self::open_at(path, None)
+    }
+
+    /// Starts at the indexed block containing `from` and skips the records before it
+    pub fn open_at(path: &Path, from: Option<DateTime<Utc>>) -> anyhow::Result<Self> {
+        let mut file = File::open(path)
+            .map_err(|e| anyhow::anyhow!("Cannot open capture file {}: {}", path.display(), e))?;
+
+        let from = from.map(to_nanos);
+        if let Some(from) = from {
+            let index = read_index(path)?;
+            let block = index.partition_point(|(start, _)| *start <= from);
+            if let Some((_, offset)) = block.checked_sub(1).and_then(|block| index.get(block)) {
+                file.seek(SeekFrom::Start(*offset))?;
+            }
+        }
+
+        let reader = BufReader::new(file);
+        let reader: Box<dyn Read + Send> = if is_compressed(path) {
+            Box::new(zstd::Decoder::new(CompleteFrames::new(reader))?)
+        } else {
+            Box::new(reader)
+        };
+        Ok(Self { reader, from })
+    }
+}
+
+// Passes on only whole zstd frames. A capture being written, or cut off by a crash, can end in
+// a partial frame and the decoder would return some of its records as if the block were done.
+struct CompleteFrames<R> {
+    inner: R,
+    pending: Vec<u8>,
+    /// Bytes at the start of `pending` that belong to complete frames
+    ready: usize,
+    eof: bool,
+}
+
+impl<R: Read> CompleteFrames<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            pending: Vec::new(),
+            ready: 0,
+            eof: false,
+        }
+    }
+}
+
+impl<R: Read> Read for CompleteFrames<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.ready > 0 {
+                let n = buf.len().min(self.ready);
+                buf[..n].copy_from_slice(&self.pending[..n]);
+                self.pending.drain(..n);
+                self.ready -= n;
+                return Ok(n);
+            }
+            if let Ok(frame_len) = zstd::zstd_safe::find_frame_compressed_size(&self.pending) {
+                if frame_len > 0 && frame_len <= self.pending.len() {
+                    self.ready = frame_len;
+                    continue;
+                }
+            }
+            if self.eof {
+                // Whatever is left is an incomplete frame
+                return Ok(0);
+            }
+
+            let mut chunk = [0u8; 64 * 1024];
+            let n = self.inner.read(&mut chunk)?;
+            self.eof = n == 0;
+            self.pending.extend_from_slice(&chunk[..n]);
+        }
+    }
+}
+
+impl Iterator for CaptureReader {
+    type Item = anyhow::Result<CaptureRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let record = match decode_record(&mut self.reader) {
+                Ok(record) => record?,
+                Err(e) => return Some(Err(e)),
+            };
+            // Streams opened before `from` still map the records after it
+            let is_stream = matches!(record.payload, CapturePayload::Subscription { .. });
+            if !is_stream && self.from.is_some_and(|from| to_nanos(record.recv_time) < from) {
+                continue;
+            }
+            return Some(Ok(record));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+
+    fn temp_capture(name: &str, compressed: bool) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("capture-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        capture_path(&dir, "BTCUSDT", NaiveDate::from_ymd_opt(2026, 1, 2).unwrap(), compressed)
+    }
+
+    fn raw(time: DateTime<Utc>, message: &str) -> CaptureRecord {
+        CaptureRecord {
+            recv_time: time,
+            payload: CapturePayload::Raw(message.to_string()),
+        }
+    }
+
+    fn messages(path: &Path, from: Option<DateTime<Utc>>) -> Vec<String> {
+        CaptureReader::open_at(path, from)
+            .unwrap()
+            .map(|record| match record.unwrap().payload {
+                CapturePayload::Raw(message) => message,
+                CapturePayload::Event(event) => format!("{:?}", event),
+                CapturePayload::Subscription { md_req_id, .. } => md_req_id,
+            })
+            .collect()
+    }
+
+    fn write_all(path: &Path, level: Option<i32>, start: DateTime<Utc>, messages: &[&str]) -> CaptureWriter {
+        let mut writer = CaptureWriter::open(path, level, 1_000_000_000).unwrap();
+        for (i, message) in messages.iter().enumerate() {
+            writer.write(&raw(start + Duration::milliseconds(600 * i as i64), message)).unwrap();
+        }
+        writer
+    }
+
+    #[test]
+    fn round_trip_and_seek() {
+        let start = Utc::now();
+        for level in [None, Some(3)] {
+            let path = temp_capture("round-trip", level.is_some());
+            write_all(&path, level, start, &["a", "b", "c", "d"]).finish().unwrap();
+
+            assert_eq!(messages(&path, None), ["a", "b", "c", "d"]);
+            assert_eq!(read_index(&path).unwrap().len(), 2);
+            assert_eq!(messages(&path, Some(start + Duration::milliseconds(1200))), ["c", "d"]);
+        }
+    }
+
+    #[test]
+    fn index_points_at_flushed_data() {
+        let path = temp_capture("flushed", false);
+        let writer = write_all(&path, None, Utc::now(), &["a", "b", "c"]);
+
+        let (_, offset) = *read_index(&path).unwrap().last().unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), offset);
+        drop(writer);
+    }
+
+    #[test]
+    fn incomplete_tail_is_a_clean_end_and_cut_on_open() {
+        let start = Utc::now();
+        for level in [None, Some(3)] {
+            let path = temp_capture("truncated", level.is_some());
+            write_all(&path, level, start, &["a", "b", "c", "d"]).finish().unwrap();
+            let len = fs::metadata(&path).unwrap().len();
+            OpenOptions::new().write(true).open(&path).unwrap().set_len(len - 3).unwrap();
+
+            // A cut zstd frame is skipped as a whole, the plain file keeps every complete record
+            let kept: &[&str] = if level.is_some() { &["a", "b"] } else { &["a", "b", "c"] };
+            assert_eq!(messages(&path, None), kept);
+
+            let later = start + Duration::seconds(10);
+            write_all(&path, level, later, &["e"]).finish().unwrap();
+            assert_eq!(messages(&path, None), [kept, &["e"]].concat());
+            assert_eq!(messages(&path, Some(later)), ["e"]);
+            assert_eq!(read_index(&path).unwrap().len(), if level.is_some() { 2 } else { 3 });
+        }
+    }
+
+    #[test]
+    fn oversized_length_is_rejected() {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&0i64.to_le_bytes());
+        bytes.push(KIND_RAW);
+        bytes.extend_from_slice(&u32::MAX.to_le_bytes());
+
+        assert!(decode_record(&mut bytes.as_slice()).is_err());
+    }
+
+    #[test]
+    fn streams_start_every_block() {
+        let path = temp_capture("streams", false);
+        let start = Utc::now();
+        let subscription = Subscription::parse("BTCUSDT:depth=5:agg=N").unwrap();
+        let stream = |time: DateTime<Utc>, subscription: Option<Subscription>| CaptureRecord {
+            recv_time: time,
+            payload: CapturePayload::Subscription {
+                md_req_id: "BTCUSDT_DEPTH5_1".to_string(),
+                subscription,
+            },
+        };
+
+        let mut writer = CaptureWriter::open(&path, None, 1_000_000_000).unwrap();
+        writer.write(&stream(start, Some(subscription.clone()))).unwrap();
+        writer.write(&raw(start + Duration::milliseconds(1500), "a")).unwrap();
+        writer.write(&stream(start + Duration::milliseconds(1600), None)).unwrap();
+        assert!(writer.streams().is_empty());
+        writer.write(&raw(start + Duration::milliseconds(3000), "b")).unwrap();
+        writer.finish().unwrap();
+
+        let records: Vec<CaptureRecord> = CaptureReader::open_at(&path, Some(start + Duration::milliseconds(1500)))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        let streams: Vec<Option<Subscription>> = records
+            .iter()
+            .filter_map(|record| match &record.payload {
+                CapturePayload::Subscription { subscription, .. } => Some(subscription.clone()),
+                _ => None,
+            })
+            .collect();
+        // Repeated at the start of the second block, closed before the third
+        assert_eq!(streams, [Some(subscription), None]);
+        assert_eq!(messages(&path, None), ["BTCUSDT_DEPTH5_1", "BTCUSDT_DEPTH5_1", "a", "BTCUSDT_DEPTH5_1", "b"]);
+    }
+}
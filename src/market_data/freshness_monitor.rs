@@ -0,0 +1,266 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::utils::config_util::{session_var, session_var_bool, session_var_parse};
+
+const DEFAULT_MAX_AGE_MS: i64 = 10_000;
+const DEFAULT_SETTLE_MS: i64 = 2_000;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Freshness {
+    Fresh,
+    /// No data for longer than the symbol's max age
+    Stale { since: DateTime<Utc> },
+    /// Data is back, trading resumes once it kept arriving for the settle time
+    Settling { since: DateTime<Utc> },
+}
+
+/// Emitted when a symbol goes stale or trading on it resumes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FreshnessChange {
+    pub symbol: String,
+    pub stale: bool,
+    /// Receive time of the last book or trade update
+    pub last_data: DateTime<Utc>,
+    pub time: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FreshnessConfig {
+    pub default_max_age: Duration,
+    pub max_age: HashMap<String, Duration>,
+    pub settle: Duration,
+    /// Cancel the resting orders of a symbol when it goes stale
+    pub cancel_resting: bool,
+}
+
+impl Default for FreshnessConfig {
+    fn default() -> Self {
+        Self {
+            default_max_age: Duration::milliseconds(DEFAULT_MAX_AGE_MS),
+            max_age: HashMap::new(),
+            settle: Duration::milliseconds(DEFAULT_SETTLE_MS),
+            cancel_resting: false,
+        }
+    }
+}
+
+impl FreshnessConfig {
+    /// Reads BINANCE_MD_MAX_AGE_MS as `MS` and/or `SYMBOL=MS` entries (e.g. `5000,ETHUSDT=20000`),
+    /// BINANCE_MD_STALE_SETTLE_MS and BINANCE_MD_STALE_CANCEL_ORDERS
+    pub fn from_env() -> anyhow::Result<Self> {
+        let mut config = Self::default();
+
+        let value = session_var("MD", "MAX_AGE_MS").unwrap_or_default();
+        for spec in value.split(',').map(str::trim).filter(|spec| !spec.is_empty()) {
+            let (symbol, millis) = match spec.split_once('=') {
+                Some((symbol, millis)) => (Some(symbol.trim().to_ascii_uppercase()), millis),
+                None => (None, spec),
+            };
+            let millis: i64 = millis
+                .trim()
+                .parse()
+                .ok()
+                .filter(|millis| *millis > 0)
+                .ok_or_else(|| anyhow::anyhow!("Invalid BINANCE_MD_MAX_AGE_MS entry '{}', expected MS or SYMBOL=MS", spec))?;
+            match symbol {
+                Some(symbol) => {
+                    config.max_age.insert(symbol, Duration::milliseconds(millis));
+                }
+                None => config.default_max_age = Duration::milliseconds(millis),
+            }
+        }
+
+        let settle_ms: i64 = session_var_parse("MD", "STALE_SETTLE_MS")?.unwrap_or(DEFAULT_SETTLE_MS);
+        if settle_ms < 0 {
+            anyhow::bail!("BINANCE_MD_STALE_SETTLE_MS must not be negative");
+        }
+        config.settle = Duration::milliseconds(settle_ms);
+        config.cancel_resting = session_var_bool("MD", "STALE_CANCEL_ORDERS")?.unwrap_or(false);
+
+        Ok(config)
+    }
+
+    pub fn max_age(&self, symbol: &str) -> Duration {
+        self.max_age.get(symbol).copied().unwrap_or(self.default_max_age)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct SymbolFreshness {
+    last_data: DateTime<Utc>,
+    state: Freshness,
+}
+
+/// Tracks how old the market data of every symbol is. A symbol is tracked from its first
+/// update, untracked symbols are not blocked.
+#[derive(Debug, Clone, Default)]
+pub struct FreshnessMonitor {
+    config: FreshnessConfig,
+    symbols: HashMap<String, SymbolFreshness>,
+}
+
+impl FreshnessMonitor {
+    pub fn new(config: FreshnessConfig) -> Self {
+        Self {
+            config,
+            symbols: HashMap::new(),
+        }
+    }
+
+    pub fn config(&self) -> &FreshnessConfig {
+        &self.config
+    }
+
+    /// Called for every book or trade update, returns the change when trading resumes
+    pub fn on_data(&mut self, symbol: &str, now: DateTime<Utc>) -> Option<FreshnessChange> {
+        let Some(tracked) = self.symbols.get_mut(symbol) else {
+            self.symbols.insert(
+                symbol.to_string(),
+                SymbolFreshness {
+                    last_data: now,
+                    state: Freshness::Fresh,
+                },
+            );
+            return None;
+        };
+
+        tracked.last_data = tracked.last_data.max(now);
+        if let Freshness::Stale { .. } = tracked.state {
+            tracked.state = Freshness::Settling { since: now };
+        }
+        self.settle(symbol, now)
+    }
+
+    /// Marks the symbols without data for longer than their max age stale and resumes
+    /// settled ones. Call regularly so a silent feed is noticed.
+    pub fn check(&mut self, now: DateTime<Utc>) -> Vec<FreshnessChange> {
+        let mut changes = Vec::new();
+        let symbols: Vec<String> = self.symbols.keys().cloned().collect();
+        for symbol in symbols {
+            let max_age = self.config.max_age(&symbol);
+            let Some(tracked) = self.symbols.get_mut(&symbol) else { continue };
+
+            let is_stale = matches!(tracked.state, Freshness::Stale { .. });
+            if !is_stale && now - tracked.last_data > max_age {
+                // A symbol that never resumed already reported going stale
+                if tracked.state == Freshness::Fresh {
+                    changes.push(FreshnessChange {
+                        symbol: symbol.clone(),
+                        stale: true,
+                        last_data: tracked.last_data,
+                        time: now,
+                    });
+                }
+                tracked.state = Freshness::Stale { since: now };
+                continue;
+            }
+            changes.extend(self.settle(&symbol, now));
+        }
+        changes
+    }
+
+    // Settling becomes fresh after the settle time
+    fn settle(&mut self, symbol: &str, now: DateTime<Utc>) -> Option<FreshnessChange> {
+        let tracked = self.symbols.get_mut(symbol)?;
+        let Freshness::Settling { since } = tracked.state else {
+            return None;
+        };
+        if now - since < self.config.settle {
+            return None;
+        }
+        tracked.state = Freshness::Fresh;
+        Some(FreshnessChange {
+            symbol: symbol.to_string(),
+            stale: false,
+            last_data: tracked.last_data,
+            time: now,
+        })
+    }
+
+    /// Stops tracking a symbol, e.g. once all its streams are unsubscribed
+    pub fn untrack(&mut self, symbol: &str) {
+        self.symbols.remove(symbol);
+    }
+
+    pub fn state(&self, symbol: &str) -> Option<Freshness> {
+        self.symbols.get(symbol).map(|tracked| tracked.state)
+    }
+
+    /// False while the symbol is stale or settling
+    pub fn is_tradable(&self, symbol: &str) -> bool {
+        self.state(symbol).is_none_or(|state| state == Freshness::Fresh)
+    }
+
+    pub fn last_data(&self, symbol: &str) -> Option<DateTime<Utc>> {
+        self.symbols.get(symbol).map(|tracked| tracked.last_data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn monitor() -> FreshnessMonitor {
+        FreshnessMonitor::new(FreshnessConfig {
+            default_max_age: Duration::seconds(10),
+            settle: Duration::seconds(2),
+            ..FreshnessConfig::default()
+        })
+    }
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000 + secs, 0).unwrap()
+    }
+
+    #[test]
+    fn stale_settles_back_to_fresh() {
+        let mut monitor = monitor();
+        assert!(monitor.on_data("BTCUSDT", at(0)).is_none());
+        assert!(monitor.check(at(10)).is_empty());
+
+        let changes = monitor.check(at(11));
+        assert_eq!(changes.len(), 1);
+        assert!(changes[0].stale);
+        assert_eq!(changes[0].last_data, at(0));
+        assert_eq!(monitor.state("BTCUSDT"), Some(Freshness::Stale { since: at(11) }));
+
+        assert!(monitor.on_data("BTCUSDT", at(12)).is_none());
+        assert_eq!(monitor.state("BTCUSDT"), Some(Freshness::Settling { since: at(12) }));
+        assert!(!monitor.is_tradable("BTCUSDT"));
+        assert!(monitor.on_data("BTCUSDT", at(13)).is_none());
+
+        let resumed = monitor.on_data("BTCUSDT", at(14)).unwrap();
+        assert!(!resumed.stale);
+        assert_eq!(resumed.time, at(14));
+        assert!(monitor.is_tradable("BTCUSDT"));
+    }
+
+    #[test]
+    fn gap_while_settling_is_stale_again() {
+        let mut monitor = monitor();
+        monitor.on_data("BTCUSDT", at(0));
+        assert_eq!(monitor.check(at(11)).len(), 1);
+        monitor.on_data("BTCUSDT", at(12));
+
+        // Already reported stale, so no second change
+        assert!(monitor.check(at(23)).is_empty());
+        assert_eq!(monitor.state("BTCUSDT"), Some(Freshness::Stale { since: at(23) }));
+
+        // The settle time starts over with the next update
+        monitor.on_data("BTCUSDT", at(24));
+        assert!(monitor.check(at(25)).is_empty());
+        assert!(!monitor.check(at(26)).is_empty());
+        assert!(monitor.is_tradable("BTCUSDT"));
+    }
+
+    #[test]
+    fn untracked_symbols_are_tradable() {
+        let monitor = monitor();
+        assert!(monitor.is_tradable("BTCUSDT"));
+        assert_eq!(monitor.state("BTCUSDT"), None);
+    }
+}
@@ -0,0 +1,445 @@
+use std::collections::VecDeque;
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use log::{debug, warn};
+use tokio::sync::Notify;
+
+use crate::market_data::market_data_event::MarketDataEvent;
+use crate::utils::config_util::session_var;
+
+const DEFAULT_CAPACITY: usize = 1024;
+
+
+/// What a subscriber's queue does when it is full. Control events (freshness, book sync and
+/// InstrumentList) are never dropped or conflated, whatever the policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackpressurePolicy {
+    /// Lossless as long as the publisher task keeps up. The publisher waits until this subscriber
+    /// has room, which holds back every other `Block` subscriber but never the market data
+    /// session or the other policies. Once the publisher is `capacity` events behind, its oldest
+    /// book, trade and bar events are dropped.
+    Block,
+    /// Drops the oldest queued book, trade or bar event
+    DropOldest,
+    /// Keeps only the latest queued event of each kind per symbol, e.g. the newest book update
+    ConflateLatest,
+}
+
+impl BackpressurePolicy {
+    /// Parses `block`, `drop_oldest` or `conflate`
+    pub fn parse(policy: &str) -> anyhow::Result<Self> {
+        match policy.trim().to_ascii_lowercase().as_str() {
+            "block" => Ok(Self::Block),
+            "drop_oldest" => Ok(Self::DropOldest),
+            "conflate" => Ok(Self::ConflateLatest),
+            other => anyhow::bail!("Unknown backpressure policy '{}', expected block, drop_oldest or conflate", other),
+        }
+    }
+}
+
+/// A normalized event with the time the session received it
+#[derive(Debug, Clone)]
+pub struct BusEvent {
+    pub recv_time: DateTime<Utc>,
+    pub event: MarketDataEvent,
+}
+
+impl BusEvent {
+    // Data events conflate when they are of the same kind for the same symbol
+    fn conflates_with(&self, other: &BusEvent) -> bool {
+        !self.event.is_control()
+            && mem::discriminant(&self.event) == mem::discriminant(&other.event)
+            && self.event.symbol() == other.event.symbol()
+    }
+}
+
+#[derive(Debug, Default)]
+struct QueueState {
+    events: VecDeque<BusEvent>,
+    dropped: u64,
+    /// The bus is gone, the subscriber drains what is left
+    closed: bool,
+    /// The subscriber is gone, nothing more is queued
+    detached: bool,
+}
+
+#[derive(Debug)]
+struct SubscriberQueue {
+    name: String,
+    policy: BackpressurePolicy,
+    capacity: usize,
+    state: Mutex<QueueState>,
+    readable: Notify,
+    writable: Notify,
+}
+
+impl SubscriberQueue {
+    fn new(name: &str, policy: BackpressurePolicy, capacity: usize) -> Self {
+        Self {
+            name: name.to_string(),
+            policy,
+            capacity,
+            state: Mutex::new(QueueState::default()),
+            readable: Notify::new(),
+            writable: Notify::new(),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, QueueState> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // Waits while a blocking queue is full
+    async fn push(&self, event: BusEvent) {
+        loop {
+            let writable = self.writable.notified();
+            {
+                let mut state = self.lock();
+                if state.detached {
+                    return;
+                }
+                if self.try_push(&mut state, &event) {
+                    self.readable.notify_one();
+                    return;
+                }
+            }
+            writable.await;
+        }
+    }
+
+    // Queues without waiting, only for the policies that never block
+    fn offer(&self, event: &BusEvent) {
+        let mut state = self.lock();
+        if !state.detached && self.try_push(&mut state, event) {
+            self.readable.notify_one();
+        }
+    }
+
+    // False when a blocking queue is full. A full queue of the other policies drops its oldest
+    // data event, or the new one when only control events are queued, and a control event is
+    // queued past the capacity rather than dropped.
+    fn try_push(&self, state: &mut QueueState, event: &BusEvent) -> bool {
+        if self.policy == BackpressurePolicy::ConflateLatest {
+            if let Some(queued) = state.events.iter_mut().find(|queued| queued.conflates_with(event)) {
+                *queued = event.clone();
+                return true;
+            }
+        }
+
+        if state.events.len() >= self.capacity {
+            if self.policy == BackpressurePolicy::Block {
+                return false;
+            }
+            match state.events.iter().position(|queued| !queued.event.is_control()) {
+                Some(oldest) => {
+                    state.events.remove(oldest);
+                    self.on_dropped(state);
+                }
+                None if !event.event.is_control() => {
+                    self.on_dropped(state);
+                    return true;
+                }
+                None => {}
+            }
+        }
+        state.events.push_back(event.clone());
+        true
+    }
+
+    fn on_dropped(&self, state: &mut QueueState) {
+        state.dropped += 1;
+        if state.dropped == 1 || state.dropped.is_multiple_of(1000) {
+            warn!("Market data subscriber {} is lagging, {} events dropped", self.name, state.dropped);
+        }
+    }
+
+    // The next event, None once the queue is closed and drained
+    async fn pop(&self) -> Option<BusEvent> {
+        loop {
+            let readable = self.readable.notified();
+            {
+                let mut state = self.lock();
+                if let Some(event) = state.events.pop_front() {
+                    self.writable.notify_one();
+                    return Some(event);
+                }
+                if state.closed {
+                    return None;
+                }
+            }
+            readable.await;
+        }
+    }
+
+    fn close(&self) {
+        self.lock().closed = true;
+        self.readable.notify_one();
+    }
+}
+
+/// Receives market data events from the bus
+#[derive(Debug)]
+pub struct BusSubscriber {
+    queue: Arc<SubscriberQueue>,
+}
+
+impl BusSubscriber {
+    /// The next event, None once the bus is gone and the queue is drained
+    pub async fn recv(&mut self) -> Option<BusEvent> {
+        self.queue.pop().await
+    }
+
+    /// Events dropped because this subscriber fell behind
+    pub fn dropped(&self) -> u64 {
+        self.queue.lock().dropped
+    }
+
+    pub fn policy(&self) -> BackpressurePolicy {
+        self.queue.policy
+    }
+}
+
+impl Drop for BusSubscriber {
+    fn drop(&mut self) {
+        self.queue.lock().detached = true;
+        self.queue.writable.notify_one();
+    }
+}
+
+type Queues = Arc<Mutex<Vec<Arc<SubscriberQueue>>>>;
+
+fn lock_queues(queues: &Queues) -> std::sync::MutexGuard<'_, Vec<Arc<SubscriberQueue>>> {
+    queues.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Fans normalized market data out to any number of subscribers, each with its own bounded
+/// queue and backpressure policy. `publish` never waits: it queues for the non-blocking
+/// subscribers directly and hands the event to a publisher task for the `Block` ones.
+/// Subscribers see the end of the stream once every clone of the bus is gone.
+#[derive(Debug, Clone)]
+pub struct MarketDataBus {
+    capacity: usize,
+    shared: Arc<BusShared>,
+}
+
+#[derive(Debug)]
+struct BusShared {
+    queues: Queues,
+    /// Events on their way to the `Block` subscribers
+    ingest: Arc<SubscriberQueue>,
+}
+
+impl Drop for BusShared {
+    fn drop(&mut self) {
+        // The publisher closes the blocking queues once it delivered what is left
+        self.ingest.close();
+        for queue in lock_queues(&self.queues).iter() {
+            if queue.policy != BackpressurePolicy::Block {
+                queue.close();
+            }
+        }
+    }
+}
+
+impl MarketDataBus {
+    /// Starts the publisher task, `capacity` is the queue size of every subscriber and of the
+    /// publisher
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        let queues: Queues = Arc::new(Mutex::new(Vec::new()));
+        let ingest = Arc::new(SubscriberQueue::new("publisher", BackpressurePolicy::DropOldest, capacity));
+        tokio::spawn(run_publisher(Arc::clone(&ingest), Arc::clone(&queues)));
+        Self {
+            capacity,
+            shared: Arc::new(BusShared { queues, ingest }),
+        }
+    }
+
+    /// Reads BINANCE_MD_BUS_CAPACITY (default 1024)
+    pub fn from_env() -> anyhow::Result<Self> {
+        let capacity = match session_var("MD", "BUS_CAPACITY") {
+            Some(value) => value
+                .trim()
+                .parse::<usize>()
+                .ok()
+                .filter(|capacity| *capacity > 0)
+                .ok_or_else(|| anyhow::anyhow!("Invalid BINANCE_MD_BUS_CAPACITY '{}'", value))?,
+            None => DEFAULT_CAPACITY,
+        };
+        Ok(Self::new(capacity))
+    }
+
+    /// Subscribes to every event published from now on
+    pub fn subscribe(&self, name: &str, policy: BackpressurePolicy) -> BusSubscriber {
+        let queue = Arc::new(SubscriberQueue::new(name, policy, self.capacity));
+        lock_queues(&self.shared.queues).push(Arc::clone(&queue));
+        debug!("Market data subscriber {} added ({:?})", name, policy);
+        BusSubscriber { queue }
+    }
+
+    /// Queues the event for every subscriber in publish order without waiting
+    pub fn publish(&self, recv_time: DateTime<Utc>, event: MarketDataEvent) {
+        let event = BusEvent { recv_time, event };
+        let mut queues = lock_queues(&self.shared.queues);
+        queues.retain(|queue| !queue.lock().detached);
+
+        let mut blocking = false;
+        for queue in queues.iter() {
+            match queue.policy {
+                BackpressurePolicy::Block => blocking = true,
+                _ => queue.offer(&event),
+            }
+        }
+        if blocking {
+            self.shared.ingest.offer(&event);
+        }
+    }
+}
+
+// Delivers to the `Block` subscribers in publish order, waiting for each one to have room
+async fn run_publisher(ingest: Arc<SubscriberQueue>, queues: Queues) {
+    while let Some(event) = ingest.pop().await {
+        let blocking: Vec<Arc<SubscriberQueue>> = lock_queues(&queues)
+            .iter()
+            .filter(|queue| queue.policy == BackpressurePolicy::Block)
+            .cloned()
+            .collect();
+        for queue in blocking {
+            queue.push(event.clone()).await;
+        }
+    }
+
+    for queue in lock_queues(&queues).iter() {
+        queue.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::market_data::market_data_event::BookUpdate;
+
+    fn book(symbol: &str, req: &str) -> MarketDataEvent {
+        MarketDataEvent::Book(BookUpdate {
+            md_req_id: req.to_string(),
+            symbol: symbol.to_string(),
+            best_bid: None,
+            best_ask: None,
+        })
+    }
+
+    fn synced(symbol: &str) -> MarketDataEvent {
+        MarketDataEvent::BookSynced { symbol: symbol.to_string() }
+    }
+
+    async fn drain(subscriber: &mut BusSubscriber) -> Vec<MarketDataEvent> {
+        let mut events = Vec::new();
+        while let Ok(Some(event)) = tokio::time::timeout(Duration::from_millis(10), subscriber.recv()).await {
+            events.push(event.event);
+        }
+        events
+    }
+
+    fn req_ids(events: &[MarketDataEvent]) -> Vec<String> {
+        events
+            .iter()
+            .map(|event| match event {
+                MarketDataEvent::Book(update) => update.md_req_id.clone(),
+                other => format!("{:?}", mem::discriminant(other)),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_keeps_control_events() {
+        let bus = MarketDataBus::new(2);
+        let mut subscriber = bus.subscribe("test", BackpressurePolicy::DropOldest);
+        let now = Utc::now();
+        bus.publish(now, synced("BTCUSDT"));
+        bus.publish(now, book("BTCUSDT", "1"));
+        bus.publish(now, book("BTCUSDT", "2"));
+        bus.publish(now, book("BTCUSDT", "3"));
+
+        let events = drain(&mut subscriber).await;
+        assert_eq!(events.len(), 2);
+        assert!(matches!(events[0], MarketDataEvent::BookSynced { .. }));
+        assert_eq!(req_ids(&events[1..]), ["3"]);
+        assert_eq!(subscriber.dropped(), 2);
+    }
+
+    #[tokio::test]
+    async fn control_events_exceed_the_capacity() {
+        let bus = MarketDataBus::new(1);
+        let mut subscriber = bus.subscribe("test", BackpressurePolicy::ConflateLatest);
+        let now = Utc::now();
+        bus.publish(now, synced("BTCUSDT"));
+        bus.publish(now, synced("BTCUSDT"));
+        bus.publish(now, book("BTCUSDT", "1"));
+
+        let events = drain(&mut subscriber).await;
+        assert_eq!(events.len(), 2);
+        assert!(events.iter().all(|event| event.is_control()));
+        assert_eq!(subscriber.dropped(), 1);
+    }
+
+    #[tokio::test]
+    async fn conflate_keeps_latest_per_symbol() {
+        let bus = MarketDataBus::new(8);
+        let mut subscriber = bus.subscribe("test", BackpressurePolicy::ConflateLatest);
+        let now = Utc::now();
+        bus.publish(now, book("BTCUSDT", "1"));
+        bus.publish(now, book("ETHUSDT", "2"));
+        bus.publish(now, book("BTCUSDT", "3"));
+
+        let events = drain(&mut subscriber).await;
+        assert_eq!(req_ids(&events), ["3", "2"]);
+        assert_eq!(subscriber.dropped(), 0);
+    }
+
+    #[tokio::test]
+    async fn block_holds_back_only_the_publisher() {
+        let bus = MarketDataBus::new(1);
+        let mut blocking = bus.subscribe("block", BackpressurePolicy::Block);
+        let mut latest = bus.subscribe("latest", BackpressurePolicy::DropOldest);
+        let now = Utc::now();
+        bus.publish(now, book("BTCUSDT", "1"));
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        bus.publish(now, book("BTCUSDT", "2"));
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        bus.publish(now, book("BTCUSDT", "3"));
+
+        assert_eq!(req_ids(&drain(&mut latest).await), ["3"]);
+        assert_eq!(req_ids(&drain(&mut blocking).await), ["1", "2", "3"]);
+        assert_eq!(blocking.dropped(), 0);
+    }
+
+    #[tokio::test]
+    async fn detached_subscriber_never_blocks() {
+        let bus = MarketDataBus::new(1);
+        let gone = bus.subscribe("gone", BackpressurePolicy::Block);
+        let mut live = bus.subscribe("live", BackpressurePolicy::Block);
+        let now = Utc::now();
+        bus.publish(now, book("BTCUSDT", "1"));
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        bus.publish(now, book("BTCUSDT", "2"));
+
+        assert_eq!(req_ids(&drain(&mut live).await), ["1"]);
+        drop(gone);
+        assert_eq!(req_ids(&drain(&mut live).await), ["2"]);
+    }
+
+    #[tokio::test]
+    async fn stream_ends_when_bus_is_gone() {
+        let bus = MarketDataBus::new(4);
+        let mut subscriber = bus.subscribe("test", BackpressurePolicy::Block);
+        bus.publish(Utc::now(), book("BTCUSDT", "1"));
+        drop(bus);
+
+        assert!(subscriber.recv().await.is_some());
+        assert!(subscriber.recv().await.is_none());
+    }
+}
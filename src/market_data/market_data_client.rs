@@ -1,30 +1,50 @@
 use std::env;
-use std::sync::Arc;
-use chrono::Utc;
-use tokio::sync::Mutex;
-use tokio::net::TcpStream;
-use tokio_native_tls::TlsStream;
-use tokio_util::codec::Framed;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::time::{Instant, MissedTickBehavior};
 use futures_util::{SinkExt, StreamExt};
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use uuid::Uuid;
 
-use crate::utils::fix_util::FixCodec;
-use crate::utils::key_util::load_signing_key;
+use crate::utils::key_util::SessionCredentials;
 use crate::utils::message_util::{
     compute_raw_data,
     build_logon_message,
     build_heartbeat_message,
     build_market_data_request,
-    build_new_order_single,
-    build_order_cancel_request,
+    build_market_data_unsubscribe,
+    build_instrument_list_request,
     extract_field,
 };
-use crate::utils::connection_util::connect_fix_endpoint;
-use crate::types::{StrategyState,};
+use crate::market_data::candle_aggregator::CandleAggregator;
+use crate::market_data::freshness_monitor::{FreshnessConfig, FreshnessMonitor};
+use crate::market_data::market_data_bus::MarketDataBus;
+use crate::market_data::market_data_event::MarketDataEvent;
+use crate::market_data::market_data_message::parse_md_message;
+use crate::market_data::pipeline::MarketDataPipeline;
+use crate::market_data::recorder::RecorderHandle;
+use crate::market_data::symbol_registry::parse_instrument_list;
+use crate::market_data::subscription::{subscriptions_from_env, StreamKind, Subscription};
+use crate::market_data::subscription_manager::{
+    reject_reason,
+    SubscriptionCommand,
+    SubscriptionManager,
+};
+use crate::utils::config_util::session_var_parse;
+use crate::utils::connection_util::{connect_fix_endpoint, ConnectionConfig, FixConnection};
+use crate::utils::redact_util::redact_fix;
 
 
-pub async fn start_market_data_client(strategy: Arc<Mutex<StrategyState>>) -> anyhow::Result<()> {
+/// Runs the market data session. Books, bars and freshness live in this task, everything the
+/// strategy learns about them is published on the bus and, if given, recorded.
+pub async fn start_market_data_client(
+    credentials: SessionCredentials,
+    freshness: FreshnessConfig,
+    mut commands: UnboundedReceiver<SubscriptionCommand>,
+    bus: MarketDataBus,
+    recorder: Option<RecorderHandle>,
+) -> anyhow::Result<()> {
     dotenvy::dotenv().ok();
 
     let sender_comp_id = Uuid::new_v4()
@@ -33,22 +53,35 @@ pub async fn start_market_data_client(strategy: Arc<Mutex<StrategyState>>) -> an
         .to_string();
 
     let target_comp_id = env::var("BINANCE_TARGET_COMP_ID")?;
-    let username = env::var("BINANCE_API_KEY")?;
-    let signing_key = load_signing_key()?;
     let sending_time = Utc::now().format("%Y%m%d-%H:%M:%S%.3f").to_string();
     let msg_seq_num = 1;
 
     let raw_data = compute_raw_data(
-        &signing_key,
+        &credentials.signing_key,
         &sender_comp_id,
         &target_comp_id,
         msg_seq_num,
         &sending_time,
     );
 
-    let hostname = env::var("BINANCE_MD_HOSTNAME").unwrap();
-    let port: u16 = env::var("BINANCE_PORT").unwrap().parse()?;
-    let mut framed = connect_fix_endpoint(&hostname, port).await?;
+    let mut subscriptions = subscriptions_from_env()?;
+    let mut manager = SubscriptionManager::from_env()?;
+    let trade_symbols: Vec<String> = subscriptions
+        .iter()
+        .filter(|subscription| subscription.kind == StreamKind::Trades)
+        .map(|subscription| subscription.symbol.clone())
+        .collect();
+    let mut pipeline = MarketDataPipeline::new(CandleAggregator::from_env(&trade_symbols)?, FreshnessMonitor::new(freshness));
+    // Closes time bars and detects stale symbols on the wall clock even when no data arrives
+    let mut clock = tokio::time::interval(Duration::from_millis(100));
+    clock.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    // Trading rules are requested on logon and refreshed on this period, 0 disables the refresh
+    let refresh_secs: u64 = session_var_parse("MD", "INSTRUMENT_REFRESH_SECS")?.unwrap_or(3600);
+    let refresh_period = Duration::from_secs(refresh_secs.max(1));
+    let mut instrument_refresh = tokio::time::interval_at(Instant::now() + refresh_period, refresh_period);
+    let mut instrument_req_count = 0u32;
+    let connection_config = ConnectionConfig::from_env("MD")?;
+    let mut framed = connect_fix_endpoint(&connection_config).await?;
 
     // Build and send logon message
     let logon_msg = build_logon_message(
@@ -57,21 +90,86 @@ pub async fn start_market_data_client(strategy: Arc<Mutex<StrategyState>>) -> an
         msg_seq_num,
         &sending_time,
         &raw_data,
-        &username,
+        &credentials.api_key,
     );
 
-    debug!("Sending Market Data Logon: {}", logon_msg.replace('\x01', "|"));
+    debug!("Sending Market Data Logon: {}", redact_fix(&logon_msg));
     framed.send(logon_msg).await?;
     info!("Sent Market Data Logon");
 
     // Start sequence number after logon
     let mut seq_num = 2;
+    let mut logged_on = false;
+
+    // Monitor the stream for messages and runtime subscription changes
+    loop {
+        let msg = tokio::select! {
+            Some(command) = commands.recv() => {
+                match command {
+                    // Sent together with the configured subscriptions once logged on
+                    SubscriptionCommand::Subscribe(subscription) if !logged_on => subscriptions.push(subscription),
+                    SubscriptionCommand::Unsubscribe(subscription) if !logged_on => {
+                        subscriptions.retain(|queued| queued.symbol != subscription.symbol || queued.kind != subscription.kind);
+                    }
+                    SubscriptionCommand::Subscribe(subscription) => {
+                        open_subscription(
+                            &mut manager,
+                            &mut framed,
+                            &sender_comp_id,
+                            &target_comp_id,
+                            &mut seq_num,
+                            &subscription,
+                            recorder.as_ref(),
+                        ).await?;
+                    }
+                    SubscriptionCommand::Unsubscribe(subscription) => match manager.find_live(&subscription) {
+                        Some(req_id) => {
+                            close_subscription(
+                                &mut manager,
+                                &mut framed,
+                                &sender_comp_id,
+                                &target_comp_id,
+                                &mut seq_num,
+                                &req_id,
+                                recorder.as_ref(),
+                            ).await?;
+                            let symbol_live = manager.is_subscribed(&subscription.symbol);
+                            let events = pipeline.on_stream_closed(&subscription, symbol_live, "unsubscribed");
+                            publish(&bus, recorder.as_ref(), Utc::now(), events);
+                        }
+                        None => warn!("No live subscription to {:?} for {}", subscription.kind, subscription.symbol),
+                    },
+                }
+                continue;
+            }
+            _ = instrument_refresh.tick(), if logged_on && refresh_secs > 0 => {
+                request_instruments(
+                    &mut framed,
+                    &sender_comp_id,
+                    &target_comp_id,
+                    &mut seq_num,
+                    &mut instrument_req_count,
+                ).await?;
+                continue;
+            }
+            _ = clock.tick() => {
+                let now = Utc::now();
+                publish(&bus, None, now, pipeline.on_time(now));
+                continue;
+            }
+            msg = framed.next() => match msg {
+                Some(msg) => msg,
+                None => break,
+            },
+        };
 
-    // Monitor the stream for messages
-    while let Some(msg) = framed.next().await {
         match msg {
             Ok(line) => {
-                debug!("Received: {}", line.replace('\x01', "|"));
+                let recv_time = Utc::now();
+                debug!("Received: {}", redact_fix(&line));
+                if let Some(recorder) = &recorder {
+                    recorder.raw(recv_time, &line);
+                }
                 
                 // Parse the message to check if it's a TestRequest
                 if let Some(msg_type) = extract_field(&line, "35") {
@@ -85,7 +183,7 @@ pub async fn start_market_data_client(strategy: Arc<Mutex<StrategyState>>) -> an
                                     seq_num,
                                     Some(&test_req_id),
                                 );
-                                debug!("Sending Heartbeat: {}", heartbeat.replace('\x01', "|"));
+                                debug!("Sending Heartbeat: {}", redact_fix(&heartbeat));
                                 framed.send(heartbeat).await?;
                                 info!("Sent Heartbeat in response to TestRequest");
                                 seq_num += 1;
@@ -99,33 +197,116 @@ pub async fn start_market_data_client(strategy: Arc<Mutex<StrategyState>>) -> an
                             // Logon acknowledgment
                             info!("Market Data Logon successful");
 
-                            // Build a MarketDataRequest for Book Ticker
-                            let req_id = "BOOK_TICKER_STREAM";
-                            let symbol = "BTCUSDT";
-                            let entry_types = vec!["0", "1"]; // BID and OFFER
-                            let book_msg = build_market_data_request(
+                            logged_on = true;
+
+                            // One MarketDataRequest per configured subscription
+                            for subscription in &subscriptions {
+                                open_subscription(
+                                    &mut manager,
+                                    &mut framed,
+                                    &sender_comp_id,
+                                    &target_comp_id,
+                                    &mut seq_num,
+                                    subscription,
+                                    recorder.as_ref(),
+                                ).await?;
+                            }
+                            info!("{}/{} market data streams open", manager.open_streams(), manager.max_streams());
+
+                            request_instruments(
+                                &mut framed,
                                 &sender_comp_id,
                                 &target_comp_id,
-                                seq_num,
-                                req_id,
-                                symbol,
-                                &entry_types,
-                                Some(1), // MarketDepth = 1
+                                &mut seq_num,
+                                &mut instrument_req_count,
+                            ).await?;
+                        },
+                        "y" => {
+                            // InstrumentList, possibly one of several fragments
+                            match parse_instrument_list(&line) {
+                                Ok(list) => {
+                                    debug!(
+                                        "InstrumentList {:?} fragment with {} symbols",
+                                        list.req_id, list.instruments.len()
+                                    );
+                                    publish(&bus, recorder.as_ref(), recv_time, vec![MarketDataEvent::Instruments(list)]);
+                                }
+                                Err(e) => error!("Failed to parse InstrumentList: {}", e),
+                            }
+                        },
+                        "Y" => {
+                            // MarketDataRequestReject
+                            let req_id = extract_field(&line, "262").unwrap_or_default();
+                            let reason = reject_reason(
+                                extract_field(&line, "281").as_deref(),
+                                extract_field(&line, "58").as_deref(),
                             );
-                            debug!("Sending MarketDataRequest: {}", book_msg.replace('\x01', "|"));
-                            info!("Sending MarketDataRequest");
-                            framed.send(book_msg).await?;
-                            seq_num += 1;
+                            match manager.on_reject(&req_id, &reason) {
+                                Some(subscription) => {
+                                    if let Some(recorder) = &recorder {
+                                        recorder.stream_closed(recv_time, &req_id, &subscription);
+                                    }
+                                    error!(
+                                        "MarketDataRequest rejected | MDReqID: {} | Symbol: {} | Stream: {:?} | Reason: {}",
+                                        req_id, subscription.symbol, subscription.kind, reason
+                                    );
+                                    let symbol_live = manager.is_subscribed(&subscription.symbol);
+                                    let events = pipeline.on_stream_closed(
+                                        &subscription,
+                                        symbol_live,
+                                        &format!("subscription rejected: {}", reason),
+                                    );
+                                    publish(&bus, recorder.as_ref(), recv_time, events);
+                                }
+                                None => warn!("MarketDataRequestReject for unknown MDReqID {}: {}", req_id, reason),
+                            }
                         },
                         "X" | "W" => {
-                            handle_market_data_with_strategy(
-                                &line,
-                                Arc::clone(&strategy),
-                                &mut framed,
-                                &sender_comp_id,
-                                &target_comp_id,
-                                &mut seq_num,
-                            ).await;
+                            let md_message = match parse_md_message(&line) {
+                                Ok(md_message) => md_message,
+                                Err(e) => {
+                                    error!("Failed to parse market data: {}", e);
+                                    continue;
+                                }
+                            };
+
+                            // Tag the update with its subscription, late updates for a closed MDReqID are dropped
+                            let req_id = md_message.md_req_id.clone().unwrap_or_default();
+                            let Some(subscription) = manager.on_data(&req_id) else {
+                                debug!("Ignoring market data for inactive MDReqID {:?}", md_message.md_req_id);
+                                continue;
+                            };
+
+                            let events = pipeline.on_md_message(&md_message, &subscription, recv_time);
+                            let unsynced = events.iter().find_map(|event| match event {
+                                MarketDataEvent::BookUnsynced { reason, .. } => Some(reason.clone()),
+                                _ => None,
+                            });
+                            publish(&bus, recorder.as_ref(), recv_time, events);
+
+                            // Resubscribe for a fresh snapshot. The book stays unsynced until it arrives
+                            // and the symbol's freshness keeps being tracked.
+                            if let Some(reason) = unsynced {
+                                warn!("Book {} unsynced ({}), resubscribing", subscription.symbol, reason);
+                                close_subscription(
+                                    &mut manager,
+                                    &mut framed,
+                                    &sender_comp_id,
+                                    &target_comp_id,
+                                    &mut seq_num,
+                                    &req_id,
+                                    recorder.as_ref(),
+                                ).await?;
+                                open_subscription(
+                                    &mut manager,
+                                    &mut framed,
+                                    &sender_comp_id,
+                                    &target_comp_id,
+                                    &mut seq_num,
+                                    &subscription,
+                                    recorder.as_ref(),
+                                ).await?;
+                            }
                         },
                         "5" => {
                             // Logout
@@ -149,116 +330,110 @@ pub async fn start_market_data_client(strategy: Arc<Mutex<StrategyState>>) -> an
     Ok(())
 }
 
-async fn handle_market_data_with_strategy(
-    message: &str,
-    state: Arc<Mutex<StrategyState>>,
-    framed: &mut Framed<TlsStream<TcpStream>, FixCodec>,
+// Records and publishes the events of one message or clock tick
+fn publish(bus: &MarketDataBus, recorder: Option<&RecorderHandle>, recv_time: DateTime<Utc>, events: Vec<MarketDataEvent>) {
+    for event in events {
+        if let Some(recorder) = recorder {
+            recorder.event(recv_time, &event);
+        }
+        bus.publish(recv_time, event);
+    }
+}
+
+async fn request_instruments(
+    framed: &mut FixConnection,
     sender_comp_id: &str,
     target_comp_id: &str,
     seq_num: &mut i32,
-) {
-    let symbol = extract_field(message, "55").unwrap_or_default();
-    let side_tag = extract_field(message, "269").unwrap_or_default(); // 0 = BID, 1 = ASK
-    let price_str = extract_field(message, "270").unwrap_or_default();
-    let qty_str = extract_field(message, "271").unwrap_or_default();
-
-    log::info!(
-        "MarketData | Symbol: {} | Side: {} | Price: {} | Qty: {}",
-        symbol, side_tag, price_str, qty_str
-    );
-
-    let price = price_str.parse::<f64>().unwrap_or(0.0);
-    let qty = qty_str.parse::<f64>().unwrap_or(0.0);
-
-    // Lock the state
-    let mut state = state.lock().await;
-
-    if !state.oe_logon_ready {
-        log::info!("⚠️ Order entry session not ready yet.");
-        return;
-    }
+    req_count: &mut u32,
+) -> anyhow::Result<()> {
+    let req_id = format!("INSTRUMENTS_{}", req_count);
+    *req_count += 1;
 
-    let reference_price = state.reference_price;
-    let buy_threshold = reference_price * 0.99;
-    let sell_threshold = reference_price * 1.01;
-
-    if side_tag == "0" && price > sell_threshold && state.side.as_deref() != Some("SELL") {
-        // SELL signal
-        if let Some(ref orig_id) = state.active_order_id {
-            let cancel_id = Uuid::new_v4().to_string();
-            let cancel_msg = build_order_cancel_request(
-                sender_comp_id,
-                target_comp_id,
-                *seq_num,
-                &symbol,
-                &cancel_id,
-                orig_id,
-            );
-            if let Err(e) = framed.send(cancel_msg).await {
-                log::error!("Failed to send cancel: {}", e);
-            }
-            *seq_num += 1;
-        }
+    let request = build_instrument_list_request(sender_comp_id, target_comp_id, *seq_num, &req_id, None);
+    debug!("Sending InstrumentListRequest: {}", redact_fix(&request));
+    info!("Sending InstrumentListRequest | InstrumentReqID: {}", req_id);
+    framed.send(request).await?;
+    *seq_num += 1;
+    Ok(())
+}
 
-        let cl_ord_id = Uuid::new_v4().to_string();
-        let order_msg = build_new_order_single(
-            sender_comp_id,
-            target_comp_id,
-            *seq_num,
-            &symbol,
-            "SELL",
-            0.0001,
-            price,
-            &cl_ord_id,
-        );
-        if let Err(e) = framed.send(order_msg).await {
-            log::error!("Failed to send SELL order: {}", e);
+// Registers the subscription and sends its MarketDataRequest, a refused subscription is only logged
+async fn open_subscription(
+    manager: &mut SubscriptionManager,
+    framed: &mut FixConnection,
+    sender_comp_id: &str,
+    target_comp_id: &str,
+    seq_num: &mut i32,
+    subscription: &Subscription,
+    recorder: Option<&RecorderHandle>,
+) -> anyhow::Result<()> {
+    let req_id = match manager.subscribe(subscription) {
+        Ok(req_id) => req_id,
+        Err(e) => {
+            error!("Not subscribing: {}", e);
+            return Ok(());
         }
-        *seq_num += 1;
-
-        state.active_order_id = Some(cl_ord_id);
-        state.side = Some("SELL".to_string());
+    };
 
-        log::info!("📈 Strategy Signal - SELL @ {:.2} | Qty: {} | Symbol: {}", price, qty, symbol);
+    if let Some(recorder) = recorder {
+        recorder.stream_opened(Utc::now(), &req_id, subscription);
     }
+    send_subscription(framed, sender_comp_id, target_comp_id, seq_num, &req_id, subscription).await
+}
 
-    if side_tag == "1" && price < buy_threshold && state.side.as_deref() != Some("BUY") {
-        // BUY signal
-        if let Some(ref orig_id) = state.active_order_id {
-            let cancel_id = Uuid::new_v4().to_string();
-            let cancel_msg = build_order_cancel_request(
-                sender_comp_id,
-                target_comp_id,
-                *seq_num,
-                &symbol,
-                &cancel_id,
-                orig_id,
-            );
-            if let Err(e) = framed.send(cancel_msg).await {
-                log::error!("Failed to send cancel: {}", e);
-            }
-            *seq_num += 1;
-        }
-
-        let cl_ord_id = Uuid::new_v4().to_string();
-        let order_msg = build_new_order_single(
-            sender_comp_id,
-            target_comp_id,
-            *seq_num,
-            &symbol,
-            "BUY",
-            0.0001,
-            price,
-            &cl_ord_id,
-        );
-        if let Err(e) = framed.send(order_msg).await {
-            log::error!("Failed to send BUY order: {}", e);
-        }
-        *seq_num += 1;
+// Sends the unsubscribe for a live MDReqID
+async fn close_subscription(
+    manager: &mut SubscriptionManager,
+    framed: &mut FixConnection,
+    sender_comp_id: &str,
+    target_comp_id: &str,
+    seq_num: &mut i32,
+    req_id: &str,
+    recorder: Option<&RecorderHandle>,
+) -> anyhow::Result<()> {
+    let Some(subscription) = manager.unsubscribe(req_id) else {
+        return Ok(());
+    };
+    if let Some(recorder) = recorder {
+        recorder.stream_closed(Utc::now(), req_id, &subscription);
+    }
 
-        state.active_order_id = Some(cl_ord_id);
-        state.side = Some("BUY".to_string());
+    let unsubscribe = build_market_data_unsubscribe(sender_comp_id, target_comp_id, *seq_num, req_id);
+    debug!("Sending MarketDataRequest unsubscribe: {}", redact_fix(&unsubscribe));
+    info!(
+        "Unsubscribing | MDReqID: {} | Symbol: {} | Stream: {:?}",
+        req_id, subscription.symbol, subscription.kind
+    );
+    framed.send(unsubscribe).await?;
+    *seq_num += 1;
+    Ok(())
+}
 
-        log::info!("📉 Strategy Signal - BUY @ {:.2} | Qty: {} | Symbol: {}", price, qty, symbol);
-    }
+async fn send_subscription(
+    framed: &mut FixConnection,
+    sender_comp_id: &str,
+    target_comp_id: &str,
+    seq_num: &mut i32,
+    req_id: &str,
+    subscription: &Subscription,
+) -> anyhow::Result<()> {
+    let request = build_market_data_request(
+        sender_comp_id,
+        target_comp_id,
+        *seq_num,
+        req_id,
+        &subscription.symbol,
+        subscription.kind.entry_types(),
+        subscription.kind.market_depth(),
+        subscription.aggregated_book,
+    );
+    debug!("Sending MarketDataRequest: {}", redact_fix(&request));
+    info!(
+        "Sending MarketDataRequest | MDReqID: {} | Symbol: {} | Stream: {:?}",
+        req_id, subscription.symbol, subscription.kind
+    );
+    framed.send(request).await?;
+    *seq_num += 1;
+    Ok(())
 }
@@ -0,0 +1,91 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::market_data::candle_aggregator::Bar;
+use crate::market_data::freshness_monitor::FreshnessChange;
+use crate::market_data::market_data_message::{AggressorSide, MdEntry, MdEntryType};
+use crate::market_data::order_book::PriceLevel;
+use crate::market_data::symbol_registry::InstrumentList;
+use crate::utils::decimal_util::{Price, Qty};
+
+
+/// An individual trade from a trade stream (MDEntryType 269=2)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Trade {
+    pub md_req_id: String,
+    pub symbol: String,
+    pub price: Price,
+    pub qty: Qty,
+    /// TransactTime (60)
+    pub time: Option<DateTime<Utc>>,
+    /// TradeID (1003)
+    pub trade_id: Option<u64>,
+    /// AggressorSide (2446)
+    pub aggressor_side: Option<AggressorSide>,
+}
+
+impl Trade {
+    pub fn from_entry(md_req_id: &str, entry: &MdEntry) -> Option<Self> {
+        if entry.entry_type != MdEntryType::Trade {
+            return None;
+        }
+
+        Some(Self {
+            md_req_id: md_req_id.to_string(),
+            symbol: entry.symbol.clone(),
+            price: entry.price,
+            qty: entry.size,
+            time: entry.transact_time,
+            trade_id: entry.trade_id,
+            aggressor_side: entry.aggressor_side,
+        })
+    }
+}
+
+/// Top of book after a snapshot or incremental refresh was applied
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BookUpdate {
+    pub md_req_id: String,
+    pub symbol: String,
+    pub best_bid: Option<PriceLevel>,
+    pub best_ask: Option<PriceLevel>,
+}
+
+/// Normalized market data delivered to the strategy
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum MarketDataEvent {
+    Book(BookUpdate),
+    Trade(Trade),
+    /// A bar closed by the candle aggregator
+    Bar(Bar),
+    /// A symbol went stale or trading on it resumed
+    Freshness(FreshnessChange),
+    /// A book lost sync, e.g. on a gap, or its stream closed. It is not traded until `BookSynced`.
+    BookUnsynced { symbol: String, reason: String },
+    /// A book applied its snapshot and is complete again
+    BookSynced { symbol: String },
+    /// Trading rules from an InstrumentList fragment
+    Instruments(InstrumentList),
+}
+
+impl MarketDataEvent {
+    /// The symbol the event is about, None for the InstrumentList
+    pub fn symbol(&self) -> Option<&str> {
+        match self {
+            Self::Book(update) => Some(&update.symbol),
+            Self::Trade(trade) => Some(&trade.symbol),
+            Self::Bar(bar) => Some(&bar.symbol),
+            Self::Freshness(change) => Some(&change.symbol),
+            Self::BookUnsynced { symbol, .. } | Self::BookSynced { symbol } => Some(symbol),
+            Self::Instruments(_) => None,
+        }
+    }
+
+    /// State changes a consumer must not miss, the bus never drops or conflates them
+    pub fn is_control(&self) -> bool {
+        matches!(
+            self,
+            Self::Freshness(_) | Self::BookUnsynced { .. } | Self::BookSynced { .. } | Self::Instruments(_)
+        )
+    }
+}
@@ -0,0 +1,231 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::utils::decimal_util::{Price, Qty};
+use crate::utils::message_util::parse_fields;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MdUpdateAction {
+    New,
+    Change,
+    Delete,
+}
+
+impl MdUpdateAction {
+    // Tag 279
+    fn from_fix(value: &str) -> Option<Self> {
+        match value {
+            "0" => Some(Self::New),
+            "1" => Some(Self::Change),
+            "2" => Some(Self::Delete),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MdEntryType {
+    Bid,
+    Offer,
+    Trade,
+}
+
+impl MdEntryType {
+    // Tag 269
+    fn from_fix(value: &str) -> Option<Self> {
+        match value {
+            "0" => Some(Self::Bid),
+            "1" => Some(Self::Offer),
+            "2" => Some(Self::Trade),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AggressorSide {
+    Buy,
+    Sell,
+}
+
+impl AggressorSide {
+    // Tag 2446
+    fn from_fix(value: &str) -> Option<Self> {
+        match value {
+            "1" => Some(Self::Buy),
+            "2" => Some(Self::Sell),
+            _ => None,
+        }
+    }
+}
+
+// TransactTime (60), e.g. 20250101-12:00:00.123456
+pub fn parse_transact_time(value: &str) -> anyhow::Result<DateTime<Utc>> {
+    let time = NaiveDateTime::parse_from_str(value, "%Y%m%d-%H:%M:%S%.f")
+        .map_err(|e| anyhow::anyhow!("Invalid TransactTime '{}': {}", value, e))?;
+    Ok(time.and_utc())
+}
+
+#[derive(Debug, Clone)]
+pub struct MdEntry {
+    /// None for snapshot entries
+    pub update_action: Option<MdUpdateAction>,
+    pub entry_type: MdEntryType,
+    pub symbol: String,
+    pub price: Price,
+    pub size: Qty,
+    pub first_book_update_id: Option<u64>,
+    pub last_book_update_id: Option<u64>,
+    /// Trade entries only
+    pub transact_time: Option<DateTime<Utc>>,
+    pub trade_id: Option<u64>,
+    pub aggressor_side: Option<AggressorSide>,
+}
+
+/// A MarketDataSnapshot (W) or MarketDataIncrementalRefresh (X) with its NoMDEntries (268) group.
+#[derive(Debug, Clone)]
+pub struct MdMessage {
+    pub is_snapshot: bool,
+    pub md_req_id: Option<String>,
+    pub symbol: Option<String>,
+    pub last_book_update_id: Option<u64>,
+    /// LastFragment (893), false while Binance is still sending the rest of a book update
+    pub last_fragment: bool,
+    pub entries: Vec<MdEntry>,
+}
+
+#[derive(Default)]
+struct EntryBuilder {
+    update_action: Option<MdUpdateAction>,
+    entry_type: Option<MdEntryType>,
+    symbol: Option<String>,
+    price: Option<Price>,
+    size: Option<Qty>,
+    first_book_update_id: Option<u64>,
+    last_book_update_id: Option<u64>,
+    transact_time: Option<DateTime<Utc>>,
+    trade_id: Option<u64>,
+    aggressor_side: Option<AggressorSide>,
+}
+
+/// Parses every MDEntry of a W or X message.
+/// Binance only repeats Symbol (55) and the book update IDs when they change,
+/// so entries inherit them from the message header or the previous entry.
+pub fn parse_md_message(message: &str) -> anyhow::Result<MdMessage> {
+    let fields = parse_fields(message);
+
+    let msg_type = fields
+        .iter()
+        .find(|(tag, _)| *tag == "35")
+        .map(|(_, value)| *value)
+        .unwrap_or_default();
+    let is_snapshot = match msg_type {
+        "W" => true,
+        "X" => false,
+        other => anyhow::bail!("Not a market data message: 35={}", other),
+    };
+
+    let mut md_message = MdMessage {
+        is_snapshot,
+        md_req_id: None,
+        symbol: None,
+        last_book_update_id: None,
+        last_fragment: true,
+        entries: Vec::new(),
+    };
+
+    let group_start = fields.iter().position(|(tag, _)| *tag == "268");
+    let (header, group) = match group_start {
+        Some(pos) => (&fields[..pos], &fields[pos + 1..]),
+        None => (&fields[..], &fields[fields.len()..]),
+    };
+
+    for (tag, value) in header {
+        match *tag {
+            "262" => md_message.md_req_id = Some(value.to_string()),
+            "55" => md_message.symbol = Some(value.to_string()),
+            "25044" => md_message.last_book_update_id = Some(value.parse()?),
+            "893" => md_message.last_fragment = *value == "Y",
+            _ => {}
+        }
+    }
+
+    // The first tag of the group marks the start of every entry
+    let Some((delimiter, _)) = group.first().copied() else {
+        return Ok(md_message);
+    };
+
+    let mut symbol = md_message.symbol.clone();
+    let mut first_id = None;
+    let mut last_id = md_message.last_book_update_id;
+    let mut current: Option<EntryBuilder> = None;
+
+    for (tag, value) in group {
+        if *tag == delimiter {
+            if let Some(builder) = current.take() {
+                md_message.entries.push(finish_entry(builder, &mut symbol, &mut first_id, &mut last_id)?);
+            }
+            current = Some(EntryBuilder::default());
+        }
+
+        let Some(builder) = current.as_mut() else { continue };
+        match *tag {
+            "279" => builder.update_action = MdUpdateAction::from_fix(value),
+            "269" => builder.entry_type = MdEntryType::from_fix(value),
+            "55" => builder.symbol = Some(value.to_string()),
+            "270" => builder.price = Some(value.parse()?),
+            "271" => builder.size = Some(value.parse()?),
+            "25043" => builder.first_book_update_id = Some(value.parse()?),
+            "25044" => builder.last_book_update_id = Some(value.parse()?),
+            "60" => builder.transact_time = Some(parse_transact_time(value)?),
+            "1003" => builder.trade_id = Some(value.parse()?),
+            "2446" => builder.aggressor_side = AggressorSide::from_fix(value),
+            "893" => md_message.last_fragment = *value == "Y",
+            _ => {}
+        }
+    }
+
+    if let Some(builder) = current.take() {
+        md_message.entries.push(finish_entry(builder, &mut symbol, &mut first_id, &mut last_id)?);
+    }
+
+    Ok(md_message)
+}
+
+fn finish_entry(
+    builder: EntryBuilder,
+    symbol: &mut Option<String>,
+    first_id: &mut Option<u64>,
+    last_id: &mut Option<u64>,
+) -> anyhow::Result<MdEntry> {
+    if builder.symbol.is_some() {
+        *symbol = builder.symbol;
+    }
+    if builder.first_book_update_id.is_some() {
+        *first_id = builder.first_book_update_id;
+    }
+    if builder.last_book_update_id.is_some() {
+        *last_id = builder.last_book_update_id;
+    }
+
+    let entry_type = builder
+        .entry_type
+        .ok_or_else(|| anyhow::anyhow!("MDEntry without a known MDEntryType (269)"))?;
+    let symbol = symbol
+        .clone()
+        .ok_or_else(|| anyhow::anyhow!("MDEntry without a Symbol (55)"))?;
+
+    Ok(MdEntry {
+        update_action: builder.update_action,
+        entry_type,
+        symbol,
+        price: builder.price.unwrap_or_default(),
+        size: builder.size.unwrap_or_default(),
+        first_book_update_id: *first_id,
+        last_book_update_id: *last_id,
+        transact_time: builder.transact_time,
+        trade_id: builder.trade_id,
+        aggressor_side: builder.aggressor_side,
+    })
+}
@@ -1 +1,15 @@
-pub mod market_data_client;
\ No newline at end of file
+pub mod candle_aggregator;
+pub mod capture;
+pub mod freshness_monitor;
+pub mod market_data_bus;
+pub mod market_data_client;
+pub mod market_data_event;
+pub mod market_data_message;
+pub mod pipeline;
+pub mod order_book;
+pub mod recorder;
+pub mod replay;
+pub mod subscription;
+pub mod subscription_manager;
+pub mod symbol_registry;
+pub mod top_of_book;
\ No newline at end of file
@@ -0,0 +1,403 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::market_data::market_data_message::{MdEntry, MdEntryType, MdMessage, MdUpdateAction};
+use crate::utils::decimal_util::{Price, Qty};
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BookSide {
+    Bid,
+    Ask,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PriceLevel {
+    pub price: Price,
+    pub size: Qty,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DepthLevel {
+    pub price: Price,
+    pub size: Qty,
+    /// Size of this level plus every better level
+    pub cumulative_size: Qty,
+}
+
+/// Why a book was dropped and needs a fresh snapshot
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BookIntegrityError {
+    /// FirstBookUpdateID (25043) did not follow the last applied LastBookUpdateID (25044), or a
+    /// fragmented update was followed by another update before its last fragment
+    Gap { expected: u64, received: u64 },
+    /// Best bid at or above best ask once an update was complete
+    Crossed { bid: Price, ask: Price },
+}
+
+impl fmt::Display for BookIntegrityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Gap { expected, received } => write!(
+                f,
+                "book update gap, expected FirstBookUpdateID {} but received {}",
+                expected, received
+            ),
+            Self::Crossed { bid, ask } => write!(f, "crossed book, bid {} >= ask {}", bid, ask),
+        }
+    }
+}
+
+impl std::error::Error for BookIntegrityError {}
+
+/// Local order book for one symbol built from W snapshots and X incremental refreshes.
+#[derive(Debug, Clone)]
+pub struct OrderBook {
+    pub symbol: String,
+    /// Levels kept per side, Some(1) for a book ticker subscription
+    pub max_depth: Option<usize>,
+    bids: BTreeMap<Price, Qty>,
+    asks: BTreeMap<Price, Qty>,
+    /// LastBookUpdateID of the last applied update
+    last_update_id: Option<u64>,
+    synced: bool,
+    /// The previous message was not the LastFragment of its book update
+    pending_fragment: bool,
+}
+
+impl OrderBook {
+    pub fn new(symbol: &str, max_depth: Option<usize>) -> Self {
+        Self {
+            symbol: symbol.to_string(),
+            max_depth,
+            bids: BTreeMap::new(),
+            asks: BTreeMap::new(),
+            last_update_id: None,
+            synced: false,
+            pending_fragment: false,
+        }
+    }
+
+    /// A book ticker is complete with every update, deeper books need a snapshot first
+    fn requires_snapshot(&self) -> bool {
+        self.max_depth != Some(1)
+    }
+
+    /// False until the first snapshot and again after an integrity error until the next one
+    pub fn is_synced(&self) -> bool {
+        self.synced
+    }
+
+    /// Drops all levels and waits for a fresh snapshot
+    pub fn reset(&mut self) {
+        self.clear();
+        self.last_update_id = None;
+        self.synced = false;
+        self.pending_fragment = false;
+    }
+
+    /// Applies every bid/offer entry of the message for this symbol.
+    /// A snapshot replaces the book, incremental entries are applied in order after checking
+    /// that their book update IDs continue the last applied update. On error the book is reset.
+    pub fn apply(&mut self, message: &MdMessage) -> Result<(), BookIntegrityError> {
+        let Some(first_entry) = message.entries.iter().find(|entry| entry.symbol == self.symbol) else {
+            return Ok(());
+        };
+        let first_id = first_entry.first_book_update_id;
+        let last_id = first_entry.last_book_update_id.or(message.last_book_update_id);
+
+        if message.is_snapshot {
+            self.clear();
+            self.last_update_id = last_id;
+            self.synced = true;
+        } else {
+            if self.requires_snapshot() && !self.synced {
+                // Still waiting for the snapshot, nothing to build on
+                return Ok(());
+            }
+
+            if let (Some(last_applied), Some(last_id)) = (self.last_update_id, last_id) {
+                let continues_fragment = self.pending_fragment && last_applied == last_id;
+                if self.pending_fragment && !continues_fragment {
+                    // The rest of the fragmented update never arrived
+                    self.reset();
+                    return Err(BookIntegrityError::Gap {
+                        expected: last_applied,
+                        received: first_id.unwrap_or(last_id),
+                    });
+                }
+                if last_id <= last_applied && !continues_fragment {
+                    // Already covered by the snapshot or a previous update
+                    return Ok(());
+                }
+                if let Some(first_id) = first_id {
+                    if !continues_fragment && first_id > last_applied + 1 {
+                        self.reset();
+                        return Err(BookIntegrityError::Gap {
+                            expected: last_applied + 1,
+                            received: first_id,
+                        });
+                    }
+                }
+            }
+            if last_id.is_some() {
+                self.last_update_id = last_id;
+            }
+            self.synced = true;
+        }
+
+        for entry in &message.entries {
+            if entry.symbol == self.symbol {
+                self.apply_entry(entry, message.is_snapshot);
+            }
+        }
+
+        self.truncate();
+        self.pending_fragment = !message.last_fragment;
+
+        if !self.pending_fragment {
+            if let (Some(bid), Some(ask)) = (self.best_bid(), self.best_ask()) {
+                if bid.price >= ask.price {
+                    self.reset();
+                    return Err(BookIntegrityError::Crossed { bid: bid.price, ask: ask.price });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn apply_entry(&mut self, entry: &MdEntry, is_snapshot: bool) {
+        let levels = match entry.entry_type {
+            MdEntryType::Bid => &mut self.bids,
+            MdEntryType::Offer => &mut self.asks,
+            MdEntryType::Trade => return,
+        };
+
+        let action = if is_snapshot {
+            MdUpdateAction::New
+        } else {
+            entry.update_action.unwrap_or(MdUpdateAction::Change)
+        };
+
+        match action {
+            MdUpdateAction::New | MdUpdateAction::Change if entry.size.is_positive() => {
+                // A book ticker only ever carries the current top level
+                if self.max_depth == Some(1) {
+                    levels.clear();
+                }
+                levels.insert(entry.price, entry.size);
+            }
+            _ => {
+                levels.remove(&entry.price);
+            }
+        }
+    }
+
+    fn truncate(&mut self) {
+        let Some(max_depth) = self.max_depth else { return };
+
+        while self.bids.len() > max_depth {
+            self.bids.pop_first();
+        }
+        while self.asks.len() > max_depth {
+            self.asks.pop_last();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.bids.clear();
+        self.asks.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bids.is_empty() && self.asks.is_empty()
+    }
+
+    pub fn best_bid(&self) -> Option<PriceLevel> {
+        self.bids
+            .iter()
+            .next_back()
+            .map(|(price, size)| PriceLevel { price: *price, size: *size })
+    }
+
+    pub fn best_ask(&self) -> Option<PriceLevel> {
+        self.asks
+            .iter()
+            .next()
+            .map(|(price, size)| PriceLevel { price: *price, size: *size })
+    }
+
+    /// Best `n` levels of one side, best price first
+    pub fn levels(&self, side: BookSide, n: usize) -> Vec<PriceLevel> {
+        let to_level = |(price, size): (&Price, &Qty)| PriceLevel { price: *price, size: *size };
+        match side {
+            BookSide::Bid => self.bids.iter().rev().take(n).map(to_level).collect(),
+            BookSide::Ask => self.asks.iter().take(n).map(to_level).collect(),
+        }
+    }
+
+    /// Best `n` levels of one side with the running size total
+    pub fn depth(&self, side: BookSide, n: usize) -> Vec<DepthLevel> {
+        let mut cumulative_size = Qty::ZERO;
+        self.levels(side, n)
+            .into_iter()
+            .map(|level| {
+                cumulative_size += level.size;
+                DepthLevel {
+                    price: level.price,
+                    size: level.size,
+                    cumulative_size,
+                }
+            })
+            .collect()
+    }
+
+    /// Total size over the best `n` levels of one side
+    pub fn cumulative_size(&self, side: BookSide, n: usize) -> Qty {
+        self.levels(side, n).iter().map(|level| level.size).sum()
+    }
+
+    pub fn level_count(&self, side: BookSide) -> usize {
+        match side {
+            BookSide::Bid => self.bids.len(),
+            BookSide::Ask => self.asks.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::market_data::market_data_message::parse_md_message;
+
+    fn message(fields: &str) -> MdMessage {
+        parse_md_message(&format!("8=FIX.4.4|{}|", fields).replace('|', "\x01")).unwrap()
+    }
+
+    fn snapshot(last_id: u64) -> MdMessage {
+        message(&format!(
+            "35=W|262=BTCUSDT_DEPTH5_1|55=BTCUSDT|25044={}|268=4|269=0|270=100|271=1|269=0|270=99|271=2|269=1|270=101|271=3|269=1|270=102|271=4",
+            last_id
+        ))
+    }
+
+    fn update(first_id: u64, last_id: u64, entries: &str) -> MdMessage {
+        message(&format!("35=X|262=BTCUSDT_DEPTH5_1|268=1|279={}|55=BTCUSDT|25043={}|25044={}", entries, first_id, last_id))
+    }
+
+    fn level(price: &str, size: &str) -> PriceLevel {
+        PriceLevel {
+            price: price.parse().unwrap(),
+            size: size.parse().unwrap(),
+        }
+    }
+
+    fn synced_book() -> OrderBook {
+        let mut book = OrderBook::new("BTCUSDT", Some(5));
+        book.apply(&snapshot(100)).unwrap();
+        book
+    }
+
+    #[test]
+    fn snapshot_then_updates() {
+        let mut book = synced_book();
+        assert!(book.is_synced());
+        assert_eq!(book.best_bid(), Some(level("100", "1")));
+        assert_eq!(book.best_ask(), Some(level("101", "3")));
+
+        book.apply(&update(101, 101, "1|269=0|270=100|271=5")).unwrap();
+        book.apply(&update(102, 102, "2|269=1|270=101|271=0")).unwrap();
+        book.apply(&update(103, 103, "0|269=0|270=100.5|271=1")).unwrap();
+
+        assert_eq!(book.levels(BookSide::Bid, 5), [level("100.5", "1"), level("100", "5"), level("99", "2")]);
+        assert_eq!(book.best_ask(), Some(level("102", "4")));
+        let depth = book.depth(BookSide::Bid, 2);
+        assert_eq!(depth[1].cumulative_size, "6".parse().unwrap());
+        assert_eq!(book.cumulative_size(BookSide::Bid, 5), "8".parse().unwrap());
+        assert_eq!(book.level_count(BookSide::Ask), 1);
+    }
+
+    #[test]
+    fn updates_wait_for_the_snapshot() {
+        let mut book = OrderBook::new("BTCUSDT", Some(5));
+        book.apply(&update(90, 90, "0|269=0|270=100|271=1")).unwrap();
+        assert!(!book.is_synced());
+        assert!(book.is_empty());
+    }
+
+    #[test]
+    fn covered_updates_are_skipped() {
+        let mut book = synced_book();
+        book.apply(&update(95, 100, "1|269=0|270=100|271=9")).unwrap();
+        assert_eq!(book.best_bid(), Some(level("100", "1")));
+    }
+
+    #[test]
+    fn gap_resets_the_book() {
+        let mut book = synced_book();
+        let err = book.apply(&update(105, 105, "1|269=0|270=100|271=9")).unwrap_err();
+
+        assert_eq!(err, BookIntegrityError::Gap { expected: 101, received: 105 });
+        assert!(!book.is_synced());
+        assert!(book.is_empty());
+        // Nothing applies until the next snapshot
+        book.apply(&update(106, 106, "1|269=0|270=100|271=9")).unwrap();
+        assert!(book.is_empty());
+        book.apply(&snapshot(110)).unwrap();
+        assert!(book.is_synced());
+    }
+
+    #[test]
+    fn crossed_book_resets() {
+        let mut book = synced_book();
+        let err = book.apply(&update(101, 101, "0|269=0|270=101.5|271=1")).unwrap_err();
+
+        assert!(matches!(err, BookIntegrityError::Crossed { .. }));
+        assert!(!book.is_synced());
+    }
+
+    #[test]
+    fn fragments_share_the_update_id() {
+        let mut book = synced_book();
+        book.apply(&message("35=X|893=N|268=1|279=1|269=0|55=BTCUSDT|270=100|271=7|25043=101|25044=101")).unwrap();
+        // Crossed only once the last fragment is in
+        book.apply(&message("35=X|893=N|268=1|279=0|269=0|55=BTCUSDT|270=101.5|271=1|25043=101|25044=101")).unwrap();
+        book.apply(&message("35=X|893=Y|268=1|279=2|269=0|55=BTCUSDT|270=101.5|271=0|25043=101|25044=101")).unwrap();
+
+        assert_eq!(book.best_bid(), Some(level("100", "7")));
+    }
+
+    #[test]
+    fn missing_last_fragment_is_a_gap() {
+        let mut book = synced_book();
+        book.apply(&message("35=X|893=N|268=1|279=1|269=0|55=BTCUSDT|270=100|271=7|25043=101|25044=101")).unwrap();
+        // Contiguous, but 101 never got its last fragment
+        let err = book.apply(&update(102, 102, "1|269=1|270=101|271=5")).unwrap_err();
+
+        assert_eq!(err, BookIntegrityError::Gap { expected: 101, received: 102 });
+        assert!(!book.is_synced());
+        assert!(book.is_empty());
+    }
+
+    #[test]
+    fn depth_is_truncated() {
+        let mut book = OrderBook::new("BTCUSDT", Some(2));
+        book.apply(&snapshot(100)).unwrap();
+        assert_eq!(book.level_count(BookSide::Bid), 2);
+        book.apply(&update(101, 101, "0|269=1|270=100.5|271=1")).unwrap();
+        assert_eq!(book.levels(BookSide::Ask, 5), [level("100.5", "1"), level("101", "3")]);
+    }
+
+    #[test]
+    fn book_ticker_needs_no_snapshot() {
+        let mut book = OrderBook::new("BTCUSDT", Some(1));
+        book.apply(&message("35=X|268=2|279=1|269=0|55=BTCUSDT|270=100|271=1|25043=1|25044=1|279=1|269=1|270=101|271=1")).unwrap();
+        assert!(book.is_synced());
+        book.apply(&message("35=X|268=1|279=1|269=0|55=BTCUSDT|270=99|271=2|25043=2|25044=2")).unwrap();
+
+        assert_eq!(book.levels(BookSide::Bid, 5), [level("99", "2")]);
+    }
+}
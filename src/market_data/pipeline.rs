@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use log::debug;
+
+use crate::market_data::candle_aggregator::CandleAggregator;
+use crate::market_data::freshness_monitor::FreshnessMonitor;
+use crate::market_data::market_data_event::{BookUpdate, MarketDataEvent, Trade};
+use crate::market_data::market_data_message::MdMessage;
+use crate::market_data::order_book::OrderBook;
+use crate::market_data::subscription::Subscription;
+
+
+/// Turns market data into the events the strategy sees, shared by the live session and replay.
+/// It owns the books and the freshness state, the strategy only learns about them from events.
+#[derive(Debug, Clone, Default)]
+pub struct MarketDataPipeline {
+    candles: CandleAggregator,
+    books: HashMap<String, OrderBook>,
+    freshness: FreshnessMonitor,
+}
+
+impl MarketDataPipeline {
+    pub fn new(candles: CandleAggregator, freshness: FreshnessMonitor) -> Self {
+        Self {
+            candles,
+            books: HashMap::new(),
+            freshness,
+        }
+    }
+
+    /// Applies a W or X message of a subscription. On an integrity error the book is reset,
+    /// reported with `BookUnsynced` and waits for a fresh snapshot.
+    pub fn on_md_message(
+        &mut self,
+        message: &MdMessage,
+        subscription: &Subscription,
+        now: DateTime<Utc>,
+    ) -> Vec<MarketDataEvent> {
+        // Any update proves the feed is alive, even one that breaks the book
+        let mut events: Vec<MarketDataEvent> = self
+            .freshness
+            .on_data(&subscription.symbol, now)
+            .map(MarketDataEvent::Freshness)
+            .into_iter()
+            .collect();
+
+        if subscription.kind.is_book() {
+            events.extend(self.update_book(message, subscription));
+            return events;
+        }
+
+        let req_id = message.md_req_id.as_deref().unwrap_or_default();
+        let trades = message.entries.iter().filter_map(|entry| Trade::from_entry(req_id, entry));
+        events.extend(self.on_trades(trades, now));
+        events
+    }
+
+    /// Drops the book of a closed stream and stops tracking the symbol's freshness once none of
+    /// its streams is live
+    pub fn on_stream_closed(&mut self, subscription: &Subscription, symbol_live: bool, reason: &str) -> Vec<MarketDataEvent> {
+        if !symbol_live {
+            self.freshness.untrack(&subscription.symbol);
+        }
+        if !subscription.kind.is_book() {
+            return Vec::new();
+        }
+        self.books.remove(&subscription.symbol);
+        vec![MarketDataEvent::BookUnsynced {
+            symbol: subscription.symbol.clone(),
+            reason: reason.to_string(),
+        }]
+    }
+
+    /// Applies a recorded normalized event, bars and freshness changes are rebuilt
+    pub fn on_event(&mut self, event: MarketDataEvent, now: DateTime<Utc>) -> Vec<MarketDataEvent> {
+        let mut events: Vec<MarketDataEvent> = match &event {
+            MarketDataEvent::Book(BookUpdate { symbol, .. }) | MarketDataEvent::Trade(Trade { symbol, .. }) => self
+                .freshness
+                .on_data(symbol, now)
+                .map(MarketDataEvent::Freshness)
+                .into_iter()
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        match event {
+            MarketDataEvent::Trade(trade) => events.extend(self.on_trades(std::iter::once(trade), now)),
+            MarketDataEvent::Bar(_) | MarketDataEvent::Freshness(_) => {}
+            event => events.push(event),
+        }
+        events
+    }
+
+    /// Time bars that closed and freshness changes that happened by `now`
+    pub fn on_time(&mut self, now: DateTime<Utc>) -> Vec<MarketDataEvent> {
+        let mut events: Vec<MarketDataEvent> = self.candles.on_time(now).into_iter().map(MarketDataEvent::Bar).collect();
+        events.extend(self.freshness.check(now).into_iter().map(MarketDataEvent::Freshness));
+        events
+    }
+
+    // Every trade is followed by the bars it closed
+    fn on_trades(&mut self, trades: impl Iterator<Item = Trade>, now: DateTime<Utc>) -> Vec<MarketDataEvent> {
+        let mut events = Vec::new();
+        for trade in trades {
+            let bars = self.candles.on_trade(&trade, now);
+            events.push(MarketDataEvent::Trade(trade));
+            events.extend(bars.into_iter().map(MarketDataEvent::Bar));
+        }
+        events
+    }
+
+    // The new top of book, nothing while the book waits for its snapshot
+    fn update_book(&mut self, message: &MdMessage, subscription: &Subscription) -> Vec<MarketDataEvent> {
+        let book = self
+            .books
+            .entry(subscription.symbol.clone())
+            .or_insert_with(|| {
+                let depth = subscription.kind.market_depth().map(|depth| depth as usize);
+                OrderBook::new(&subscription.symbol, depth)
+            });
+
+        let was_synced = book.is_synced();
+        if let Err(err) = book.apply(message) {
+            return vec![MarketDataEvent::BookUnsynced {
+                symbol: book.symbol.clone(),
+                reason: err.to_string(),
+            }];
+        }
+        if !book.is_synced() {
+            return Vec::new();
+        }
+
+        debug!(
+            "Book {} | Best Bid: {:?} | Best Ask: {:?}",
+            book.symbol, book.best_bid(), book.best_ask()
+        );
+        let mut events = Vec::new();
+        if !was_synced {
+            events.push(MarketDataEvent::BookSynced { symbol: book.symbol.clone() });
+        }
+        events.push(MarketDataEvent::Book(BookUpdate {
+            md_req_id: message.md_req_id.clone().unwrap_or_default(),
+            symbol: book.symbol.clone(),
+            best_bid: book.best_bid(),
+            best_ask: book.best_ask(),
+        }));
+        events
+    }
+}
@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use log::{error, info};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::market_data::capture::{capture_path, CapturePayload, CaptureRecord, CaptureWriter};
+use crate::market_data::market_data_event::MarketDataEvent;
+use crate::market_data::subscription::Subscription;
+use crate::utils::config_util::{session_var, session_var_parse};
+use crate::utils::message_util::parse_fields;
+use crate::utils::redact_util::mask_fix;
+
+// Session messages, e.g. heartbeats, rejects and the InstrumentList, are kept under this name
+const SESSION_SYMBOL: &str = "_SESSION";
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordMode {
+    /// Every FIX message as received
+    Raw,
+    /// Normalized book and trade events
+    Events,
+}
+
+#[derive(Debug, Clone)]
+pub struct RecorderConfig {
+    pub dir: PathBuf,
+    pub mode: RecordMode,
+    /// zstd level, None for uncompressed captures
+    pub zstd_level: Option<i32>,
+    /// A new indexed block is started after this many milliseconds
+    pub index_interval_ms: i64,
+}
+
+impl RecorderConfig {
+    /// None unless BINANCE_MD_RECORD_DIR is set
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let Some(dir) = session_var("MD", "RECORD_DIR") else {
+            return Ok(None);
+        };
+
+        let mode = match session_var("MD", "RECORD_MODE").as_deref().map(str::trim) {
+            None | Some("raw") => RecordMode::Raw,
+            Some("events") => RecordMode::Events,
+            Some(other) => anyhow::bail!("Invalid BINANCE_MD_RECORD_MODE '{}', expected raw or events", other),
+        };
+
+        let zstd_level = match session_var("MD", "RECORD_COMPRESSION").as_deref().map(str::trim) {
+            None | Some("none") => None,
+            Some("zstd") => Some(zstd::DEFAULT_COMPRESSION_LEVEL),
+            Some(level) => match level.strip_prefix("zstd:").map(str::parse::<i32>) {
+                Some(Ok(level)) => Some(level),
+                _ => anyhow::bail!(
+                    "Invalid BINANCE_MD_RECORD_COMPRESSION '{}', expected none, zstd or zstd:LEVEL",
+                    level
+                ),
+            },
+        };
+
+        let index_interval_ms = session_var_parse("MD", "RECORD_INDEX_MS")?.unwrap_or(1000);
+        if index_interval_ms <= 0 {
+            anyhow::bail!("BINANCE_MD_RECORD_INDEX_MS must be positive");
+        }
+
+        Ok(Some(Self {
+            dir: PathBuf::from(dir),
+            mode,
+            zstd_level,
+            index_interval_ms,
+        }))
+    }
+}
+
+enum RecorderMessage {
+    Record { symbol: String, record: CaptureRecord },
+    /// Finish every capture and stop, answered once the files are complete
+    Finish(oneshot::Sender<()>),
+}
+
+/// Hands records to the recorder task, disk writes never block the market data session
+#[derive(Debug, Clone)]
+pub struct RecorderHandle {
+    mode: RecordMode,
+    records: mpsc::UnboundedSender<RecorderMessage>,
+}
+
+impl RecorderHandle {
+    /// Records a received FIX message in raw mode, with the same fields masked as in the logs
+    pub fn raw(&self, recv_time: DateTime<Utc>, message: &str) {
+        if self.mode != RecordMode::Raw {
+            return;
+        }
+        let fields = parse_fields(message);
+        let is_market_data = fields
+            .iter()
+            .any(|(tag, msg_type)| *tag == "35" && matches!(*msg_type, "W" | "X"));
+        let symbol = fields
+            .into_iter()
+            .find(|(tag, _)| is_market_data && *tag == "55")
+            .map_or(SESSION_SYMBOL, |(_, symbol)| symbol)
+            .to_string();
+        self.send(symbol, recv_time, CapturePayload::Raw(mask_fix(message)));
+    }
+
+    /// Records a book, trade, book sync or InstrumentList event in events mode, bars and
+    /// freshness changes are derived and not recorded
+    pub fn event(&self, recv_time: DateTime<Utc>, event: &MarketDataEvent) {
+        if self.mode != RecordMode::Events || matches!(event, MarketDataEvent::Bar(_) | MarketDataEvent::Freshness(_)) {
+            return;
+        }
+        let symbol = event.symbol().unwrap_or(SESSION_SYMBOL).to_string();
+        self.send(symbol, recv_time, CapturePayload::Event(event.clone()));
+    }
+
+    /// Records a stream the session opened in raw mode, replay needs it to map the MDReqID
+    pub fn stream_opened(&self, recv_time: DateTime<Utc>, md_req_id: &str, subscription: &Subscription) {
+        self.stream(recv_time, md_req_id, &subscription.symbol, Some(subscription));
+    }
+
+    /// Records that a stream closed, it is no longer repeated in new blocks
+    pub fn stream_closed(&self, recv_time: DateTime<Utc>, md_req_id: &str, subscription: &Subscription) {
+        self.stream(recv_time, md_req_id, &subscription.symbol, None);
+    }
+
+    fn stream(&self, recv_time: DateTime<Utc>, md_req_id: &str, symbol: &str, subscription: Option<&Subscription>) {
+        if self.mode != RecordMode::Raw {
+            return;
+        }
+        let payload = CapturePayload::Subscription {
+            md_req_id: md_req_id.to_string(),
+            subscription: subscription.cloned(),
+        };
+        self.send(symbol.to_string(), recv_time, payload);
+    }
+
+    fn send(&self, symbol: String, recv_time: DateTime<Utc>, payload: CapturePayload) {
+        let message = RecorderMessage::Record {
+            symbol,
+            record: CaptureRecord { recv_time, payload },
+        };
+        if self.records.send(message).is_err() {
+            error!("Market data recorder stopped, dropping record");
+        }
+    }
+
+    /// Finishes every open capture and stops the recorder, later records are dropped
+    pub async fn finish(&self) {
+        let (done, finished) = oneshot::channel();
+        if self.records.send(RecorderMessage::Finish(done)).is_ok() {
+            let _ = finished.await;
+        }
+    }
+}
+
+/// Starts the recorder on a blocking thread, it finishes its files on `RecorderHandle::finish` or
+/// once every handle is dropped
+pub fn spawn_recorder(config: RecorderConfig) -> RecorderHandle {
+    let (records, mut receiver) = mpsc::unbounded_channel::<RecorderMessage>();
+    let mode = config.mode;
+
+    tokio::task::spawn_blocking(move || {
+        info!(
+            "Recording market data ({:?}) to {} | Compression: {:?}",
+            config.mode, config.dir.display(), config.zstd_level
+        );
+        let mut writers: HashMap<String, (NaiveDate, CaptureWriter)> = HashMap::new();
+
+        let mut finished = None;
+        while let Some(message) = receiver.blocking_recv() {
+            match message {
+                RecorderMessage::Record { symbol, record } => {
+                    if let Err(e) = write_record(&config, &mut writers, &symbol, &record) {
+                        error!("Failed to record market data: {}", e);
+                    }
+                }
+                RecorderMessage::Finish(done) => {
+                    finished = Some(done);
+                    break;
+                }
+            }
+        }
+
+        for (_, (_, writer)) in writers {
+            let path = writer.path().display().to_string();
+            if let Err(e) = writer.finish() {
+                error!("Failed to finish capture {}: {}", path, e);
+            }
+        }
+        info!("Market data recorder stopped");
+        if let Some(done) = finished {
+            let _ = done.send(());
+        }
+    });
+
+    RecorderHandle { mode, records }
+}
+
+// Files roll over per symbol when the UTC date of the receive time changes
+fn write_record(
+    config: &RecorderConfig,
+    writers: &mut HashMap<String, (NaiveDate, CaptureWriter)>,
+    symbol: &str,
+    record: &CaptureRecord,
+) -> anyhow::Result<()> {
+    let date = record.recv_time.date_naive();
+
+    let current_date = writers.get(symbol).map(|(date, _)| *date);
+    if current_date != Some(date) {
+        let mut streams = Vec::new();
+        if let Some((_, writer)) = writers.remove(symbol) {
+            streams = writer.streams().to_vec();
+            writer.finish()?;
+        }
+        let path = capture_path(&config.dir, symbol, date, config.zstd_level.is_some());
+        info!("Opening capture {}", path.display());
+        let mut writer = CaptureWriter::open(&path, config.zstd_level, config.index_interval_ms * 1_000_000)?;
+        // The streams stay open across the rollover
+        for (md_req_id, subscription) in streams {
+            writer.write(&CaptureRecord {
+                recv_time: record.recv_time,
+                payload: CapturePayload::Subscription {
+                    md_req_id,
+                    subscription: Some(subscription),
+                },
+            })?;
+        }
+        writers.insert(symbol.to_string(), (date, writer));
+    }
+
+    let (_, writer) = writers
+        .get_mut(symbol)
+        .ok_or_else(|| anyhow::anyhow!("No capture open for {}", symbol))?;
+    writer.write(record)
+}
@@ -0,0 +1,291 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Duration, Utc};
+use log::{debug, error, info};
+use tokio::time::Instant;
+
+use crate::market_data::capture::{CapturePayload, CaptureReader, CaptureRecord};
+use crate::market_data::market_data_event::MarketDataEvent;
+use crate::market_data::market_data_message::parse_md_message;
+use crate::market_data::pipeline::MarketDataPipeline;
+use crate::market_data::subscription::Subscription;
+use crate::market_data::symbol_registry::parse_instrument_list;
+use crate::execution::order_gateway::OrderRequest;
+use crate::strategy;
+use crate::types::StrategyState;
+use crate::utils::message_util::extract_field;
+
+// Same period as the live session's clock
+const CLOCK_TICK_MS: i64 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaySpeed {
+    /// Records are spaced as they were received
+    RealTime,
+    /// N times faster than real time
+    Multiple(f64),
+    /// No pacing at all
+    AsFastAsPossible,
+}
+
+impl ReplaySpeed {
+    /// Parses `realtime`, `max` or a factor such as `10` or `10x`
+    pub fn parse(speed: &str) -> anyhow::Result<Self> {
+        match speed.trim().to_ascii_lowercase().as_str() {
+            "realtime" | "1" | "1x" => Ok(Self::RealTime),
+            "max" => Ok(Self::AsFastAsPossible),
+            factor => {
+                let factor: f64 = factor
+                    .strip_suffix('x')
+                    .unwrap_or(factor)
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid replay speed '{}', expected realtime, max or e.g. 10x", speed))?;
+                if !factor.is_finite() || factor <= 0.0 {
+                    anyhow::bail!("Replay speed must be positive, got '{}'", speed);
+                }
+                Ok(Self::Multiple(factor))
+            }
+        }
+    }
+
+    fn factor(&self) -> Option<f64> {
+        match self {
+            Self::RealTime => Some(1.0),
+            Self::Multiple(factor) => Some(*factor),
+            Self::AsFastAsPossible => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ReplayConfig {
+    /// Capture files or directories holding them, e.g. the recorder's root directory
+    pub paths: Vec<PathBuf>,
+    pub speed: ReplaySpeed,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReplaySummary {
+    pub records: u64,
+    pub events: u64,
+    pub orders: u64,
+}
+
+fn is_capture(path: &Path) -> bool {
+    let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+    name.ends_with(".mdc") || name.ends_with(".mdc.zst")
+}
+
+/// Every capture file under `paths`, directories are searched recursively
+pub fn capture_files(paths: &[PathBuf]) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending: Vec<PathBuf> = paths.to_vec();
+    while let Some(path) = pending.pop() {
+        if path.is_dir() {
+            for entry in fs::read_dir(&path)? {
+                pending.push(entry?.path());
+            }
+        } else if is_capture(&path) {
+            files.push(path);
+        } else if paths.contains(&path) {
+            anyhow::bail!("{} is not a capture file", path.display());
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Merges several captures into one stream ordered by receive time
+struct MergedCaptures {
+    readers: Vec<CaptureReader>,
+    heads: Vec<Option<CaptureRecord>>,
+    // (receive time in nanos, reader), ties keep the file order
+    queue: BinaryHeap<Reverse<(i64, usize)>>,
+}
+
+impl MergedCaptures {
+    fn open(files: &[PathBuf], from: Option<DateTime<Utc>>) -> anyhow::Result<Self> {
+        let mut merged = Self {
+            readers: Vec::new(),
+            heads: Vec::new(),
+            queue: BinaryHeap::new(),
+        };
+        for file in files {
+            merged.readers.push(CaptureReader::open_at(file, from)?);
+            merged.heads.push(None);
+            merged.advance(merged.readers.len() - 1)?;
+        }
+        Ok(merged)
+    }
+
+    fn advance(&mut self, reader: usize) -> anyhow::Result<()> {
+        if let Some(record) = self.readers[reader].next().transpose()? {
+            let nanos = record.recv_time.timestamp_nanos_opt().unwrap_or(i64::MAX);
+            self.queue.push(Reverse((nanos, reader)));
+            self.heads[reader] = Some(record);
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for MergedCaptures {
+    type Item = anyhow::Result<CaptureRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Reverse((_, reader)) = self.queue.pop()?;
+        let record = self.heads[reader].take()?;
+        if let Err(e) = self.advance(reader) {
+            return Some(Err(e));
+        }
+        Some(Ok(record))
+    }
+}
+
+/// Feeds recorded market data through the pipeline and the strategy as if it arrived live.
+/// The strategy sees each record's receive time as the current time and its orders are only logged.
+/// Between records the pipeline gets the clock ticks of the live session in simulated time.
+pub async fn run_replay(
+    config: &ReplayConfig,
+    pipeline: &mut MarketDataPipeline,
+    state: &mut StrategyState,
+) -> anyhow::Result<ReplaySummary> {
+    let files = capture_files(&config.paths)?;
+    if files.is_empty() {
+        anyhow::bail!("No capture files found in {:?}", config.paths);
+    }
+    info!("Replaying {} capture files at {:?}", files.len(), config.speed);
+
+    let mut summary = ReplaySummary::default();
+    let mut clock: Option<(DateTime<Utc>, Instant)> = None;
+    let mut next_tick: Option<DateTime<Utc>> = None;
+    // MDReqIDs are never reused, so closed streams can stay mapped
+    let mut streams: HashMap<String, Subscription> = HashMap::new();
+
+    for record in MergedCaptures::open(&files, config.from)? {
+        let record = record?;
+        let now = record.recv_time;
+        let payload = match record.payload {
+            CapturePayload::Subscription { md_req_id, subscription } => {
+                if let Some(subscription) = subscription {
+                    streams.insert(md_req_id, subscription);
+                }
+                continue;
+            }
+            payload => payload,
+        };
+        if config.to.is_some_and(|to| now > to) {
+            break;
+        }
+        summary.records += 1;
+
+        // Bars and staleness follow simulated time, as the live clock follows the wall clock
+        let mut tick = *next_tick.get_or_insert(now + Duration::milliseconds(CLOCK_TICK_MS));
+        while tick <= now {
+            pace(config.speed, &mut clock, tick).await;
+            let events = pipeline.on_time(tick);
+            run_strategy(&events, state, tick, &mut summary);
+            tick += Duration::milliseconds(CLOCK_TICK_MS);
+        }
+        next_tick = Some(tick);
+
+        pace(config.speed, &mut clock, now).await;
+        let events = match payload {
+            CapturePayload::Raw(message) => replay_raw(&message, &streams, pipeline, now),
+            CapturePayload::Event(event) => pipeline.on_event(event, now),
+            CapturePayload::Subscription { .. } => Vec::new(),
+        };
+        run_strategy(&events, state, now, &mut summary);
+    }
+
+    info!(
+        "Replay finished | Records: {} | Events: {} | Simulated orders: {}",
+        summary.records, summary.events, summary.orders
+    );
+    Ok(summary)
+}
+
+// Waits until `time` is due at the replay speed, the first call starts the clock
+async fn pace(speed: ReplaySpeed, clock: &mut Option<(DateTime<Utc>, Instant)>, time: DateTime<Utc>) {
+    if let Some(factor) = speed.factor() {
+        let (start, wall_start) = *clock.get_or_insert((time, Instant::now()));
+        let elapsed = (time - start).to_std().unwrap_or_default();
+        tokio::time::sleep_until(wall_start + elapsed.div_f64(factor)).await;
+    }
+}
+
+fn run_strategy(events: &[MarketDataEvent], state: &mut StrategyState, now: DateTime<Utc>, summary: &mut ReplaySummary) {
+    for event in events {
+        summary.events += 1;
+        for action in strategy::on_market_data(event, state, now) {
+            summary.orders += 1;
+            log_action(&action, now);
+        }
+    }
+}
+
+// Handles the messages the live session acts on, everything else is skipped
+fn replay_raw(
+    message: &str,
+    streams: &HashMap<String, Subscription>,
+    pipeline: &mut MarketDataPipeline,
+    now: DateTime<Utc>,
+) -> Vec<MarketDataEvent> {
+    match extract_field(message, "35").as_deref() {
+        Some("X") | Some("W") => {
+            let md_message = match parse_md_message(message) {
+                Ok(md_message) => md_message,
+                Err(e) => {
+                    error!("Failed to parse recorded market data: {}", e);
+                    return Vec::new();
+                }
+            };
+            let req_id = md_message.md_req_id.clone().unwrap_or_default();
+            let Some(subscription) = streams.get(&req_id) else {
+                debug!("Skipping market data of MDReqID {:?}, no subscription recorded", req_id);
+                return Vec::new();
+            };
+            // The recording holds the fresh snapshot the live session resubscribed for
+            pipeline.on_md_message(&md_message, subscription, now)
+        }
+        Some("y") => match parse_instrument_list(message) {
+            Ok(list) => vec![MarketDataEvent::Instruments(list)],
+            Err(e) => {
+                error!("Failed to parse recorded InstrumentList: {}", e);
+                Vec::new()
+            }
+        },
+        Some("Y") => {
+            let subscription = extract_field(message, "262").and_then(|req_id| streams.get(&req_id));
+            match subscription {
+                Some(subscription) => pipeline.on_stream_closed(subscription, true, "subscription rejected"),
+                None => Vec::new(),
+            }
+        }
+        _ => Vec::new(),
+    }
+}
+
+fn log_action(action: &OrderRequest, now: DateTime<Utc>) {
+    match action {
+        OrderRequest::Cancel { symbol, orig_cl_ord_id, .. } => {
+            info!("[{}] Simulated cancel | Symbol: {} | OrigClOrdID: {}", now, symbol, orig_cl_ord_id);
+        }
+        OrderRequest::New(order) => {
+            info!(
+                "[{}] Simulated order | {} {} {} {} | ClOrdID: {}",
+                now, order.side, order.qty, order.symbol, order.ord_type, order.cl_ord_id
+            );
+        }
+        OrderRequest::Amend { orig_cl_ord_id, order, .. } => {
+            info!(
+                "[{}] Simulated amend | OrigClOrdID: {} | {} {} {} {} | ClOrdID: {}",
+                now, orig_cl_ord_id, order.side, order.qty, order.symbol, order.ord_type, order.cl_ord_id
+            );
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use std::collections::HashSet;
+use std::env;
+
+use serde::{Deserialize, Serialize};
+
+const DEFAULT_SUBSCRIPTIONS: &str = "BTCUSDT:book_ticker";
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum StreamKind {
+    /// Best bid and offer, MarketDepth = 1
+    BookTicker,
+    /// Diff depth stream with the given MarketDepth
+    Depth(u32),
+    Trades,
+}
+
+impl StreamKind {
+    pub fn is_book(&self) -> bool {
+        matches!(self, Self::BookTicker | Self::Depth(_))
+    }
+
+    // MDEntryTypes (269) requested for this stream
+    pub fn entry_types(&self) -> &'static [&'static str] {
+        match self {
+            Self::BookTicker | Self::Depth(_) => &["0", "1"], // BID and OFFER
+            Self::Trades => &["2"],                            // TRADE
+        }
+    }
+
+    // MarketDepth (264)
+    pub fn market_depth(&self) -> Option<i32> {
+        match self {
+            Self::BookTicker => Some(1),
+            Self::Depth(levels) => Some(*levels as i32),
+            Self::Trades => None,
+        }
+    }
+
+    fn req_id_tag(&self) -> String {
+        match self {
+            Self::BookTicker => "BOOK_TICKER".to_string(),
+            Self::Depth(levels) => format!("DEPTH{}", levels),
+            Self::Trades => "TRADES".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Subscription {
+    pub symbol: String,
+    pub kind: StreamKind,
+    /// AggregatedBook (266)
+    pub aggregated_book: bool,
+}
+
+impl Subscription {
+    /// Unique MDReqID, `generation` changes on every resubscription
+    pub fn md_req_id(&self, generation: u32) -> String {
+        format!("{}_{}_{}", self.symbol, self.kind.req_id_tag(), generation)
+    }
+
+    /// Parses `SYMBOL:KIND[:agg=Y|N]` where KIND is `book_ticker`, `depth=N` or `trades`
+    pub fn parse(spec: &str) -> anyhow::Result<Self> {
+        let mut parts = spec.split(':').map(str::trim);
+
+        let symbol = parts
+            .next()
+            .filter(|symbol| !symbol.is_empty())
+            .ok_or_else(|| anyhow::anyhow!("Subscription '{}' is missing a symbol", spec))?
+            .to_ascii_uppercase();
+
+        let kind = match parts.next().map(str::to_ascii_lowercase).as_deref() {
+            Some("book_ticker") | None => StreamKind::BookTicker,
+            Some("trades") => StreamKind::Trades,
+            Some(depth) if depth.starts_with("depth=") => {
+                let levels: u32 = depth["depth=".len()..]
+                    .parse()
+                    .map_err(|e| anyhow::anyhow!("Invalid depth in subscription '{}': {}", spec, e))?;
+                match levels {
+                    0 => anyhow::bail!("Depth must be at least 1 in subscription '{}'", spec),
+                    1 => StreamKind::BookTicker,
+                    levels => StreamKind::Depth(levels),
+                }
+            }
+            Some(other) => anyhow::bail!(
+                "Unknown stream kind '{}' in subscription '{}', expected book_ticker, depth=N or trades",
+                other, spec
+            ),
+        };
+
+        let mut aggregated_book = true;
+        for option in parts {
+            match option.to_ascii_lowercase().as_str() {
+                "agg=y" => aggregated_book = true,
+                "agg=n" => aggregated_book = false,
+                other => anyhow::bail!("Unknown option '{}' in subscription '{}'", other, spec),
+            }
+        }
+
+        Ok(Self {
+            symbol,
+            kind,
+            aggregated_book,
+        })
+    }
+}
+
+/// Reads BINANCE_MD_SUBSCRIPTIONS, a comma separated list of subscriptions,
+/// e.g. `BTCUSDT:book_ticker,ETHUSDT:depth=10:agg=N,BTCUSDT:trades`
+pub fn subscriptions_from_env() -> anyhow::Result<Vec<Subscription>> {
+    let value = env::var("BINANCE_MD_SUBSCRIPTIONS").unwrap_or_else(|_| DEFAULT_SUBSCRIPTIONS.to_string());
+
+    let subscriptions = value
+        .split(',')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+        .map(Subscription::parse)
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    // Books are kept per symbol, so a symbol can only have one book stream
+    let mut book_symbols = HashSet::new();
+    let mut seen = HashSet::new();
+    for subscription in &subscriptions {
+        if !seen.insert((subscription.symbol.clone(), subscription.kind)) {
+            anyhow::bail!("Duplicate subscription {:?} for {}", subscription.kind, subscription.symbol);
+        }
+        if subscription.kind.is_book() && !book_symbols.insert(subscription.symbol.clone()) {
+            anyhow::bail!("{} has more than one book subscription", subscription.symbol);
+        }
+    }
+
+    Ok(subscriptions)
+}
@@ -0,0 +1,304 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+
+use log::info;
+use tokio::sync::mpsc;
+
+use crate::market_data::subscription::Subscription;
+use crate::utils::config_util::session_var_parse;
+
+// Binance allows at most 1000 streams on one market data connection
+const DEFAULT_MAX_STREAMS: usize = 1000;
+// Rejected and unsubscribed requests kept for their state, the oldest are forgotten first
+const MAX_CLOSED_REQUESTS: usize = 1000;
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SubscriptionState {
+    /// MarketDataRequest sent, no data or reject yet
+    Pending,
+    /// At least one W or X received for the MDReqID
+    Active,
+    /// MarketDataRequestReject (Y) received
+    Rejected { reason: String },
+    /// Unsubscribe (263=2) sent, late updates are dropped
+    Unsubscribed,
+}
+
+impl SubscriptionState {
+    /// Pending and active requests count against the stream limit
+    pub fn is_live(&self) -> bool {
+        matches!(self, Self::Pending | Self::Active)
+    }
+}
+
+impl fmt::Display for SubscriptionState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pending => write!(f, "pending"),
+            Self::Active => write!(f, "active"),
+            Self::Rejected { reason } => write!(f, "rejected ({})", reason),
+            Self::Unsubscribed => write!(f, "unsubscribed"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TrackedSubscription {
+    pub subscription: Subscription,
+    pub state: SubscriptionState,
+}
+
+/// Tracks every MDReqID sent on a market data connection and its state. Only the last
+/// `MAX_CLOSED_REQUESTS` rejected or unsubscribed requests are kept.
+#[derive(Debug)]
+pub struct SubscriptionManager {
+    max_streams: usize,
+    /// Appended to every MDReqID so a resubscription never reuses one
+    generation: u32,
+    requests: HashMap<String, TrackedSubscription>,
+    /// Rejected and unsubscribed MDReqIDs, oldest first
+    closed: VecDeque<String>,
+}
+
+impl SubscriptionManager {
+    pub fn new(max_streams: usize) -> Self {
+        Self {
+            max_streams,
+            generation: 0,
+            requests: HashMap::new(),
+            closed: VecDeque::new(),
+        }
+    }
+
+    /// Reads BINANCE_MD_MAX_STREAMS, defaulting to the Binance limit of 1000
+    pub fn from_env() -> anyhow::Result<Self> {
+        let max_streams = session_var_parse("MD", "MAX_STREAMS")?.unwrap_or(DEFAULT_MAX_STREAMS);
+        if max_streams == 0 {
+            anyhow::bail!("BINANCE_MD_MAX_STREAMS must be at least 1");
+        }
+        Ok(Self::new(max_streams))
+    }
+
+    pub fn max_streams(&self) -> usize {
+        self.max_streams
+    }
+
+    pub fn open_streams(&self) -> usize {
+        self.requests.values().filter(|tracked| tracked.state.is_live()).count()
+    }
+
+    /// Registers a pending request and returns its MDReqID.
+    /// Fails for a duplicate stream, a second book stream for a symbol or when the limit is reached.
+    pub fn subscribe(&mut self, subscription: &Subscription) -> anyhow::Result<String> {
+        for tracked in self.requests.values().filter(|tracked| tracked.state.is_live()) {
+            let existing = &tracked.subscription;
+            if existing.symbol != subscription.symbol {
+                continue;
+            }
+            if existing.kind == subscription.kind {
+                anyhow::bail!("{} is already subscribed to {:?}", subscription.symbol, subscription.kind);
+            }
+            if existing.kind.is_book() && subscription.kind.is_book() {
+                anyhow::bail!("{} already has a book subscription ({:?})", subscription.symbol, existing.kind);
+            }
+        }
+
+        let open_streams = self.open_streams();
+        if open_streams >= self.max_streams {
+            anyhow::bail!(
+                "Stream limit reached ({}/{}), not subscribing {} {:?}",
+                open_streams, self.max_streams, subscription.symbol, subscription.kind
+            );
+        }
+
+        let req_id = subscription.md_req_id(self.generation);
+        self.generation += 1;
+        self.requests.insert(
+            req_id.clone(),
+            TrackedSubscription {
+                subscription: subscription.clone(),
+                state: SubscriptionState::Pending,
+            },
+        );
+        Ok(req_id)
+    }
+
+    /// Marks a live request unsubscribed and returns its subscription
+    pub fn unsubscribe(&mut self, req_id: &str) -> Option<Subscription> {
+        let tracked = self.requests.get_mut(req_id).filter(|tracked| tracked.state.is_live())?;
+        tracked.state = SubscriptionState::Unsubscribed;
+        let subscription = tracked.subscription.clone();
+        self.on_closed(req_id);
+        Some(subscription)
+    }
+
+    /// MDReqID of the live request for this symbol and stream kind
+    pub fn find_live(&self, subscription: &Subscription) -> Option<String> {
+        self.requests
+            .iter()
+            .find(|(_, tracked)| {
+                tracked.state.is_live()
+                    && tracked.subscription.symbol == subscription.symbol
+                    && tracked.subscription.kind == subscription.kind
+            })
+            .map(|(req_id, _)| req_id.clone())
+    }
+
+    /// Whether any live request is for the symbol
+    pub fn is_subscribed(&self, symbol: &str) -> bool {
+        self.requests
+            .values()
+            .any(|tracked| tracked.state.is_live() && tracked.subscription.symbol == symbol)
+    }
+
+    /// Called for every W or X. Activates a pending request and returns its subscription,
+    /// None for unknown, rejected or unsubscribed MDReqIDs.
+    pub fn on_data(&mut self, req_id: &str) -> Option<Subscription> {
+        let tracked = self.requests.get_mut(req_id)?;
+        match tracked.state {
+            SubscriptionState::Pending => {
+                info!("MDReqID {} active", req_id);
+                tracked.state = SubscriptionState::Active;
+            }
+            SubscriptionState::Active => {}
+            _ => return None,
+        }
+        Some(tracked.subscription.clone())
+    }
+
+    /// Records a MarketDataRequestReject, returns the subscription if the request was live
+    pub fn on_reject(&mut self, req_id: &str, reason: &str) -> Option<Subscription> {
+        let tracked = self.requests.get_mut(req_id)?;
+        let was_live = tracked.state.is_live();
+        tracked.state = SubscriptionState::Rejected { reason: reason.to_string() };
+        let subscription = was_live.then(|| tracked.subscription.clone());
+        if was_live {
+            self.on_closed(req_id);
+        }
+        subscription
+    }
+
+    // Keeps the state of a request that just closed, forgetting the oldest closed one past the limit
+    fn on_closed(&mut self, req_id: &str) {
+        self.closed.push_back(req_id.to_string());
+        while self.closed.len() > MAX_CLOSED_REQUESTS {
+            if let Some(oldest) = self.closed.pop_front() {
+                self.requests.remove(&oldest);
+            }
+        }
+    }
+
+    pub fn state(&self, req_id: &str) -> Option<&SubscriptionState> {
+        self.requests.get(req_id).map(|tracked| &tracked.state)
+    }
+
+    pub fn requests(&self) -> impl Iterator<Item = (&String, &TrackedSubscription)> {
+        self.requests.iter()
+    }
+}
+
+/// Readable reject reason from MDReqRejReason (281) and Text (58)
+pub fn reject_reason(code: Option<&str>, text: Option<&str>) -> String {
+    let code = match code {
+        Some("1") => Some("DUPLICATE_MDREQID".to_string()),
+        Some("2") => Some("TOO_MANY_SUBSCRIPTIONS".to_string()),
+        Some(other) => Some(format!("MDReqRejReason {}", other)),
+        None => None,
+    };
+    match (code, text) {
+        (Some(code), Some(text)) => format!("{}: {}", code, text),
+        (Some(code), None) => code,
+        (None, Some(text)) => text.to_string(),
+        (None, None) => "no reason given".to_string(),
+    }
+}
+
+/// Runtime subscription changes for the market data session
+#[derive(Debug, Clone)]
+pub enum SubscriptionCommand {
+    Subscribe(Subscription),
+    /// Matches the live request with the same symbol and stream kind
+    Unsubscribe(Subscription),
+}
+
+/// Sends subscription changes to a running market data session
+#[derive(Debug, Clone)]
+pub struct SubscriptionHandle {
+    commands: mpsc::UnboundedSender<SubscriptionCommand>,
+}
+
+impl SubscriptionHandle {
+    pub fn subscribe(&self, subscription: Subscription) -> anyhow::Result<()> {
+        self.send(SubscriptionCommand::Subscribe(subscription))
+    }
+
+    pub fn unsubscribe(&self, subscription: Subscription) -> anyhow::Result<()> {
+        self.send(SubscriptionCommand::Unsubscribe(subscription))
+    }
+
+    fn send(&self, command: SubscriptionCommand) -> anyhow::Result<()> {
+        self.commands
+            .send(command)
+            .map_err(|_| anyhow::anyhow!("Market data session is not running"))
+    }
+}
+
+pub fn subscription_channel() -> (SubscriptionHandle, mpsc::UnboundedReceiver<SubscriptionCommand>) {
+    let (commands, receiver) = mpsc::unbounded_channel();
+    (SubscriptionHandle { commands }, receiver)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn subscription(spec: &str) -> Subscription {
+        Subscription::parse(spec).unwrap()
+    }
+
+    #[test]
+    fn closed_requests_keep_their_state() {
+        let mut manager = SubscriptionManager::new(2);
+        let trades = manager.subscribe(&subscription("BTCUSDT:trades")).unwrap();
+        let book = manager.subscribe(&subscription("BTCUSDT:book_ticker")).unwrap();
+
+        assert!(manager.on_reject(&trades, "TOO_MANY_SUBSCRIPTIONS").is_some());
+        assert!(manager.unsubscribe(&book).is_some());
+        assert_eq!(
+            manager.state(&trades),
+            Some(&SubscriptionState::Rejected { reason: "TOO_MANY_SUBSCRIPTIONS".to_string() })
+        );
+        assert_eq!(manager.state(&book), Some(&SubscriptionState::Unsubscribed));
+        assert!(manager.on_data(&book).is_none());
+        assert!(!manager.is_subscribed("BTCUSDT"));
+    }
+
+    #[test]
+    fn only_live_requests_count_against_the_limit() {
+        let mut manager = SubscriptionManager::new(1);
+        let trades = manager.subscribe(&subscription("BTCUSDT:trades")).unwrap();
+        assert!(manager.subscribe(&subscription("ETHUSDT:trades")).is_err());
+
+        manager.on_data(&trades);
+        manager.unsubscribe(&trades);
+        assert_eq!(manager.open_streams(), 0);
+        let resubscribed = manager.subscribe(&subscription("BTCUSDT:trades")).unwrap();
+        assert_ne!(resubscribed, trades);
+        assert_eq!(manager.state(&resubscribed), Some(&SubscriptionState::Pending));
+    }
+
+    #[test]
+    fn oldest_closed_requests_are_pruned() {
+        let mut manager = SubscriptionManager::new(1);
+        let first = manager.subscribe(&subscription("BTCUSDT:trades")).unwrap();
+        manager.unsubscribe(&first);
+        for _ in 0..MAX_CLOSED_REQUESTS {
+            let req_id = manager.subscribe(&subscription("BTCUSDT:trades")).unwrap();
+            manager.unsubscribe(&req_id);
+        }
+
+        assert_eq!(manager.state(&first), None);
+        assert_eq!(manager.requests().count(), MAX_CLOSED_REQUESTS);
+    }
+}
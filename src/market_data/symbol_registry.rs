@@ -0,0 +1,378 @@
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::utils::decimal_util::{notional, Price, Qty, Rounding};
+use crate::utils::message_util::parse_fields;
+
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TradingStatus {
+    Trading,
+    Halted,
+    NotAvailable,
+    Other(String),
+}
+
+impl TradingStatus {
+    // Tag 326 SecurityTradingStatus
+    fn from_fix(value: &str) -> Self {
+        match value {
+            "17" | "3" => Self::Trading, // READY_TO_TRADE, RESUME
+            "2" => Self::Halted,
+            "18" => Self::NotAvailable,
+            other => Self::Other(other.to_string()),
+        }
+    }
+}
+
+/// Trading rules of one symbol from an InstrumentList (y)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SymbolFilters {
+    pub symbol: String,
+    /// Currency (15)
+    pub currency: Option<String>,
+    /// MinPriceIncrement (969)
+    pub tick_size: Option<Price>,
+    /// MinTradeVol (562)
+    pub min_qty: Option<Qty>,
+    /// MaxTradeVol (1140)
+    pub max_qty: Option<Qty>,
+    /// MinQtyIncrement (25039)
+    pub step_size: Option<Qty>,
+    /// MarketMinTradeVol (25040)
+    pub market_min_qty: Option<Qty>,
+    /// MarketMaxTradeVol (25041)
+    pub market_max_qty: Option<Qty>,
+    /// MarketMinQtyIncrement (25042)
+    pub market_step_size: Option<Qty>,
+    /// Not part of the Binance InstrumentList, set from BINANCE_MIN_NOTIONAL
+    pub min_notional: Option<Price>,
+    /// SecurityTradingStatus (326), None when not sent
+    pub trading_status: Option<TradingStatus>,
+}
+
+/// Why an order does not pass a symbol's filters
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterViolation {
+    UnknownSymbol(String),
+    NotTrading(TradingStatus),
+    PriceNotOnTick { price: Price, tick_size: Price },
+    QtyBelowMin { qty: Qty, min_qty: Qty },
+    QtyAboveMax { qty: Qty, max_qty: Qty },
+    QtyNotOnStep { qty: Qty, step_size: Qty },
+    BelowMinNotional { notional: Price, min_notional: Price },
+}
+
+impl fmt::Display for FilterViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownSymbol(symbol) => write!(f, "no trading rules loaded for {}", symbol),
+            Self::NotTrading(status) => write!(f, "symbol is not trading ({:?})", status),
+            Self::PriceNotOnTick { price, tick_size } => {
+                write!(f, "price {} is not a multiple of the tick size {}", price, tick_size)
+            }
+            Self::QtyBelowMin { qty, min_qty } => write!(f, "quantity {} is below the minimum {}", qty, min_qty),
+            Self::QtyAboveMax { qty, max_qty } => write!(f, "quantity {} is above the maximum {}", qty, max_qty),
+            Self::QtyNotOnStep { qty, step_size } => {
+                write!(f, "quantity {} is not a multiple of the step size {}", qty, step_size)
+            }
+            Self::BelowMinNotional { notional, min_notional } => {
+                write!(f, "notional {} is below the minimum {}", notional, min_notional)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FilterViolation {}
+
+impl SymbolFilters {
+    fn new(symbol: &str) -> Self {
+        Self {
+            symbol: symbol.to_string(),
+            currency: None,
+            tick_size: None,
+            min_qty: None,
+            max_qty: None,
+            step_size: None,
+            market_min_qty: None,
+            market_max_qty: None,
+            market_step_size: None,
+            min_notional: None,
+            trading_status: None,
+        }
+    }
+
+    /// Price on the tick size, unchanged when the tick size is unknown
+    pub fn round_price(&self, price: Price, rounding: Rounding) -> Price {
+        self.tick_size.map_or(price, |tick_size| price.round_to(tick_size, rounding))
+    }
+
+    /// Quantity on the step size, unchanged when the step size is unknown
+    pub fn round_qty(&self, qty: Qty, rounding: Rounding) -> Qty {
+        self.step_size.map_or(qty, |step_size| qty.round_to(step_size, rounding))
+    }
+
+    /// Symbols without a SecurityTradingStatus are assumed to be trading
+    pub fn is_trading(&self) -> bool {
+        matches!(self.trading_status, None | Some(TradingStatus::Trading))
+    }
+
+    pub fn check_limit_order(&self, price: Price, qty: Qty) -> Result<(), FilterViolation> {
+        self.check_trading()?;
+        if let Some(tick_size) = self.tick_size.filter(|tick| tick.is_positive()) {
+            if !price.is_multiple_of(tick_size) {
+                return Err(FilterViolation::PriceNotOnTick { price, tick_size });
+            }
+        }
+        check_qty(qty, self.min_qty, self.max_qty, self.step_size)?;
+        if let Some(min_notional) = self.min_notional {
+            let notional = notional(price, qty);
+            if notional < min_notional {
+                return Err(FilterViolation::BelowMinNotional { notional, min_notional });
+            }
+        }
+        Ok(())
+    }
+
+    /// Market orders use the market quantity filters, falling back to the limit ones
+    pub fn check_market_order(&self, qty: Qty) -> Result<(), FilterViolation> {
+        self.check_trading()?;
+        check_qty(
+            qty,
+            self.market_min_qty.or(self.min_qty),
+            self.market_max_qty.or(self.max_qty),
+            self.market_step_size.or(self.step_size),
+        )
+    }
+
+    fn check_trading(&self) -> Result<(), FilterViolation> {
+        match &self.trading_status {
+            Some(status) if !self.is_trading() => Err(FilterViolation::NotTrading(status.clone())),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn check_qty(
+    qty: Qty,
+    min_qty: Option<Qty>,
+    max_qty: Option<Qty>,
+    step_size: Option<Qty>,
+) -> Result<(), FilterViolation> {
+    if let Some(min_qty) = min_qty {
+        if qty < min_qty {
+            return Err(FilterViolation::QtyBelowMin { qty, min_qty });
+        }
+    }
+    if let Some(max_qty) = max_qty.filter(|max| max.is_positive()) {
+        if qty > max_qty {
+            return Err(FilterViolation::QtyAboveMax { qty, max_qty });
+        }
+    }
+    if let Some(step_size) = step_size.filter(|step| step.is_positive()) {
+        if !qty.is_multiple_of(step_size) {
+            return Err(FilterViolation::QtyNotOnStep { qty, step_size });
+        }
+    }
+    Ok(())
+}
+
+/// An InstrumentList (y), large lists arrive in several fragments
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstrumentList {
+    /// InstrumentReqID (320)
+    pub req_id: Option<String>,
+    /// LastFragment (893)
+    pub last_fragment: bool,
+    pub instruments: Vec<SymbolFilters>,
+}
+
+/// Parses the NoRelatedSym (146) group of an InstrumentList, every entry starts with Symbol (55)
+pub fn parse_instrument_list(message: &str) -> anyhow::Result<InstrumentList> {
+    let fields = parse_fields(message);
+
+    match fields.iter().find(|(tag, _)| *tag == "35") {
+        Some((_, "y")) => {}
+        other => anyhow::bail!("Not an InstrumentList: {:?}", other.map(|(_, value)| value)),
+    }
+
+    let mut list = InstrumentList {
+        req_id: None,
+        last_fragment: true,
+        instruments: Vec::new(),
+    };
+
+    let mut current: Option<SymbolFilters> = None;
+    for (tag, value) in fields {
+        if tag == "55" {
+            list.instruments.extend(current.take());
+            current = Some(SymbolFilters::new(value));
+            continue;
+        }
+
+        let Some(filters) = current.as_mut() else {
+            match tag {
+                "320" => list.req_id = Some(value.to_string()),
+                "893" => list.last_fragment = value == "Y",
+                _ => {}
+            }
+            continue;
+        };
+
+        match tag {
+            "15" => filters.currency = Some(value.to_string()),
+            "969" => filters.tick_size = parse_filter_value(&filters.symbol, tag, value)?,
+            "562" => filters.min_qty = parse_filter_value(&filters.symbol, tag, value)?,
+            "1140" => filters.max_qty = parse_filter_value(&filters.symbol, tag, value)?,
+            "25039" => filters.step_size = parse_filter_value(&filters.symbol, tag, value)?,
+            "25040" => filters.market_min_qty = parse_filter_value(&filters.symbol, tag, value)?,
+            "25041" => filters.market_max_qty = parse_filter_value(&filters.symbol, tag, value)?,
+            "25042" => filters.market_step_size = parse_filter_value(&filters.symbol, tag, value)?,
+            "326" => filters.trading_status = Some(TradingStatus::from_fix(value)),
+            "893" => list.last_fragment = value == "Y",
+            _ => {}
+        }
+    }
+    list.instruments.extend(current);
+
+    Ok(list)
+}
+
+fn parse_filter_value<T>(symbol: &str, tag: &str, value: &str) -> anyhow::Result<Option<T>>
+where
+    T: std::str::FromStr<Err = anyhow::Error>,
+{
+    value
+        .parse()
+        .map(Some)
+        .map_err(|e| anyhow::anyhow!("Invalid value '{}' for tag {} of {}: {}", value, tag, symbol, e))
+}
+
+/// Trading rules per symbol, refreshed from InstrumentList messages
+#[derive(Debug, Clone, Default)]
+pub struct SymbolRegistry {
+    symbols: HashMap<String, SymbolFilters>,
+    /// Set from BINANCE_MIN_NOTIONAL since InstrumentList does not carry it
+    min_notional: HashMap<String, Price>,
+    last_update: Option<DateTime<Utc>>,
+}
+
+impl SymbolRegistry {
+    /// Reads BINANCE_MIN_NOTIONAL, a comma separated list of `SYMBOL=VALUE`
+    pub fn from_env() -> anyhow::Result<Self> {
+        let mut registry = Self::default();
+
+        let value = env::var("BINANCE_MIN_NOTIONAL").unwrap_or_default();
+        for spec in value.split(',').map(str::trim).filter(|spec| !spec.is_empty()) {
+            let (symbol, min_notional) = spec
+                .split_once('=')
+                .ok_or_else(|| anyhow::anyhow!("Invalid BINANCE_MIN_NOTIONAL entry '{}', expected SYMBOL=VALUE", spec))?;
+            let min_notional: Price = min_notional
+                .trim()
+                .parse()
+                .map_err(|e| anyhow::anyhow!("Invalid min notional in '{}': {}", spec, e))?;
+            registry.min_notional.insert(symbol.trim().to_ascii_uppercase(), min_notional);
+        }
+
+        Ok(registry)
+    }
+
+    /// Replaces the rules of every symbol in the list, `now` is when the list was received
+    pub fn apply(&mut self, list: &InstrumentList, now: DateTime<Utc>) {
+        for instrument in &list.instruments {
+            let mut filters = instrument.clone();
+            if let Some(min_notional) = self.min_notional.get(&filters.symbol) {
+                filters.min_notional = Some(*min_notional);
+            }
+            self.symbols.insert(filters.symbol.clone(), filters);
+        }
+        self.last_update = Some(now);
+    }
+
+    pub fn get(&self, symbol: &str) -> Option<&SymbolFilters> {
+        self.symbols.get(symbol)
+    }
+
+    /// False for unknown symbols
+    pub fn is_trading(&self, symbol: &str) -> bool {
+        self.get(symbol).is_some_and(SymbolFilters::is_trading)
+    }
+
+    pub fn check_limit_order(&self, symbol: &str, price: Price, qty: Qty) -> Result<(), FilterViolation> {
+        self.get(symbol)
+            .ok_or_else(|| FilterViolation::UnknownSymbol(symbol.to_string()))?
+            .check_limit_order(price, qty)
+    }
+
+    pub fn check_market_order(&self, symbol: &str, qty: Qty) -> Result<(), FilterViolation> {
+        self.get(symbol)
+            .ok_or_else(|| FilterViolation::UnknownSymbol(symbol.to_string()))?
+            .check_market_order(qty)
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    /// When the last InstrumentList was applied
+    pub fn last_update(&self) -> Option<DateTime<Utc>> {
+        self.last_update
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(fields: &str) -> String {
+        format!("8=FIX.4.4|{}|", fields).replace('|', "\x01")
+    }
+
+    #[test]
+    fn parses_every_group_of_an_instrument_list() {
+        let list = parse_instrument_list(&message(concat!(
+            "35=y|320=instruments-1|893=N|146=2|",
+            "55=BTCUSDT|15=USDT|969=0.01|562=0.00001|1140=9000|25039=0.00001|25040=0.00001|25041=100|25042=0.00001|326=17|",
+            "55=ETHUSDT|15=USDT|969=0.01|562=0.0001|25039=0.0001|326=2"
+        )))
+        .unwrap();
+
+        assert_eq!(list.req_id.as_deref(), Some("instruments-1"));
+        assert!(!list.last_fragment);
+        assert_eq!(list.instruments.len(), 2);
+
+        let btc = &list.instruments[0];
+        assert_eq!(btc.symbol, "BTCUSDT");
+        assert_eq!(btc.currency.as_deref(), Some("USDT"));
+        assert_eq!(btc.tick_size, Some("0.01".parse().unwrap()));
+        assert_eq!(btc.max_qty, Some("9000".parse().unwrap()));
+        assert_eq!(btc.market_max_qty, Some("100".parse().unwrap()));
+        assert_eq!(btc.trading_status, Some(TradingStatus::Trading));
+
+        // Fields of the second group never leak into the first
+        let eth = &list.instruments[1];
+        assert_eq!(eth.symbol, "ETHUSDT");
+        assert_eq!(eth.min_qty, Some("0.0001".parse().unwrap()));
+        assert_eq!(eth.max_qty, None);
+        assert_eq!(eth.trading_status, Some(TradingStatus::Halted));
+    }
+
+    #[test]
+    fn apply_uses_the_receive_time() {
+        let list = parse_instrument_list(&message("35=y|146=1|55=BTCUSDT|969=0.01")).unwrap();
+        let received = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let mut registry = SymbolRegistry::default();
+        registry.apply(&list, received);
+
+        assert_eq!(registry.last_update(), Some(received));
+        assert!(registry.get("BTCUSDT").is_some());
+    }
+}
@@ -0,0 +1,161 @@
+use chrono::{DateTime, Duration, Utc};
+
+use crate::market_data::order_book::PriceLevel;
+use crate::utils::decimal_util::{Price, Rounding};
+
+
+/// Best bid and ask of one symbol with the analytics strategies share
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TopOfBook {
+    pub symbol: String,
+    pub bid: Option<PriceLevel>,
+    pub ask: Option<PriceLevel>,
+    /// From the SymbolRegistry, None until the InstrumentList arrived
+    pub tick_size: Option<Price>,
+    /// Last time the bid price or size changed
+    pub bid_changed_at: Option<DateTime<Utc>>,
+    pub ask_changed_at: Option<DateTime<Utc>>,
+}
+
+impl TopOfBook {
+    pub fn new(symbol: &str) -> Self {
+        Self {
+            symbol: symbol.to_string(),
+            bid: None,
+            ask: None,
+            tick_size: None,
+            bid_changed_at: None,
+            ask_changed_at: None,
+        }
+    }
+
+    /// Records the current best levels, `now` is the receive time of the update
+    pub fn update(&mut self, bid: Option<PriceLevel>, ask: Option<PriceLevel>, now: DateTime<Utc>) {
+        if bid != self.bid {
+            self.bid = bid;
+            self.bid_changed_at = Some(now);
+        }
+        if ask != self.ask {
+            self.ask = ask;
+            self.ask_changed_at = Some(now);
+        }
+    }
+
+    pub fn mid(&self) -> Option<Price> {
+        let (bid, ask) = self.prices()?;
+        Some((bid + ask).mul_ratio(1, 2, Rounding::Nearest))
+    }
+
+    pub fn spread(&self) -> Option<Price> {
+        let (bid, ask) = self.prices()?;
+        Some(ask - bid)
+    }
+
+    pub fn spread_ticks(&self) -> Option<f64> {
+        let tick_size = self.tick_size.filter(|tick| tick.is_positive())?;
+        Some(self.spread()?.units() as f64 / tick_size.units() as f64)
+    }
+
+    /// Spread relative to the mid in basis points
+    pub fn spread_bps(&self) -> Option<f64> {
+        let mid = self.mid().filter(|mid| mid.is_positive())?;
+        Some(self.spread()?.units() as f64 / mid.units() as f64 * 10_000.0)
+    }
+
+    /// Mid weighted towards the side with less size: (bid × ask size + ask × bid size) / total size
+    pub fn microprice(&self) -> Option<Price> {
+        let (bid, ask) = (self.bid?, self.ask?);
+        let total = bid.size.units() as i128 + ask.size.units() as i128;
+        if total <= 0 {
+            return self.mid();
+        }
+        let weighted = bid.price.units() as i128 * ask.size.units() as i128
+            + ask.price.units() as i128 * bid.size.units() as i128;
+        Some(Price::from_units(((weighted + total / 2) / total) as i64))
+    }
+
+    /// (bid size - ask size) / (bid size + ask size), from -1 (all ask) to 1 (all bid)
+    pub fn imbalance(&self) -> Option<f64> {
+        let (bid, ask) = (self.bid?, self.ask?);
+        let total = bid.size.units() as f64 + ask.size.units() as f64;
+        (total > 0.0).then(|| (bid.size.units() as f64 - ask.size.units() as f64) / total)
+    }
+
+    pub fn bid_age(&self, now: DateTime<Utc>) -> Option<Duration> {
+        self.bid_changed_at.map(|changed| now - changed)
+    }
+
+    pub fn ask_age(&self, now: DateTime<Utc>) -> Option<Duration> {
+        self.ask_changed_at.map(|changed| now - changed)
+    }
+
+    fn prices(&self) -> Option<(Price, Price)> {
+        Some((self.bid?.price, self.ask?.price))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level(price: &str, size: &str) -> Option<PriceLevel> {
+        Some(PriceLevel {
+            price: price.parse().unwrap(),
+            size: size.parse().unwrap(),
+        })
+    }
+
+    fn top(bid: Option<PriceLevel>, ask: Option<PriceLevel>) -> TopOfBook {
+        let mut top = TopOfBook::new("BTCUSDT");
+        top.tick_size = Some("0.01".parse().unwrap());
+        top.update(bid, ask, Utc::now());
+        top
+    }
+
+    fn price(value: &str) -> Price {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn two_sided_analytics() {
+        let top = top(level("100", "3"), level("100.1", "1"));
+        assert_eq!(top.mid(), Some(price("100.05")));
+        assert_eq!(top.spread(), Some(price("0.1")));
+        assert!((top.spread_ticks().unwrap() - 10.0).abs() < 1e-9);
+        assert!((top.spread_bps().unwrap() - 0.1 / 100.05 * 10_000.0).abs() < 1e-9);
+        // Most size on the bid pulls the microprice towards the ask
+        assert_eq!(top.microprice(), Some(price("100.075")));
+        assert_eq!(top.imbalance(), Some(0.5));
+    }
+
+    #[test]
+    fn one_sided_book_has_no_analytics() {
+        for top in [top(level("100", "1"), None), top(None, level("101", "1")), top(None, None)] {
+            assert_eq!(top.mid(), None);
+            assert_eq!(top.spread_ticks(), None);
+            assert_eq!(top.spread_bps(), None);
+            assert_eq!(top.microprice(), None);
+            assert_eq!(top.imbalance(), None);
+        }
+    }
+
+    #[test]
+    fn zero_sizes_fall_back_to_the_mid() {
+        let empty = top(level("100", "0"), level("101", "0"));
+        assert_eq!(empty.microprice(), Some(price("100.5")));
+        assert_eq!(empty.imbalance(), None);
+
+        let bid_only = top(level("100", "2"), level("101", "0"));
+        assert_eq!(bid_only.microprice(), Some(price("101")));
+        assert_eq!(bid_only.imbalance(), Some(1.0));
+    }
+
+    #[test]
+    fn spread_ticks_needs_a_tick_size() {
+        let mut top = top(level("100", "1"), level("100.1", "1"));
+        top.tick_size = None;
+        assert_eq!(top.spread_ticks(), None);
+        top.tick_size = Some(Price::ZERO);
+        assert_eq!(top.spread_ticks(), None);
+    }
+}
@@ -0,0 +1,275 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::execution::execution_report::Fill;
+use crate::execution::order_gateway::{NewOrder, OrderError, OrderGateway, OrderRequest};
+use crate::execution::order_types::Side;
+use crate::market_data::freshness_monitor::FreshnessChange;
+use crate::market_data::market_data_bus::BusSubscriber;
+use crate::market_data::market_data_event::{BookUpdate, MarketDataEvent};
+use crate::market_data::symbol_registry::InstrumentList;
+use crate::market_data::top_of_book::TopOfBook;
+use crate::types::StrategyState;
+use crate::utils::decimal_util::{Price, Qty, Rounding};
+
+const ORDER_QTY: Qty = Qty::new(1, 4);
+
+
+// Price and quantity on the symbol's tick and step, None when the order would break its trading rules
+fn order_terms(state: &StrategyState, symbol: &str, side: Side, price: Price, rounding: Rounding) -> Option<(Price, Qty)> {
+    let Some(filters) = state.symbols.get(symbol) else {
+        log::warn!("⚠️ Skipping {} {} @ {}: no trading rules loaded", side, symbol, price);
+        return None;
+    };
+
+    let price = filters.round_price(price, rounding);
+    let qty = filters.round_qty(ORDER_QTY, Rounding::Down);
+    match filters.check_limit_order(price, qty) {
+        Ok(()) => Some((price, qty)),
+        Err(violation) => {
+            log::warn!("⚠️ Skipping {} {} {} @ {}: {}", side, qty, symbol, price, violation);
+            None
+        }
+    }
+}
+
+// Cancels the active order, then replaces it on the other side
+fn flip_position(state: &mut StrategyState, symbol: &str, side: Side, price: Price, qty: Qty) -> Vec<OrderRequest> {
+    let order = match NewOrder::limit(symbol, side, qty, price) {
+        Ok(order) => order,
+        Err(e) => {
+            log::warn!("⚠️ Skipping {} {} {} @ {}: {}", side, qty, symbol, price, e);
+            return Vec::new();
+        }
+    };
+
+    let mut actions = Vec::new();
+    if let Some(orig_cl_ord_id) = state.active_order_id.take() {
+        actions.push(OrderRequest::Cancel {
+            symbol: symbol.to_string(),
+            cl_ord_id: Uuid::new_v4().to_string(),
+            orig_cl_ord_id,
+        });
+    }
+
+    state.active_order_id = Some(order.cl_ord_id.clone());
+    state.active_order_symbol = Some(symbol.to_string());
+    state.side = Some(side);
+    actions.push(OrderRequest::New(order));
+    actions
+}
+
+// Optionally cancels the resting order of a symbol that went stale
+fn on_freshness_change(change: &FreshnessChange, state: &mut StrategyState) -> Vec<OrderRequest> {
+    if !change.stale {
+        state.stale_symbols.remove(&change.symbol);
+        log::info!("✅ Market data for {} is fresh again, trading resumed", change.symbol);
+        return Vec::new();
+    }
+    state.stale_symbols.insert(change.symbol.clone());
+    log::warn!(
+        "⚠️ Market data for {} is stale, last update at {}, trading paused",
+        change.symbol, change.last_data
+    );
+
+    let resting_here = state.active_order_symbol.as_deref() == Some(change.symbol.as_str());
+    if !state.cancel_on_stale || !resting_here {
+        return Vec::new();
+    }
+    let Some(orig_cl_ord_id) = state.active_order_id.take() else {
+        return Vec::new();
+    };
+    state.active_order_symbol = None;
+    vec![OrderRequest::Cancel {
+        symbol: change.symbol.clone(),
+        cl_ord_id: Uuid::new_v4().to_string(),
+        orig_cl_ord_id,
+    }]
+}
+
+/// Called by the order entry session for each new fill of our orders, after the order manager
+/// and the position tracker took it in
+pub fn on_fill(fill: &Fill, state: &mut StrategyState) {
+    let position = state.positions.get(&fill.symbol).cloned().unwrap_or_default();
+    log::info!(
+        "💰 Fill | {} {} {} @ {} | {:?} | Commission: {} {} | Position: {} @ {:?} | Realized: {}",
+        fill.side, fill.qty, fill.symbol, fill.price, fill.liquidity,
+        fill.commission, fill.commission_asset.as_deref().unwrap_or(""),
+        position.qty, position.avg_px, position.realized_pnl
+    );
+
+    // A filled order no longer needs cancelling when the strategy flips
+    let active_done = state.active_order_id.as_deref() == Some(fill.cl_ord_id.as_str())
+        && state.orders.get(&fill.cl_ord_id).is_some_and(|order| !order.is_open());
+    if active_done {
+        state.active_order_id = None;
+        state.active_order_symbol = None;
+    }
+}
+
+// Forgets a post-only quote the exchange refused, the next book update quotes again.
+// The strategy only sends plain limit orders so far, this is for quoting with NewOrder::post_only.
+fn on_would_cross(cl_ord_id: &str, state: &mut StrategyState) {
+    if state.active_order_id.as_deref() == Some(cl_ord_id) {
+        state.active_order_id = None;
+        state.active_order_symbol = None;
+        state.side = None;
+    }
+}
+
+// Keeps the top of book in step with the book events, it is never ahead of the event being handled
+fn update_top_of_book(update: &BookUpdate, state: &mut StrategyState, now: DateTime<Utc>) {
+    let top = state
+        .top_of_book
+        .entry(update.symbol.clone())
+        .or_insert_with(|| TopOfBook::new(&update.symbol));
+    top.tick_size = state.symbols.get(&update.symbol).and_then(|filters| filters.tick_size);
+    top.update(update.best_bid, update.best_ask, now);
+}
+
+fn on_instrument_list(list: &InstrumentList, state: &mut StrategyState, now: DateTime<Utc>) {
+    state.symbols.apply(list, now);
+    if list.last_fragment {
+        log::info!(
+            "InstrumentList {:?} received, trading rules for {} symbols",
+            list.req_id, state.symbols.len()
+        );
+    }
+}
+
+/// Runs the strategy on one market data event and returns the orders to send, replays only log them.
+/// `now` is the receive time of the event, the simulated time during a replay, so timer logic
+/// never reads the wall clock.
+pub fn on_market_data(event: &MarketDataEvent, state: &mut StrategyState, now: DateTime<Utc>) -> Vec<OrderRequest> {
+    let update = match event {
+        MarketDataEvent::Trade(trade) => {
+            log::info!(
+                "Trade | Symbol: {} | Price: {} | Qty: {} | Aggressor: {:?} | TradeID: {:?}",
+                trade.symbol, trade.price, trade.qty, trade.aggressor_side, trade.trade_id
+            );
+            return Vec::new();
+        }
+        MarketDataEvent::Bar(bar) => {
+            log::info!(
+                "Bar {} {} | {} - {} | O: {} H: {} L: {} C: {} | Volume: {} | Trades: {}",
+                bar.symbol, bar.spec, bar.open_time, bar.close_time,
+                bar.open, bar.high, bar.low, bar.close, bar.volume, bar.trade_count
+            );
+            return Vec::new();
+        }
+        MarketDataEvent::Freshness(change) => return on_freshness_change(change, state),
+        MarketDataEvent::BookUnsynced { symbol, reason } => {
+            state.top_of_book.remove(symbol);
+            log::warn!("⚠️ Book {} unsynced ({}), trading paused until its next snapshot", symbol, reason);
+            return Vec::new();
+        }
+        MarketDataEvent::BookSynced { symbol } => {
+            log::info!("✅ Book {} synced", symbol);
+            return Vec::new();
+        }
+        MarketDataEvent::Instruments(list) => {
+            on_instrument_list(list, state, now);
+            return Vec::new();
+        }
+        MarketDataEvent::Book(update) => {
+            update_top_of_book(update, state, now);
+            update
+        }
+    };
+    let symbol = update.symbol.clone();
+
+    // An unsynced book has no top of book, both sides are needed to trade
+    let book_ready = match state.top_of_book.get(&symbol) {
+        Some(top) => {
+            log::info!(
+                "MarketData | Symbol: {} | Bid: {:?} | Ask: {:?} | Mid: {:?} | Spread: {:.2} bps | Microprice: {:?} | Imbalance: {:.3} | Bid age: {:?}",
+                symbol,
+                update.best_bid,
+                update.best_ask,
+                top.mid(),
+                top.spread_bps().unwrap_or(f64::NAN),
+                top.microprice(),
+                top.imbalance().unwrap_or(f64::NAN),
+                top.bid_age(now).map(|age| age.num_milliseconds())
+            );
+            top.bid.is_some() && top.ask.is_some()
+        }
+        None => {
+            log::info!(
+                "MarketData | Symbol: {} | Bid: {:?} | Ask: {:?}",
+                symbol, update.best_bid, update.best_ask
+            );
+            false
+        }
+    };
+
+    if !state.oe_logon_ready {
+        log::info!("⚠️ Order entry session not ready yet.");
+        return Vec::new();
+    }
+
+    if !book_ready {
+        log::info!("⚠️ Book {} is unsynced, waiting for recovery.", symbol);
+        return Vec::new();
+    }
+
+    if state.stale_symbols.contains(&symbol) {
+        log::info!("⚠️ Market data for {} is not fresh, no orders.", symbol);
+        return Vec::new();
+    }
+
+    let reference_price = state.reference_price;
+    let buy_threshold = reference_price.mul_ratio(99, 100, Rounding::Down);
+    let sell_threshold = reference_price.mul_ratio(101, 100, Rounding::Up);
+    let mut actions = Vec::new();
+
+    // Never sell below the bid or buy above the ask when rounding onto the tick
+    let sell_signal = update
+        .best_bid
+        .filter(|bid| bid.price > sell_threshold && state.side != Some(Side::Sell))
+        .and_then(|bid| order_terms(state, &symbol, Side::Sell, bid.price, Rounding::Up));
+    if let Some((price, qty)) = sell_signal {
+        actions.extend(flip_position(state, &symbol, Side::Sell, price, qty));
+        log::info!("📈 Strategy Signal - SELL @ {} | Qty: {} | Symbol: {}", price, qty, symbol);
+    }
+
+    let buy_signal = update
+        .best_ask
+        .filter(|ask| ask.price < buy_threshold && state.side != Some(Side::Buy))
+        .and_then(|ask| order_terms(state, &symbol, Side::Buy, ask.price, Rounding::Down));
+    if let Some((price, qty)) = buy_signal {
+        actions.extend(flip_position(state, &symbol, Side::Buy, price, qty));
+        log::info!("📉 Strategy Signal - BUY @ {} | Qty: {} | Symbol: {}", price, qty, symbol);
+    }
+
+    actions
+}
+
+/// Runs the strategy on every event of its bus subscription until the bus is gone,
+/// its orders go out through the order entry session
+pub async fn run_strategy(mut events: BusSubscriber, state: Arc<Mutex<StrategyState>>, orders: OrderGateway) {
+    while let Some(bus_event) = events.recv().await {
+        let requests = on_market_data(&bus_event.event, &mut *state.lock().await, bus_event.recv_time);
+        for request in requests {
+            let ticket = orders.send(request);
+            let state = Arc::clone(&state);
+            tokio::spawn(async move {
+                let cl_ord_id = ticket.cl_ord_id().to_string();
+                match ticket.await {
+                    Ok(ack) => log::info!(
+                        "Order acknowledged | ClOrdID: {} | OrderID: {:?} | ExecType: {}",
+                        cl_ord_id, ack.order_id, ack.exec_type
+                    ),
+                    Err(OrderError::WouldCross(reason)) => {
+                        log::warn!("⚠️ Post-only order {} would have crossed ({}), re-quoting", cl_ord_id, reason);
+                        on_would_cross(&cl_ord_id, &mut *state.lock().await);
+                    }
+                    Err(e) => log::error!("Order {} failed: {}", cl_ord_id, e),
+                }
+            });
+        }
+    }
+}
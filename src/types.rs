@@ -1,6 +1,34 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::execution::order_manager::OrderManager;
+use crate::execution::order_types::Side;
+use crate::execution::position_tracker::PositionTracker;
+use crate::market_data::subscription_manager::SubscriptionHandle;
+use crate::market_data::symbol_registry::SymbolRegistry;
+use crate::market_data::top_of_book::TopOfBook;
+use crate::utils::decimal_util::Price;
+
 pub struct StrategyState {
-    pub reference_price: f64,
+    pub reference_price: Price,
     pub active_order_id: Option<String>,
-    pub side: Option<String>,
+    /// Symbol of the active order
+    pub active_order_symbol: Option<String>,
+    pub side: Option<Side>,
     pub oe_logon_ready: bool,
+    /// Every order of the session and its lifecycle state, from the ExecutionReports
+    pub orders: OrderManager,
+    /// Net position per symbol from our fills
+    pub positions: PositionTracker,
+    /// Mid, spread, microprice and imbalance per book symbol, updated with every book event.
+    /// Unsynced books have none.
+    pub top_of_book: HashMap<String, TopOfBook>,
+    /// Symbols whose market data is stale or settling. The order entry session refuses their new
+    /// orders and amends with `OrderError::StaleMarketData`.
+    pub stale_symbols: HashSet<String>,
+    /// Cancel the resting order of a symbol when it goes stale
+    pub cancel_on_stale: bool,
+    /// Trading rules per symbol from the InstrumentList
+    pub symbols: SymbolRegistry,
+    /// Subscribe or unsubscribe market data streams at runtime
+    pub md_subscriptions: SubscriptionHandle,
 }
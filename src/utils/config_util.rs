@@ -0,0 +1,35 @@
+use std::env;
+use std::str::FromStr;
+
+
+// Looks up BINANCE_<SESSION>_<NAME>, falling back to the shared BINANCE_<NAME>
+pub fn session_var(session: &str, name: &str) -> Option<String> {
+    env::var(format!("BINANCE_{}_{}", session, name))
+        .or_else(|_| env::var(format!("BINANCE_{}", name)))
+        .ok()
+        .filter(|value| !value.trim().is_empty())
+}
+
+pub fn session_var_parse<T>(session: &str, name: &str) -> anyhow::Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    match session_var(session, name) {
+        Some(value) => value.trim().parse::<T>().map(Some).map_err(|e| {
+            anyhow::anyhow!("Invalid value '{}' for BINANCE_{}_{}: {}", value, session, name, e)
+        }),
+        None => Ok(None),
+    }
+}
+
+pub fn session_var_bool(session: &str, name: &str) -> anyhow::Result<Option<bool>> {
+    match session_var(session, name) {
+        Some(value) => match value.trim().to_ascii_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => Ok(Some(true)),
+            "0" | "false" | "no" | "off" => Ok(Some(false)),
+            _ => anyhow::bail!("Invalid boolean '{}' for BINANCE_{}_{}", value, session, name),
+        },
+        None => Ok(None),
+    }
+}
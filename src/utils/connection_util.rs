@@ -1,19 +1,135 @@
+use std::time::Duration;
+use socket2::{SockRef, TcpKeepalive};
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpStream;
 use tokio_util::codec::Framed;
-use tokio_native_tls::TlsStream;
-use tokio_native_tls::TlsConnector;
 
+use crate::utils::config_util::{session_var, session_var_bool, session_var_parse};
 use crate::utils::fix_util::FixCodec;
+use crate::utils::proxy_util::{connect_via_proxy, ProxyConfig};
+use crate::utils::tls_util::{tls_handshake, TlsConfig};
 
+pub trait FixTransport: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> FixTransport for T {}
+
+pub type FixConnection = Framed<Box<dyn FixTransport>, FixCodec>;
+
+const DEFAULT_TLS_PORT: u16 = 9000;
+const DEFAULT_CONNECT_TIMEOUT_MS: u64 = 10_000;
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionMode {
+    /// TLS to the endpoint, SNI and certificate name taken from the hostname
+    Tls,
+    /// Plain TCP, e.g. to a local stunnel or a test acceptor
+    Tcp,
+    /// TLS with the SNI and certificate name overridden
+    TlsWithSni(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct ConnectionConfig {
+    pub hostname: String,
+    pub port: u16,
+    pub mode: ConnectionMode,
+    pub connect_timeout: Duration,
+    pub tcp_nodelay: bool,
+    pub tcp_keepalive: Option<Duration>,
+    pub proxy: Option<ProxyConfig>,
+    pub tls: TlsConfig,
+}
+
+impl ConnectionConfig {
+    /// Reads BINANCE_<SESSION>_* settings, e.g. `from_env("MD")` or `from_env("OE")`
+    pub fn from_env(session: &str) -> anyhow::Result<Self> {
+        let hostname = session_var(session, "HOSTNAME")
+            .ok_or_else(|| anyhow::anyhow!("BINANCE_{}_HOSTNAME is not set", session))?;
+
+        let mode = match session_var(session, "CONNECTION_MODE").as_deref().map(str::to_ascii_lowercase).as_deref() {
+            None | Some("tls") => match session_var(session, "TLS_SNI") {
+                Some(sni) => ConnectionMode::TlsWithSni(sni),
+                None => ConnectionMode::Tls,
+            },
+            Some("tcp") => ConnectionMode::Tcp,
+            Some(other) => anyhow::bail!(
+                "Invalid BINANCE_{}_CONNECTION_MODE '{}', expected 'tls' or 'tcp'",
+                session, other
+            ),
+        };
+
+        let port = match session_var_parse::<u16>(session, "PORT")? {
+            Some(0) => anyhow::bail!("BINANCE_{}_PORT must not be 0", session),
+            Some(port) => port,
+            None if mode != ConnectionMode::Tcp => DEFAULT_TLS_PORT,
+            None => anyhow::bail!("BINANCE_{}_PORT is required in tcp mode", session),
+        };
+
+        let connect_timeout = Duration::from_millis(
+            session_var_parse(session, "CONNECT_TIMEOUT_MS")?.unwrap_or(DEFAULT_CONNECT_TIMEOUT_MS),
+        );
+        let tcp_nodelay = session_var_bool(session, "TCP_NODELAY")?.unwrap_or(true);
+        let tcp_keepalive = session_var_parse::<u64>(session, "TCP_KEEPALIVE_SECS")?
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs);
+
+        let proxy = session_var(session, "PROXY")
+            .map(|value| ProxyConfig::parse(&value))
+            .transpose()
+            .map_err(|e| anyhow::anyhow!("BINANCE_{}_PROXY: {}", session, e))?;
+
+        Ok(Self {
+            hostname,
+            port,
+            mode,
+            connect_timeout,
+            tcp_nodelay,
+            tcp_keepalive,
+            proxy,
+            tls: TlsConfig::from_env(session)?,
+        })
+    }
+}
+
+pub async fn connect_fix_endpoint(config: &ConnectionConfig) -> anyhow::Result<FixConnection> {
+    let stream = tokio::time::timeout(config.connect_timeout, open_transport(config))
+        .await
+        .map_err(|_| {
+            anyhow::anyhow!(
+                "Timed out connecting to {}:{} after {:?}",
+                config.hostname, config.port, config.connect_timeout
+            )
+        })??;
 
-pub async fn connect_fix_endpoint(
-    hostname: &str, port: u16,
-) -> anyhow::Result<Framed<TlsStream<TcpStream>, FixCodec>> {
-    let port = if port == 0 { 9000 } else { port };
-    let addr = format!("{}:{}", hostname, port);
-    let tcp = TcpStream::connect(addr).await?;
-    let connector = native_tls::TlsConnector::builder().build()?;
-    let tls = TlsConnector::from(connector);
-    let stream = tls.connect(hostname, tcp).await?;
     Ok(Framed::new(stream, FixCodec))
 }
+
+async fn open_transport(config: &ConnectionConfig) -> anyhow::Result<Box<dyn FixTransport>> {
+    let tcp = match &config.proxy {
+        Some(proxy) => connect_via_proxy(proxy, &config.hostname, config.port).await?,
+        None => TcpStream::connect((config.hostname.as_str(), config.port))
+            .await
+            .map_err(|e| anyhow::anyhow!("Exchange {}:{} unreachable: {}", config.hostname, config.port, e))?,
+    };
+    configure_socket(&tcp, config)?;
+
+    let server_name = match &config.mode {
+        ConnectionMode::Tcp => return Ok(Box::new(tcp)),
+        ConnectionMode::Tls => &config.hostname,
+        ConnectionMode::TlsWithSni(sni) => sni,
+    };
+    let stream = tls_handshake(server_name, tcp, &config.tls)
+        .await
+        .map_err(|e| anyhow::anyhow!("TLS handshake with {} failed: {}", server_name, e))?;
+    Ok(Box::new(stream))
+}
+
+fn configure_socket(tcp: &TcpStream, config: &ConnectionConfig) -> anyhow::Result<()> {
+    tcp.set_nodelay(config.tcp_nodelay)?;
+
+    if let Some(idle) = config.tcp_keepalive {
+        let keepalive = TcpKeepalive::new().with_time(idle).with_interval(idle);
+        SockRef::from(tcp).set_tcp_keepalive(&keepalive)?;
+    }
+    Ok(())
+}
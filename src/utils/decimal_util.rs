@@ -0,0 +1,338 @@
+use std::fmt;
+use std::ops::{Add, AddAssign, Sub, SubAssign};
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+// Binance prices and quantities have at most 8 decimals
+pub const DECIMALS: u32 = 8;
+const SCALE: i64 = 10i64.pow(DECIMALS);
+
+
+/// Direction used whenever a value has to be brought onto a tick or step
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rounding {
+    /// Towards negative infinity
+    Down,
+    /// Towards positive infinity
+    Up,
+    /// Nearest, halves away from zero
+    Nearest,
+}
+
+// n / d for d > 0 rounded in the given direction
+fn div_round(n: i128, d: i128, rounding: Rounding) -> i128 {
+    let quotient = n.div_euclid(d);
+    let remainder = n.rem_euclid(d);
+    if remainder == 0 {
+        return quotient;
+    }
+    match rounding {
+        Rounding::Down => quotient,
+        Rounding::Up => quotient + 1,
+        Rounding::Nearest => {
+            let twice = remainder * 2;
+            if twice > d || (twice == d && n >= 0) {
+                quotient + 1
+            } else {
+                quotient
+            }
+        }
+    }
+}
+
+// Parses a plain decimal string into units of 10^-8, no exponents
+fn parse_units(value: &str) -> anyhow::Result<i64> {
+    let trimmed = value.trim();
+    let (negative, digits) = match trimmed.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, trimmed.strip_prefix('+').unwrap_or(trimmed)),
+    };
+    let (int_part, frac_part) = digits.split_once('.').unwrap_or((digits, ""));
+
+    let is_digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+    if (int_part.is_empty() && frac_part.is_empty()) || !is_digits(int_part) || !is_digits(frac_part) {
+        anyhow::bail!("Invalid decimal '{}'", value);
+    }
+
+    let frac_digits = frac_part.trim_end_matches('0');
+    if frac_digits.len() > DECIMALS as usize {
+        anyhow::bail!("Decimal '{}' has more than {} decimals", value, DECIMALS);
+    }
+
+    let int_units = if int_part.is_empty() {
+        0
+    } else {
+        int_part
+            .parse::<i64>()
+            .ok()
+            .and_then(|int| int.checked_mul(SCALE))
+            .ok_or_else(|| anyhow::anyhow!("Decimal '{}' is out of range", value))?
+    };
+    let frac_units = if frac_digits.is_empty() {
+        0
+    } else {
+        frac_digits.parse::<i64>()? * 10i64.pow(DECIMALS - frac_digits.len() as u32)
+    };
+
+    let units = int_units
+        .checked_add(frac_units)
+        .ok_or_else(|| anyhow::anyhow!("Decimal '{}' is out of range", value))?;
+    Ok(if negative { -units } else { units })
+}
+
+// Plain decimal without exponent or trailing zeros, e.g. 98999.99
+fn format_units(units: i64) -> String {
+    let sign = if units < 0 { "-" } else { "" };
+    let abs = units.unsigned_abs();
+    let int = abs / SCALE as u64;
+    let frac = abs % SCALE as u64;
+    if frac == 0 {
+        format!("{}{}", sign, int)
+    } else {
+        let frac = format!("{:0width$}", frac, width = DECIMALS as usize);
+        format!("{}{}.{}", sign, int, frac.trim_end_matches('0'))
+    }
+}
+
+macro_rules! fixed_point {
+    ($(#[$meta:meta])* $name:ident) => {
+        $(#[$meta])*
+        #[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        pub struct $name(i64);
+
+        impl $name {
+            pub const ZERO: Self = Self(0);
+
+            /// `value` × 10^-`decimals`, e.g. `new(1, 4)` is 0.0001
+            pub const fn new(value: i64, decimals: u32) -> Self {
+                assert!(decimals <= DECIMALS, "more decimals than supported");
+                Self(value * 10i64.pow(DECIMALS - decimals))
+            }
+
+            /// Value in units of 10^-8
+            pub const fn from_units(units: i64) -> Self {
+                Self(units)
+            }
+
+            pub const fn units(self) -> i64 {
+                self.0
+            }
+
+            /// Nearest representable value, None for NaN, infinity or out of range values
+            pub fn from_f64(value: f64) -> Option<Self> {
+                let units = (value * SCALE as f64).round();
+                (units.is_finite() && units.abs() < i64::MAX as f64).then(|| Self(units as i64))
+            }
+
+            pub fn to_f64(self) -> f64 {
+                self.0 as f64 / SCALE as f64
+            }
+
+            pub fn is_zero(self) -> bool {
+                self.0 == 0
+            }
+
+            pub fn is_positive(self) -> bool {
+                self.0 > 0
+            }
+
+            /// Multiple of `increment` in the given direction, unchanged for a non-positive increment
+            pub fn round_to(self, increment: Self, rounding: Rounding) -> Self {
+                if increment.0 <= 0 {
+                    return self;
+                }
+                let steps = div_round(self.0 as i128, increment.0 as i128, rounding);
+                Self((steps * increment.0 as i128) as i64)
+            }
+
+            pub fn is_multiple_of(self, increment: Self) -> bool {
+                increment.0 > 0 && self.0 % increment.0 == 0
+            }
+
+            /// self × numerator / denominator, e.g. `mul_ratio(99, 100, ..)` for 99%
+            pub fn mul_ratio(self, numerator: i64, denominator: i64, rounding: Rounding) -> Self {
+                assert!(denominator > 0, "denominator must be positive");
+                Self(div_round(self.0 as i128 * numerator as i128, denominator as i128, rounding) as i64)
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = anyhow::Error;
+
+            fn from_str(value: &str) -> anyhow::Result<Self> {
+                parse_units(value).map(Self)
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.pad(&format_units(self.0))
+            }
+        }
+
+        // Decimal value instead of the raw units
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{}({})", stringify!($name), format_units(self.0))
+            }
+        }
+
+        impl Add for $name {
+            type Output = Self;
+
+            fn add(self, other: Self) -> Self {
+                Self(self.0 + other.0)
+            }
+        }
+
+        impl Sub for $name {
+            type Output = Self;
+
+            fn sub(self, other: Self) -> Self {
+                Self(self.0 - other.0)
+            }
+        }
+
+        impl AddAssign for $name {
+            fn add_assign(&mut self, other: Self) {
+                self.0 += other.0;
+            }
+        }
+
+        impl SubAssign for $name {
+            fn sub_assign(&mut self, other: Self) {
+                self.0 -= other.0;
+            }
+        }
+
+        // Serialized as a decimal string so no precision is lost
+        impl Serialize for $name {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_str(self)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let value = String::deserialize(deserializer)?;
+                value.parse().map_err(serde::de::Error::custom)
+            }
+        }
+
+        impl std::iter::Sum for $name {
+            fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+                iter.fold(Self::ZERO, Add::add)
+            }
+        }
+    };
+}
+
+fixed_point!(
+    /// Price or quote amount with 8 fixed decimals
+    Price
+);
+
+fixed_point!(
+    /// Quantity with 8 fixed decimals
+    Qty
+);
+
+/// Quote amount of `qty` at `price`, rounded down to 8 decimals
+pub fn notional(price: Price, qty: Qty) -> Price {
+    Price::from_units(div_round(price.units() as i128 * qty.units() as i128, SCALE as i128, Rounding::Down) as i64)
+}
+
+/// Quantity worth `quote` at `price`, None unless the price is positive
+pub fn qty_for_notional(quote: Price, price: Price, rounding: Rounding) -> Option<Qty> {
+    if !price.is_positive() {
+        return None;
+    }
+    let units = div_round(quote.units() as i128 * SCALE as i128, price.units() as i128, rounding);
+    Some(Qty::from_units(units as i64))
+}
+
+/// Quote amount over quantity, e.g. the average fill price, None unless the quantity is positive
+pub fn average_price(quote: Price, qty: Qty) -> Option<Price> {
+    if !qty.is_positive() {
+        return None;
+    }
+    let units = div_round(quote.units() as i128 * SCALE as i128, qty.units() as i128, Rounding::Nearest);
+    Some(Price::from_units(units as i64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn price(value: &str) -> Price {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_and_format() {
+        assert_eq!(price("98999.99").units(), 9_899_999_000_000);
+        assert_eq!(price("0.00000001").units(), 1);
+        assert_eq!(price(".5"), price("0.50000000"));
+        assert_eq!(price("-1.5").units(), -150_000_000);
+        assert_eq!(price("+2"), Price::new(2, 0));
+        assert_eq!(price("1.10000000000"), Price::new(11, 1));
+
+        assert_eq!(price("98999.990").to_string(), "98999.99");
+        assert_eq!(price("-0.5").to_string(), "-0.5");
+        assert_eq!(price("100").to_string(), "100");
+        assert_eq!(format!("{:?}", Qty::new(25, 2)), "Qty(0.25)");
+    }
+
+    #[test]
+    fn parse_rejects_invalid_values() {
+        for value in ["", ".", "-", "1e5", "1.2.3", "abc", "0.000000001", "99999999999999999999", "92233720368.99999999"] {
+            assert!(value.parse::<Price>().is_err(), "{:?} parsed", value);
+        }
+    }
+
+    #[test]
+    fn round_to_increment() {
+        let tick = price("0.01");
+        assert_eq!(price("1.234").round_to(tick, Rounding::Down), price("1.23"));
+        assert_eq!(price("1.231").round_to(tick, Rounding::Up), price("1.24"));
+        assert_eq!(price("1.235").round_to(tick, Rounding::Nearest), price("1.24"));
+        assert_eq!(price("-1.235").round_to(tick, Rounding::Nearest), price("-1.24"));
+        assert_eq!(price("-1.234").round_to(tick, Rounding::Down), price("-1.24"));
+        assert_eq!(price("1.23").round_to(tick, Rounding::Up), price("1.23"));
+        assert_eq!(price("1.234").round_to(Price::ZERO, Rounding::Up), price("1.234"));
+
+        assert!(price("1.23").is_multiple_of(tick));
+        assert!(!price("1.234").is_multiple_of(tick));
+        assert!(!price("1").is_multiple_of(Price::ZERO));
+    }
+
+    #[test]
+    fn ratios_and_notional() {
+        assert_eq!(price("100").mul_ratio(99, 100, Rounding::Down), price("99"));
+        assert_eq!(price("0.00000001").mul_ratio(1, 2, Rounding::Down), Price::ZERO);
+        assert_eq!(price("0.00000001").mul_ratio(1, 2, Rounding::Nearest), price("0.00000001"));
+
+        assert_eq!(notional(price("100000.5"), "0.001".parse().unwrap()), price("100.0005"));
+        assert_eq!(notional(price("0.00000003"), "0.5".parse().unwrap()), price("0.00000001"));
+        assert_eq!(average_price(price("300"), "3".parse().unwrap()), Some(price("100")));
+        assert_eq!(average_price(price("1"), "3".parse().unwrap()), Some(price("0.33333333")));
+        assert_eq!(average_price(price("1"), Qty::ZERO), None);
+    }
+
+    #[test]
+    fn serde_round_trip() {
+        let json = serde_json::to_string(&price("98999.99")).unwrap();
+        assert_eq!(json, "\"98999.99\"");
+        assert_eq!(serde_json::from_str::<Price>(&json).unwrap(), price("98999.99"));
+        assert!(serde_json::from_str::<Price>("\"1e3\"").is_err());
+    }
+
+    #[test]
+    fn f64_conversion() {
+        assert_eq!(Price::from_f64(0.1), Some(price("0.1")));
+        assert_eq!(Price::from_f64(f64::NAN), None);
+        assert_eq!(Price::from_f64(1e30), None);
+        assert_eq!(price("2.5").to_f64(), 2.5);
+    }
+}
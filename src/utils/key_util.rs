@@ -1,19 +1,393 @@
 use std::env;
 use base64::{engine::general_purpose, Engine as _};
-use ed25519_dalek::{SigningKey, SECRET_KEY_LENGTH};
+use chrono::Utc;
+use ed25519_dalek::pkcs8::{spki::der::pem::LineEnding, DecodePrivateKey, DecodePublicKey, EncodePrivateKey, EncodePublicKey};
+use ed25519_dalek::{Signature, SigningKey, Verifier, VerifyingKey, PUBLIC_KEY_LENGTH, SECRET_KEY_LENGTH};
+use rand_core::OsRng;
+use pkcs8::der::{pem, Decode};
+use pkcs8::{EncryptedPrivateKeyInfo, ObjectIdentifier, PrivateKeyInfo};
+use std::path::PathBuf;
+use zeroize::Zeroizing;
 
+use crate::utils::config_util::session_var;
+use crate::utils::message_util::{compute_raw_data, logon_signature_payload};
+use crate::utils::secret_util::{parse_secret_source, EnvSecret, FileSecret, SecretProvider};
 
-pub fn load_signing_key() -> anyhow::Result<SigningKey> {
-    let key_b64 = env::var("BINANCE_PRIVATE_KEY_BASE64")?;
-    let key_bytes = general_purpose::STANDARD.decode(key_b64)?;
-    
-    let private_key_bytes = if key_bytes.len() == 48 && key_bytes[0] == 0x30 {
-        &key_bytes[16..48]
-    } else if key_bytes.len() == SECRET_KEY_LENGTH {
-        &key_bytes[..]
+const ED25519_OID: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.101.112");
+
+
+pub struct SessionCredentials {
+    pub api_key: String,
+    /// Zeroized on drop by ed25519-dalek
+    pub signing_key: SigningKey,
+}
+
+impl SessionCredentials {
+    /// Loads the API key and signing key for a session ("MD" or "OE").
+    /// BINANCE_<SESSION>_API_KEY_SOURCE, _PRIVATE_KEY_SOURCE and _PRIVATE_KEY_PASSPHRASE_SOURCE
+    /// select a secret provider, falling back to the plain BINANCE_API_KEY style variables.
+    pub fn load(session: &str) -> anyhow::Result<Self> {
+        let api_key_source = match session_var(session, "API_KEY_SOURCE") {
+            Some(spec) => parse_secret_source(&spec)?,
+            None => default_env_source(session, "API_KEY"),
+        };
+
+        let private_key_source = match (
+            session_var(session, "PRIVATE_KEY_SOURCE"),
+            session_var(session, "PRIVATE_KEY_PATH"),
+        ) {
+            (Some(spec), _) => parse_secret_source(&spec)?,
+            (None, Some(path)) => Box::new(FileSecret { path: PathBuf::from(path) }),
+            (None, None) => default_env_source(session, "PRIVATE_KEY_BASE64"),
+        };
+
+        let passphrase_source = match session_var(session, "PRIVATE_KEY_PASSPHRASE_SOURCE") {
+            Some(spec) => Some(parse_secret_source(&spec)?),
+            None => session_var(session, "PRIVATE_KEY_PASSPHRASE")
+                .map(|_| default_env_source(session, "PRIVATE_KEY_PASSPHRASE")),
+        };
+
+        let api_key = api_key_source
+            .fetch()
+            .map_err(|e| anyhow::anyhow!("{} API key ({}): {}", session, api_key_source, e))?
+            .trim()
+            .to_string();
+
+        let key_material = private_key_source
+            .fetch()
+            .map_err(|e| anyhow::anyhow!("{} private key ({}): {}", session, private_key_source, e))?;
+        let passphrase = passphrase_source
+            .map(|source| {
+                source
+                    .fetch()
+                    .map_err(|e| anyhow::anyhow!("{} private key passphrase ({}): {}", session, source, e))
+            })
+            .transpose()?;
+
+        let signing_key = parse_signing_key(&key_material, passphrase.as_ref().map(|p| p.as_str()))
+            .map_err(|e| anyhow::anyhow!("{} private key ({}): {}", session, private_key_source, e))?;
+
+        Ok(Self { api_key, signing_key })
+    }
+}
+
+impl SessionCredentials {
+    /// Catches a key that doesn't belong to the API key before Binance rejects the logon.
+    /// Compares against BINANCE_<SESSION>_PUBLIC_KEY when configured and round-trips a
+    /// logon signature through the exact RawData format.
+    pub fn self_check(&self, session: &str) -> anyhow::Result<()> {
+        let verifying_key = self.signing_key.verifying_key();
+        let public_pem = verifying_key
+            .to_public_key_pem(LineEnding::LF)
+            .map_err(|e| anyhow::anyhow!("Unable to encode {} public key: {}", session, e))?;
+
+        log::info!(
+            "{} session public key (register this with the API key):\n{}",
+            session,
+            public_pem.trim_end()
+        );
+
+        if let Some(expected) = session_var(session, "PUBLIC_KEY") {
+            self.check_public_key(session, &expected)?;
+        }
+
+        let sending_time = Utc::now().format("%Y%m%d-%H:%M:%S%.3f").to_string();
+        let raw_data = compute_raw_data(&self.signing_key, "SELFCHECK", "SPOT", 1, &sending_time);
+        let signature_bytes = general_purpose::STANDARD.decode(&raw_data)?;
+        let signature = Signature::from_slice(&signature_bytes)?;
+        let payload = logon_signature_payload("SELFCHECK", "SPOT", 1, &sending_time);
+        verifying_key
+            .verify(payload.as_bytes(), &signature)
+            .map_err(|e| anyhow::anyhow!("{} logon signature self-check failed: {}", session, e))?;
+
+        Ok(())
+    }
+
+    // Fails unless `expected` is the public key of the signing key
+    fn check_public_key(&self, session: &str, expected: &str) -> anyhow::Result<()> {
+        let verifying_key = self.signing_key.verifying_key();
+        let expected_key = parse_public_key(expected)
+            .map_err(|e| anyhow::anyhow!("BINANCE_{}_PUBLIC_KEY: {}", session, e))?;
+        if expected_key != verifying_key {
+            anyhow::bail!(
+                "{} private key does not match the configured public key (derived {}, expected {})",
+                session,
+                general_purpose::STANDARD.encode(verifying_key.as_bytes()),
+                general_purpose::STANDARD.encode(expected_key.as_bytes()),
+            );
+        }
+        Ok(())
+    }
+}
+
+// Prefers BINANCE_<SESSION>_<NAME> when set, otherwise BINANCE_<NAME>
+fn default_env_source(session: &str, name: &str) -> Box<dyn SecretProvider> {
+    let session_name = format!("BINANCE_{}_{}", session, name);
+    let name = if env::var(&session_name).is_ok() {
+        session_name
     } else {
-        anyhow::bail!("Invalid Ed25519 private key format");
+        format!("BINANCE_{}", name)
     };
+    Box::new(EnvSecret { name })
+}
+
+/// Accepts PEM (`PRIVATE KEY` or `ENCRYPTED PRIVATE KEY`), base64 PKCS#8 DER,
+/// base64 encrypted PKCS#8 DER or a base64 raw 32-byte seed.
+pub fn parse_signing_key(material: &str, passphrase: Option<&str>) -> anyhow::Result<SigningKey> {
+    let material = material.trim();
+
+    let der = if material.starts_with("-----BEGIN") {
+        let (label, der) = pem::decode_vec(material.as_bytes())
+            .map_err(|e| anyhow::anyhow!("Malformed PEM private key: {}", e))?;
+        let der = Zeroizing::new(der);
+        match label {
+            "PRIVATE KEY" => der,
+            "ENCRYPTED PRIVATE KEY" => return decrypt_pkcs8(&der, passphrase),
+            "OPENSSH PRIVATE KEY" => anyhow::bail!(
+                "OpenSSH private keys are not supported, convert it with `ssh-keygen -p -m PKCS8 -f <file>`"
+            ),
+            "PUBLIC KEY" => anyhow::bail!(
+                "This is a public key, the Ed25519 private key registered with the API key is required"
+            ),
+            other => anyhow::bail!("Unsupported PEM label '{}', expected PRIVATE KEY", other),
+        }
+    } else {
+        let compact: Zeroizing<String> = Zeroizing::new(material.split_whitespace().collect());
+        let der = general_purpose::STANDARD
+            .decode(compact.as_bytes())
+            .map_err(|e| anyhow::anyhow!("Private key is neither PEM nor valid base64: {}", e))?;
+        Zeroizing::new(der)
+    };
+
+    if der.len() == SECRET_KEY_LENGTH {
+        return Ok(SigningKey::from_bytes(der[..].try_into()?));
+    }
+
+    if der.first() != Some(&0x30) {
+        anyhow::bail!(
+            "Private key is {} bytes, expected a 32-byte Ed25519 seed or a PKCS#8 DER structure",
+            der.len()
+        );
+    }
+
+    if PrivateKeyInfo::from_der(&der).is_err() && EncryptedPrivateKeyInfo::from_der(&der).is_ok() {
+        return decrypt_pkcs8(&der, passphrase);
+    }
+
+    signing_key_from_pkcs8(&der)
+}
+
+fn decrypt_pkcs8(der: &[u8], passphrase: Option<&str>) -> anyhow::Result<SigningKey> {
+    let passphrase = passphrase.ok_or_else(|| {
+        anyhow::anyhow!("Private key is encrypted, set BINANCE_PRIVATE_KEY_PASSPHRASE or BINANCE_PRIVATE_KEY_PASSPHRASE_SOURCE")
+    })?;
+
+    let encrypted = EncryptedPrivateKeyInfo::from_der(der)
+        .map_err(|e| anyhow::anyhow!("Malformed encrypted PKCS#8 private key: {}", e))?;
+    let document = encrypted
+        .decrypt(passphrase)
+        .map_err(|e| anyhow::anyhow!("Unable to decrypt private key (wrong passphrase or unsupported cipher): {}", e))?;
+
+    signing_key_from_pkcs8(document.as_bytes())
+}
+
+fn signing_key_from_pkcs8(der: &[u8]) -> anyhow::Result<SigningKey> {
+    let info = PrivateKeyInfo::from_der(der)
+        .map_err(|e| anyhow::anyhow!("Malformed PKCS#8 private key: {}", e))?;
+
+    let oid = info.algorithm.oid;
+    if oid != ED25519_OID {
+        anyhow::bail!(
+            "Private key algorithm is {} ({}), Binance FIX requires an Ed25519 key",
+            algorithm_name(&oid),
+            oid
+        );
+    }
+
+    SigningKey::from_pkcs8_der(der).map_err(|e| anyhow::anyhow!("Invalid Ed25519 PKCS#8 private key: {}", e))
+}
+
+fn algorithm_name(oid: &ObjectIdentifier) -> &'static str {
+    match oid.to_string().as_str() {
+        "1.2.840.113549.1.1.1" => "RSA",
+        "1.2.840.10045.2.1" => "EC",
+        "1.3.101.113" => "Ed448",
+        "1.3.101.110" => "X25519",
+        _ => "unknown",
+    }
+}
+
+/// Accepts PEM (`PUBLIC KEY`), base64 SubjectPublicKeyInfo DER or a base64 raw 32-byte key.
+pub fn parse_public_key(material: &str) -> anyhow::Result<VerifyingKey> {
+    let material = material.trim();
+
+    if material.starts_with("-----BEGIN") {
+        return VerifyingKey::from_public_key_pem(material)
+            .map_err(|e| anyhow::anyhow!("Invalid Ed25519 PEM public key: {}", e));
+    }
+
+    let compact: String = material.split_whitespace().collect();
+    let bytes = general_purpose::STANDARD
+        .decode(compact)
+        .map_err(|e| anyhow::anyhow!("Public key is neither PEM nor valid base64: {}", e))?;
+
+    if bytes.len() == PUBLIC_KEY_LENGTH {
+        return VerifyingKey::from_bytes(bytes[..].try_into()?)
+            .map_err(|e| anyhow::anyhow!("Invalid Ed25519 public key: {}", e));
+    }
+
+    VerifyingKey::from_public_key_der(&bytes)
+        .map_err(|e| anyhow::anyhow!("Invalid Ed25519 public key DER ({} bytes): {}", bytes.len(), e))
+}
+
+/// Generates a new Ed25519 keypair as PKCS#8 / SPKI PEM, the format Binance accepts.
+/// Writes `<prefix>.pem` (mode 0600) and `<prefix>.pub.pem` and prints the public key.
+pub fn keygen(out_prefix: &str) -> anyhow::Result<()> {
+    let signing_key = SigningKey::generate(&mut OsRng);
+
+    let private_pem = signing_key
+        .to_pkcs8_pem(LineEnding::LF)
+        .map_err(|e| anyhow::anyhow!("Unable to encode private key: {}", e))?;
+    let public_pem = signing_key
+        .verifying_key()
+        .to_public_key_pem(LineEnding::LF)
+        .map_err(|e| anyhow::anyhow!("Unable to encode public key: {}", e))?;
+
+    let private_path = format!("{}.pem", out_prefix);
+    let public_path = format!("{}.pub.pem", out_prefix);
+    write_private_file(&private_path, private_pem.as_bytes())?;
+    std::fs::write(&public_path, public_pem.as_bytes())
+        .map_err(|e| anyhow::anyhow!("Unable to write {}: {}", public_path, e))?;
+
+    println!("Private key written to {}", private_path);
+    println!("Public key written to {}, register it with Binance:\n", public_path);
+    print!("{}", public_pem);
+    Ok(())
+}
+
+#[cfg(unix)]
+fn write_private_file(path: &str, contents: &[u8]) -> anyhow::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+        .map_err(|e| anyhow::anyhow!("Unable to create {}: {}", path, e))?;
+    file.write_all(contents)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn write_private_file(path: &str, contents: &[u8]) -> anyhow::Result<()> {
+    use std::io::Write;
+
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .map_err(|e| anyhow::anyhow!("Unable to create {}: {}", path, e))?;
+    file.write_all(contents)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use pkcs8::pkcs5::pbes2;
+
+    use super::*;
+
+    fn test_key() -> SigningKey {
+        SigningKey::from_bytes(&[7; SECRET_KEY_LENGTH])
+    }
+
+    fn encrypted_der(passphrase: &str) -> Vec<u8> {
+        let der = test_key().to_pkcs8_der().unwrap();
+        let params = pbes2::Parameters::pbkdf2_sha256_aes256cbc(1000, b"saltsalt", &[1; 16]).unwrap();
+        PrivateKeyInfo::from_der(der.as_bytes())
+            .unwrap()
+            .encrypt_with_params(params, passphrase)
+            .unwrap()
+            .as_bytes()
+            .to_vec()
+    }
+
+    fn base64(bytes: &[u8]) -> String {
+        general_purpose::STANDARD.encode(bytes)
+    }
+
+    fn error(result: anyhow::Result<SigningKey>) -> String {
+        result.map(|_| ()).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn accepts_every_private_key_format() {
+        let pem = test_key().to_pkcs8_pem(LineEnding::LF).unwrap();
+        let der = test_key().to_pkcs8_der().unwrap();
+        let encrypted = encrypted_der("secret");
+        let encrypted_pem = pem::encode_string("ENCRYPTED PRIVATE KEY", LineEnding::LF, &encrypted).unwrap();
+
+        for (material, passphrase) in [
+            (pem.to_string(), None),
+            (base64(der.as_bytes()), None),
+            (base64(&encrypted), Some("secret")),
+            (encrypted_pem, Some("secret")),
+            // Raw seed, as older configurations stored it
+            (base64(test_key().as_bytes()), None),
+        ] {
+            let key = parse_signing_key(&material, passphrase).unwrap();
+            assert_eq!(key.to_bytes(), test_key().to_bytes());
+        }
+    }
+
+    #[test]
+    fn encrypted_key_needs_the_right_passphrase() {
+        let encrypted = base64(&encrypted_der("secret"));
+        assert!(error(parse_signing_key(&encrypted, None)).contains("is encrypted"));
+        assert!(error(parse_signing_key(&encrypted, Some("wrong"))).contains("wrong passphrase"));
+
+        let der = encrypted_der("secret");
+        assert!(decrypt_pkcs8(&der, Some("secret")).is_ok());
+        assert!(decrypt_pkcs8(&der, None).is_err());
+    }
+
+    #[test]
+    fn rejects_keys_that_are_not_ed25519() {
+        let ec_pem = include_str!("testdata/localhost_key.pem");
+        let err = error(parse_signing_key(ec_pem, None));
+        assert!(err.contains("EC (1.2.840.10045.2.1)"), "{}", err);
+
+        let (_, der) = pem::decode_vec(ec_pem.as_bytes()).unwrap();
+        assert!(signing_key_from_pkcs8(&der).is_err());
+        assert!(error(parse_signing_key(&base64(&[1; 16]), None)).contains("16 bytes"));
+    }
+
+    #[test]
+    fn parses_public_keys() {
+        let verifying_key = test_key().verifying_key();
+        let pem = verifying_key.to_public_key_pem(LineEnding::LF).unwrap();
+        let der = verifying_key.to_public_key_der().unwrap();
+
+        for material in [pem, base64(der.as_bytes()), base64(verifying_key.as_bytes())] {
+            assert_eq!(parse_public_key(&material).unwrap(), verifying_key);
+        }
+        assert!(parse_public_key("not base64!").is_err());
+    }
+
+    #[test]
+    fn self_check_catches_a_foreign_public_key() {
+        let credentials = SessionCredentials {
+            api_key: "key".to_string(),
+            signing_key: test_key(),
+        };
+        let own = base64(test_key().verifying_key().as_bytes());
+        let other = base64(SigningKey::from_bytes(&[8; SECRET_KEY_LENGTH]).verifying_key().as_bytes());
 
-    Ok(SigningKey::from_bytes(private_key_bytes.try_into()?))
+        assert!(credentials.check_public_key("OE", &own).is_ok());
+        let err = credentials.check_public_key("OE", &other).unwrap_err().to_string();
+        assert!(err.contains("does not match"), "{}", err);
+        assert!(credentials.self_check("KEYUTILTEST").is_ok());
+    }
 }
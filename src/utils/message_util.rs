@@ -2,8 +2,20 @@ use base64::{engine::general_purpose, Engine as _};
 use ed25519_dalek::{SigningKey, Signer};
 use chrono::Utc;
 
+use crate::execution::order_types::{OrdType, Side, TimeInForce};
+use crate::utils::decimal_util::Qty;
 use crate::utils::fix_util::build_fix_message;
 
+// MsgType, SenderCompID, TargetCompID, MsgSeqNum and SendingTime joined by SOH
+pub fn logon_signature_payload(
+    sender: &str,
+    target: &str,
+    seq_num: i32,
+    sending_time: &str,
+) -> String {
+    format!("A\x01{}\x01{}\x01{}\x01{}", sender, target, seq_num, sending_time)
+}
+
 pub fn compute_raw_data(
     private_key: &SigningKey,
     sender: &str,
@@ -11,7 +23,7 @@ pub fn compute_raw_data(
     seq_num: i32,
     sending_time: &str,
 ) -> String {
-    let payload = format!("A\x01{}\x01{}\x01{}\x01{}", sender, target, seq_num, sending_time);
+    let payload = logon_signature_payload(sender, target, seq_num, sending_time);
     let sig = private_key.sign(payload.as_bytes());
     general_purpose::STANDARD.encode(sig.to_bytes())
 }
@@ -72,6 +84,7 @@ pub fn build_heartbeat_message(
     build_fix_message(fields)
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn build_market_data_request(
     sender: &str,
     target: &str,
@@ -80,6 +93,7 @@ pub fn build_market_data_request(
     symbol: &str,
     entry_types: &[&str], // e.g., ["0", "1"] for BID and OFFER
     market_depth: Option<i32>, // e.g., Some(1) for BookTicker
+    aggregated_book: bool,
 ) -> String {
     let sending_time = Utc::now().format("%Y%m%d-%H:%M:%S%.3f").to_string();
 
@@ -109,29 +123,81 @@ pub fn build_market_data_request(
     }
 
     // AggregatedBook is required
-    fields.push("266=Y".to_string());
+    fields.push(format!("266={}", if aggregated_book { "Y" } else { "N" }));
 
     build_fix_message(fields)
 }
 
+pub fn build_market_data_unsubscribe(
+    sender: &str,
+    target: &str,
+    seq_num: i32,
+    req_id: &str,
+) -> String {
+    let sending_time = Utc::now().format("%Y%m%d-%H:%M:%S%.3f").to_string();
+
+    let fields = vec![
+        "8=FIX.4.4".to_string(),
+        "9=000".to_string(), // placeholder
+        "35=V".to_string(),
+        format!("49={}", sender),
+        format!("56={}", target),
+        format!("34={}", seq_num),
+        format!("52={}", sending_time),
+        format!("262={}", req_id),
+        "263=2".to_string(), // 2 = UNSUBSCRIBE
+    ];
+
+    build_fix_message(fields)
+}
+
+// InstrumentListRequest for one symbol, or for every instrument when symbol is None
+pub fn build_instrument_list_request(
+    sender: &str,
+    target: &str,
+    seq_num: i32,
+    req_id: &str,
+    symbol: Option<&str>,
+) -> String {
+    let sending_time = Utc::now().format("%Y%m%d-%H:%M:%S%.3f").to_string();
+
+    let mut fields = vec![
+        "8=FIX.4.4".to_string(),
+        "9=000".to_string(), // placeholder
+        "35=x".to_string(),
+        format!("49={}", sender),
+        format!("56={}", target),
+        format!("34={}", seq_num),
+        format!("52={}", sending_time),
+        format!("320={}", req_id),
+    ];
+
+    match symbol {
+        Some(symbol) => {
+            fields.push("559=0".to_string()); // 0 = SINGLE_INSTRUMENT
+            fields.push(format!("55={}", symbol));
+        }
+        None => fields.push("559=4".to_string()), // 4 = ALL_INSTRUMENTS
+    }
+
+    build_fix_message(fields)
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn build_new_order_single(
     sender: &str,
     target: &str,
     seq_num: i32,
     symbol: &str,
-    side: &str,    // "BUY" or "SELL"
-    qty: f64,
-    price: f64,
+    side: Side,
+    qty: Qty,
+    ord_type: &OrdType,
+    time_in_force: TimeInForce,
     cl_ord_id: &str, // Original Client Order ID for canceling
 ) -> String {
     let sending_time = Utc::now().format("%Y%m%d-%H:%M:%S%.3f").to_string();
-    let side_code = match side {
-        "BUY" => "1",
-        "SELL" => "2",
-        _ => panic!("Invalid side"),
-    };
 
-    let fields = vec![
+    let mut fields = vec![
         "8=FIX.4.4".to_string(),
         "9=000".to_string(), // placeholder
         "35=D".to_string(), // New Order Single
@@ -141,16 +207,41 @@ pub fn build_new_order_single(
         format!("52={}", sending_time),
         format!("11={}", cl_ord_id),
         format!("55={}", symbol),
-        format!("54={}", side_code),
-        format!("38={}", qty),
-        "40=2".to_string(), // LIMIT order
-        format!("44={}", price),
-        "59=1".to_string(), // TimeInForce = GTC
     ];
+    fields.extend(order_fields(side, qty, ord_type, time_in_force));
 
     build_fix_message(fields)
 }
 
+// Side, quantity, OrdType and the prices, TimeInForce and trigger the type takes
+fn order_fields(side: Side, qty: Qty, ord_type: &OrdType, time_in_force: TimeInForce) -> Vec<String> {
+    let mut fields = vec![
+        format!("54={}", side.fix_code()),
+        format!("38={}", qty),
+        format!("40={}", ord_type.fix_code()),
+    ];
+    if let Some(price) = ord_type.price() {
+        fields.push(format!("44={}", price));
+    }
+    if ord_type.accepts_time_in_force() {
+        fields.push(format!("59={}", time_in_force.fix_code()));
+    }
+    // Binance's post-only, the order is rejected instead of taking liquidity
+    if let OrdType::LimitMaker { .. } = ord_type {
+        fields.push("18=6".to_string()); // ExecInst = PARTICIPATE_DONT_INITIATE
+    }
+    if let (Some(trigger_price), Some(direction)) = (ord_type.trigger_price(), ord_type.trigger_direction(side)) {
+        fields.extend([
+            "1100=4".to_string(), // TriggerType = PRICE_MOVEMENT
+            "1101=1".to_string(), // TriggerAction = ACTIVATE
+            format!("1102={}", trigger_price),
+            "1107=2".to_string(), // TriggerPriceType = LAST_TRADE
+            format!("1109={}", direction),
+        ]);
+    }
+    fields
+}
+
 pub fn build_order_cancel_request(
     sender: &str,
     target: &str,
@@ -177,6 +268,42 @@ pub fn build_order_cancel_request(
     build_fix_message(fields)
 }
 
+// Binance's cancel-replace, the new order is only placed once the cancel succeeded
+#[allow(clippy::too_many_arguments)]
+pub fn build_order_cancel_replace_request(
+    sender: &str,
+    target: &str,
+    seq_num: i32,
+    symbol: &str,
+    side: Side,
+    qty: Qty,
+    ord_type: &OrdType,
+    time_in_force: TimeInForce,
+    cancel_cl_ord_id: &str,
+    orig_cl_ord_id: &str,
+    cl_ord_id: &str, // ClOrdID of the new order
+) -> String {
+    let sending_time = Utc::now().format("%Y%m%d-%H:%M:%S%.3f").to_string();
+
+    let mut fields = vec![
+        "8=FIX.4.4".to_string(),
+        "9=000".to_string(),  // placeholder
+        "35=XCN".to_string(), // OrderCancelRequestAndNewOrderSingle
+        format!("49={}", sender),
+        format!("56={}", target),
+        format!("34={}", seq_num),
+        format!("52={}", sending_time),
+        "25033=1".to_string(), // STOP_ON_FAILURE
+        format!("25034={}", cancel_cl_ord_id),
+        format!("41={}", orig_cl_ord_id),
+        format!("11={}", cl_ord_id),
+        format!("55={}", symbol),
+    ];
+    fields.extend(order_fields(side, qty, ord_type, time_in_force));
+
+    build_fix_message(fields)
+}
+
 // Helper function to extract a field value from a FIX message
 pub fn extract_field(message: &str, tag: &str) -> Option<String> {
     let search_pattern = format!("{}=", tag);
@@ -188,3 +315,110 @@ pub fn extract_field(message: &str, tag: &str) -> Option<String> {
         None
     }
 }
+
+// Splits a FIX message into (tag, value) pairs in wire order, keeping repeating groups intact
+pub fn parse_fields(message: &str) -> Vec<(&str, &str)> {
+    message
+        .split('\x01')
+        .filter_map(|field| field.split_once('='))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::decimal_util::Price;
+
+    fn price(value: &str) -> Price {
+        value.parse().unwrap()
+    }
+
+    fn fields(side: Side, ord_type: OrdType, time_in_force: TimeInForce) -> Vec<String> {
+        order_fields(side, "0.5".parse().unwrap(), &ord_type, time_in_force)
+    }
+
+    #[test]
+    fn market_and_limit_fields() {
+        assert_eq!(fields(Side::Buy, OrdType::Market, TimeInForce::GoodTillCancel), ["54=1", "38=0.5", "40=1"]);
+        assert_eq!(
+            fields(Side::Sell, OrdType::Limit { price: price("101.5") }, TimeInForce::ImmediateOrCancel),
+            ["54=2", "38=0.5", "40=2", "44=101.5", "59=3"]
+        );
+    }
+
+    #[test]
+    fn limit_maker_is_a_post_only_limit() {
+        assert_eq!(
+            fields(Side::Buy, OrdType::LimitMaker { price: price("100") }, TimeInForce::GoodTillCancel),
+            ["54=1", "38=0.5", "40=2", "44=100", "18=6"]
+        );
+    }
+
+    #[test]
+    fn cancel_replace_names_both_orders() {
+        let message = build_order_cancel_replace_request(
+            "SENDER",
+            "SPOT",
+            7,
+            "BTCUSDT",
+            Side::Buy,
+            "0.5".parse().unwrap(),
+            &OrdType::LimitMaker { price: price("100") },
+            TimeInForce::GoodTillCancel,
+            "cancel-1",
+            "resting-1",
+            "new-1",
+        );
+        let fields = parse_fields(&message);
+        let value = |tag: &str| fields.iter().find(|(field, _)| *field == tag).map(|(_, value)| *value);
+
+        assert_eq!(value("35"), Some("XCN"));
+        assert_eq!(value("25033"), Some("1"));
+        assert_eq!(value("25034"), Some("cancel-1"));
+        assert_eq!(value("41"), Some("resting-1"));
+        assert_eq!(value("11"), Some("new-1"));
+        assert_eq!(value("55"), Some("BTCUSDT"));
+        assert_eq!(value("18"), Some("6"));
+        assert_eq!(value("59"), None);
+    }
+
+    // The order fields followed by a trigger at 95 in `direction`
+    fn triggered(order: &[&str], direction: &str) -> Vec<String> {
+        let trigger = ["1100=4", "1101=1", "1102=95", "1107=2", &format!("1109={}", direction)].map(String::from);
+        order.iter().map(|field| field.to_string()).chain(trigger).collect()
+    }
+
+    #[test]
+    fn stop_and_take_profit_fields() {
+        let trigger_price = price("95");
+        assert_eq!(
+            fields(Side::Sell, OrdType::StopLoss { trigger_price }, TimeInForce::GoodTillCancel),
+            triggered(&["54=2", "38=0.5", "40=3"], "D")
+        );
+        assert_eq!(
+            fields(Side::Buy, OrdType::StopLimit { price: price("96"), trigger_price }, TimeInForce::FillOrKill),
+            triggered(&["54=1", "38=0.5", "40=4", "44=96", "59=4"], "U")
+        );
+        assert_eq!(
+            fields(Side::Buy, OrdType::TakeProfit { trigger_price }, TimeInForce::GoodTillCancel),
+            triggered(&["54=1", "38=0.5", "40=3"], "D")
+        );
+        assert_eq!(
+            fields(Side::Sell, OrdType::TakeProfitLimit { price: price("94"), trigger_price }, TimeInForce::GoodTillCancel),
+            triggered(&["54=2", "38=0.5", "40=4", "44=94", "59=1"], "U")
+        );
+    }
+
+    #[test]
+    fn time_in_force_only_for_the_types_that_take_it() {
+        for ord_type in [
+            OrdType::Market,
+            OrdType::LimitMaker { price: price("100") },
+            OrdType::StopLoss { trigger_price: price("95") },
+            OrdType::TakeProfit { trigger_price: price("95") },
+        ] {
+            let fields = fields(Side::Buy, ord_type, TimeInForce::GoodTillCancel);
+            assert!(!fields.iter().any(|field| field.starts_with("59=")), "{:?}", fields);
+        }
+    }
+}
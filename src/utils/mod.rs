@@ -1,4 +1,10 @@
+pub mod config_util;
 pub mod connection_util;
+pub mod decimal_util;
 pub mod fix_util;
 pub mod key_util;
 pub mod message_util;
+pub mod proxy_util;
+pub mod redact_util;
+pub mod secret_util;
+pub mod tls_util;
@@ -0,0 +1,331 @@
+use std::fmt;
+use base64::{engine::general_purpose, Engine as _};
+use percent_encoding::percent_decode_str;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_socks::tcp::Socks5Stream;
+use url::Url;
+
+const MAX_CONNECT_RESPONSE_LEN: usize = 8192;
+
+
+#[derive(Clone)]
+pub struct ProxyCredentials {
+    pub username: String,
+    pub password: String,
+}
+
+// Never print the proxy password
+impl fmt::Debug for ProxyCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProxyCredentials")
+            .field("username", &self.username)
+            .field("password", &"***")
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProxyKind {
+    Socks5,
+    HttpConnect,
+}
+
+#[derive(Debug, Clone)]
+pub struct ProxyConfig {
+    pub kind: ProxyKind,
+    pub host: String,
+    pub port: u16,
+    pub credentials: Option<ProxyCredentials>,
+}
+
+impl fmt::Display for ProxyConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scheme = match self.kind {
+            ProxyKind::Socks5 => "socks5",
+            ProxyKind::HttpConnect => "http",
+        };
+        write!(f, "{}://{}", scheme, authority(&self.host, self.port))
+    }
+}
+
+/// `host:port`, with an IPv6 address in brackets as `[addr]:port`
+pub fn authority(host: &str, port: u16) -> String {
+    if host.contains(':') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
+impl ProxyConfig {
+    /// Parses `socks5://[user:pass@]host:port` or `http://[user:pass@]host:port`
+    pub fn parse(value: &str) -> anyhow::Result<Self> {
+        let url = Url::parse(value).map_err(|e| anyhow::anyhow!("Invalid proxy URL: {}", e))?;
+
+        let kind = match url.scheme() {
+            "socks5" | "socks5h" => ProxyKind::Socks5,
+            "http" => ProxyKind::HttpConnect,
+            other => anyhow::bail!("Unsupported proxy scheme '{}', expected socks5 or http", other),
+        };
+
+        // An IPv6 host comes in brackets, the socket address needs it without
+        let host = url
+            .host_str()
+            .ok_or_else(|| anyhow::anyhow!("Proxy URL is missing a host"))?
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string();
+        let port = match (url.port(), &kind) {
+            (Some(port), _) => port,
+            (None, ProxyKind::Socks5) => 1080,
+            (None, ProxyKind::HttpConnect) => 8080,
+        };
+
+        let credentials = if url.username().is_empty() {
+            None
+        } else {
+            Some(ProxyCredentials {
+                username: percent_decode(url.username())?,
+                password: percent_decode(url.password().unwrap_or_default())?,
+            })
+        };
+
+        Ok(Self {
+            kind,
+            host,
+            port,
+            credentials,
+        })
+    }
+}
+
+fn percent_decode(value: &str) -> anyhow::Result<String> {
+    Ok(percent_decode_str(value).decode_utf8()?.into_owned())
+}
+
+/// Opens a TCP tunnel to `host:port` through the proxy.
+/// Errors name the proxy when the proxy itself failed and the exchange when the proxy could not reach it.
+pub async fn connect_via_proxy(proxy: &ProxyConfig, host: &str, port: u16) -> anyhow::Result<TcpStream> {
+    let tcp = TcpStream::connect((proxy.host.as_str(), proxy.port))
+        .await
+        .map_err(|e| anyhow::anyhow!("Proxy {} unreachable: {}", proxy, e))?;
+
+    match proxy.kind {
+        ProxyKind::Socks5 => socks5_connect(proxy, tcp, host, port).await,
+        ProxyKind::HttpConnect => http_connect(proxy, tcp, host, port).await,
+    }
+}
+
+async fn socks5_connect(
+    proxy: &ProxyConfig,
+    tcp: TcpStream,
+    host: &str,
+    port: u16,
+) -> anyhow::Result<TcpStream> {
+    let result = match &proxy.credentials {
+        Some(creds) => {
+            Socks5Stream::connect_with_password_and_socket(
+                tcp, (host, port), &creds.username, &creds.password,
+            )
+            .await
+        }
+        None => Socks5Stream::connect_with_socket(tcp, (host, port)).await,
+    };
+
+    match result {
+        Ok(stream) => Ok(stream.into_inner()),
+        // The proxy is fine, it just could not reach the exchange
+        Err(
+            e @ (tokio_socks::Error::NetworkUnreachable
+            | tokio_socks::Error::HostUnreachable
+            | tokio_socks::Error::ConnectionRefused
+            | tokio_socks::Error::TtlExpired),
+        ) => anyhow::bail!("Exchange {} unreachable via proxy {}: {}", authority(host, port), proxy, e),
+        Err(e) => anyhow::bail!("Proxy {} failed: {}", proxy, e),
+    }
+}
+
+async fn http_connect(
+    proxy: &ProxyConfig,
+    mut tcp: TcpStream,
+    host: &str,
+    port: u16,
+) -> anyhow::Result<TcpStream> {
+    let mut request = format!(
+        "CONNECT {target} HTTP/1.1\r\nHost: {target}\r\n",
+        target = authority(host, port),
+    );
+    if let Some(creds) = &proxy.credentials {
+        let token = general_purpose::STANDARD.encode(format!("{}:{}", creds.username, creds.password));
+        request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", token));
+    }
+    request.push_str("\r\n");
+
+    tcp.write_all(request.as_bytes())
+        .await
+        .map_err(|e| anyhow::anyhow!("Proxy {} failed: {}", proxy, e))?;
+
+    // Read byte by byte so nothing after the header (e.g. the TLS ServerHello) is consumed
+    let mut response = Vec::new();
+    let mut byte = [0u8; 1];
+    while !response.ends_with(b"\r\n\r\n") {
+        let n = tcp
+            .read(&mut byte)
+            .await
+            .map_err(|e| anyhow::anyhow!("Proxy {} failed: {}", proxy, e))?;
+        if n == 0 {
+            anyhow::bail!("Proxy {} closed the connection during CONNECT", proxy);
+        }
+        if response.len() >= MAX_CONNECT_RESPONSE_LEN {
+            anyhow::bail!("Proxy {} sent an oversized CONNECT response", proxy);
+        }
+        response.push(byte[0]);
+    }
+
+    let response = String::from_utf8_lossy(&response);
+    let status_line = response.lines().next().unwrap_or_default();
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse::<u16>().ok())
+        .ok_or_else(|| anyhow::anyhow!("Proxy {} sent an invalid CONNECT response: {}", proxy, status_line))?;
+
+    match status {
+        200..=299 => Ok(tcp),
+        // 502/503/504 are the proxy reporting the upstream failure
+        502..=504 => anyhow::bail!("Exchange {} unreachable via proxy {}: {}", authority(host, port), proxy, status_line),
+        _ => anyhow::bail!("Proxy {} rejected CONNECT: {}", proxy, status_line),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use tokio::net::TcpListener;
+    use tokio::task::JoinHandle;
+
+    use super::*;
+
+    fn proxy(kind: ProxyKind, addr: SocketAddr, credentials: bool) -> ProxyConfig {
+        ProxyConfig {
+            kind,
+            host: addr.ip().to_string(),
+            port: addr.port(),
+            credentials: credentials.then(|| ProxyCredentials {
+                username: "user".to_string(),
+                password: "p@ss".to_string(),
+            }),
+        }
+    }
+
+    // An HTTP proxy that answers one CONNECT with `reply` and returns the request header
+    async fn http_proxy(reply: &'static [u8]) -> (SocketAddr, JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            while !request.ends_with(b"\r\n\r\n") {
+                request.push(stream.read_u8().await.unwrap());
+            }
+            stream.write_all(reply).await.unwrap();
+            String::from_utf8(request).unwrap()
+        });
+        (addr, server)
+    }
+
+    #[test]
+    fn parse_proxy_urls() {
+        let config = ProxyConfig::parse("http://user:p%40ss@[::1]:3128").unwrap();
+        assert_eq!(config.kind, ProxyKind::HttpConnect);
+        assert_eq!(config.host, "::1");
+        assert_eq!(config.to_string(), "http://[::1]:3128");
+        assert_eq!(config.credentials.as_ref().unwrap().password, "p@ss");
+        assert!(!format!("{:?}", config).contains("p@ss"));
+
+        let config = ProxyConfig::parse("socks5h://proxy.local").unwrap();
+        assert_eq!((config.kind, config.port), (ProxyKind::Socks5, 1080));
+        assert!(config.credentials.is_none());
+        assert!(ProxyConfig::parse("ftp://proxy.local").is_err());
+    }
+
+    #[tokio::test]
+    async fn http_connect_to_ipv6_target() {
+        let (addr, server) = http_proxy(b"HTTP/1.1 200 Connection established\r\n\r\nhello").await;
+        let mut stream = connect_via_proxy(&proxy(ProxyKind::HttpConnect, addr, true), "::1", 9000).await.unwrap();
+
+        let request = server.await.unwrap();
+        assert!(request.starts_with("CONNECT [::1]:9000 HTTP/1.1\r\nHost: [::1]:9000\r\n"), "{}", request);
+        let token = general_purpose::STANDARD.encode("user:p@ss");
+        assert!(request.contains(&format!("Proxy-Authorization: Basic {}\r\n", token)));
+
+        // Bytes after the response header belong to the tunnel
+        let mut tunneled = String::new();
+        stream.read_to_string(&mut tunneled).await.unwrap();
+        assert_eq!(tunneled, "hello");
+    }
+
+    #[tokio::test]
+    async fn http_connect_failures() {
+        let (addr, _) = http_proxy(b"HTTP/1.1 503 Service Unavailable\r\n\r\n").await;
+        let err = connect_via_proxy(&proxy(ProxyKind::HttpConnect, addr, false), "fix.example", 9000).await.unwrap_err();
+        assert!(err.to_string().starts_with("Exchange fix.example:9000 unreachable"), "{}", err);
+
+        let (addr, _) = http_proxy(b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n").await;
+        let err = connect_via_proxy(&proxy(ProxyKind::HttpConnect, addr, false), "fix.example", 9000).await.unwrap_err();
+        assert!(err.to_string().contains("rejected CONNECT"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn socks5_connect_to_ipv6_target() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            // Greeting, then username/password authentication
+            let mut greeting = [0u8; 2];
+            stream.read_exact(&mut greeting).await.unwrap();
+            let mut methods = vec![0u8; greeting[1] as usize];
+            stream.read_exact(&mut methods).await.unwrap();
+            assert!(methods.contains(&2));
+            stream.write_all(&[5, 2]).await.unwrap();
+
+            let mut auth = Vec::new();
+            assert_eq!(stream.read_u8().await.unwrap(), 1);
+            for _ in 0..2 {
+                let mut value = vec![0u8; stream.read_u8().await.unwrap() as usize];
+                stream.read_exact(&mut value).await.unwrap();
+                auth.push(String::from_utf8(value).unwrap());
+            }
+            assert_eq!(auth, ["user", "p@ss"]);
+            stream.write_all(&[1, 0]).await.unwrap();
+
+            // CONNECT with an IPv6 address (ATYP 4)
+            let mut request = [0u8; 4 + 16 + 2];
+            stream.read_exact(&mut request).await.unwrap();
+            assert_eq!(request[..4], [5, 1, 0, 4]);
+            assert_eq!(request[4..20], "::1".parse::<std::net::Ipv6Addr>().unwrap().octets());
+            assert_eq!(u16::from_be_bytes([request[20], request[21]]), 9000);
+            stream.write_all(&[5, 0, 0, 1, 127, 0, 0, 1, 0, 0]).await.unwrap();
+            stream.write_all(b"hello").await.unwrap();
+        });
+
+        let mut stream = connect_via_proxy(&proxy(ProxyKind::Socks5, addr, true), "::1", 9000).await.unwrap();
+        let mut tunneled = String::new();
+        stream.read_to_string(&mut tunneled).await.unwrap();
+        assert_eq!(tunneled, "hello");
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn unreachable_proxy_is_named() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let err = connect_via_proxy(&proxy(ProxyKind::Socks5, addr, false), "fix.example", 9000).await.unwrap_err();
+        assert!(err.to_string().starts_with(&format!("Proxy socks5://{} unreachable", addr)), "{}", err);
+    }
+}
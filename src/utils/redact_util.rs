@@ -0,0 +1,125 @@
+use std::collections::HashSet;
+use std::env;
+use std::sync::OnceLock;
+
+// Username (API key), RawData (logon signature) and Password
+const DEFAULT_SENSITIVE_TAGS: [&str; 3] = ["553", "96", "554"];
+
+static REDACTOR: OnceLock<Redactor> = OnceLock::new();
+
+
+/// Masks sensitive FIX fields before they reach any log or export.
+/// Extra tags come from BINANCE_LOG_REDACT_TAGS (comma separated) and
+/// BINANCE_LOG_UNREDACTED=true disables masking entirely.
+pub struct Redactor {
+    sensitive_tags: HashSet<String>,
+    unredacted: bool,
+}
+
+impl Redactor {
+    /// Masks the default tags and `extra_tags`, or nothing when `unredacted`
+    pub fn new<'a>(extra_tags: impl IntoIterator<Item = &'a str>, unredacted: bool) -> Self {
+        let sensitive_tags = DEFAULT_SENSITIVE_TAGS
+            .into_iter()
+            .chain(extra_tags.into_iter().map(str::trim).filter(|tag| !tag.is_empty()))
+            .map(str::to_string)
+            .collect();
+        Self {
+            sensitive_tags,
+            unredacted,
+        }
+    }
+
+    pub fn from_env() -> Self {
+        let extra = env::var("BINANCE_LOG_REDACT_TAGS").unwrap_or_default();
+        let unredacted = matches!(
+            env::var("BINANCE_LOG_UNREDACTED").as_deref().map(str::to_ascii_lowercase).as_deref(),
+            Ok("1" | "true" | "yes")
+        );
+        if unredacted {
+            log::warn!("BINANCE_LOG_UNREDACTED is set, credentials and signatures will be logged in full");
+        }
+
+        Self::new(extra.split(','), unredacted)
+    }
+
+    /// Renders a FIX message with `|` separators and sensitive values masked, keeping their length.
+    pub fn redact(&self, message: &str) -> String {
+        self.mask(message).replace('\x01', "|")
+    }
+
+    /// Masks sensitive values like `redact` but keeps the SOH separators, so the message still
+    /// parses as FIX.
+    pub fn mask(&self, message: &str) -> String {
+        if self.unredacted {
+            return message.to_string();
+        }
+
+        message
+            .split('\x01')
+            .map(|field| match field.split_once('=') {
+                Some((tag, value)) if self.sensitive_tags.contains(tag) => {
+                    format!("{}=***({})", tag, value.len())
+                }
+                _ => field.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("\x01")
+    }
+}
+
+/// Formats a FIX message for logging through the process wide redactor.
+pub fn redact_fix(message: &str) -> String {
+    REDACTOR.get_or_init(Redactor::from_env).redact(message)
+}
+
+/// Masks a FIX message for storage through the process wide redactor, e.g. in a raw capture.
+pub fn mask_fix(message: &str) -> String {
+    REDACTOR.get_or_init(Redactor::from_env).mask(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOGON: &str = "8=FIX.4.4\x0135=A\x01553=apikey\x0196=c2lnbmF0dXJl\x01554=hunter2\x0158=note\x01";
+
+    #[test]
+    fn masks_credentials_by_default() {
+        let redactor = Redactor::new([], false);
+        assert_eq!(
+            redactor.mask(LOGON),
+            "8=FIX.4.4\x0135=A\x01553=***(6)\x0196=***(12)\x01554=***(7)\x0158=note\x01"
+        );
+        assert_eq!(
+            redactor.redact(LOGON),
+            "8=FIX.4.4|35=A|553=***(6)|96=***(12)|554=***(7)|58=note|"
+        );
+    }
+
+    #[test]
+    fn masks_extra_tags() {
+        let redactor = Redactor::new([" 58", ""], false);
+        assert!(redactor.mask(LOGON).contains("\x0158=***(4)\x01"));
+    }
+
+    #[test]
+    fn masks_a_last_value_without_soh() {
+        let redactor = Redactor::new([], false);
+        assert_eq!(redactor.mask("35=A\x01554=hunter2"), "35=A\x01554=***(7)");
+    }
+
+    #[test]
+    fn unredacted_keeps_the_message() {
+        let redactor = Redactor::new(["58"], true);
+        assert_eq!(redactor.mask(LOGON), LOGON);
+    }
+
+    #[test]
+    fn mask_fix_masks_the_defaults() {
+        // Whatever the environment adds, the defaults stay masked unless masking is off
+        if !REDACTOR.get_or_init(Redactor::from_env).unredacted {
+            assert!(mask_fix(LOGON).contains("\x01553=***(6)\x01"));
+        }
+    }
+}
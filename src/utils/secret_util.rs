@@ -0,0 +1,211 @@
+use std::env;
+use std::fmt;
+use std::io::{self, BufRead, IsTerminal};
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::Mutex;
+use zeroize::Zeroizing;
+
+// Sessions may load credentials concurrently, only one prompt at a time
+static PROMPT_LOCK: Mutex<()> = Mutex::new(());
+
+
+/// A source of secret material such as an API key, a private key or a passphrase.
+pub trait SecretProvider: Send + Sync + fmt::Display {
+    fn fetch(&self) -> anyhow::Result<Zeroizing<String>>;
+}
+
+/// `env:NAME`
+pub struct EnvSecret {
+    pub name: String,
+}
+
+/// `file:/path/to/secret`, refused when readable by other users
+pub struct FileSecret {
+    pub path: PathBuf,
+}
+
+/// `prompt` or `prompt:Custom prompt text`, read from the terminal without echo, or as one line
+/// from stdin when stdin is not a terminal, e.g. piped in by a service manager
+pub struct PromptSecret {
+    pub prompt: String,
+}
+
+/// `cmd:some command`, the trimmed stdout of the command is the secret
+pub struct CommandSecret {
+    pub command: String,
+}
+
+impl fmt::Display for EnvSecret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "env:{}", self.name)
+    }
+}
+
+impl fmt::Display for FileSecret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "file:{}", self.path.display())
+    }
+}
+
+impl fmt::Display for PromptSecret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "prompt:{}", self.prompt)
+    }
+}
+
+impl fmt::Display for CommandSecret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cmd:{}", self.command)
+    }
+}
+
+impl SecretProvider for EnvSecret {
+    fn fetch(&self) -> anyhow::Result<Zeroizing<String>> {
+        env::var(&self.name)
+            .map(Zeroizing::new)
+            .map_err(|_| anyhow::anyhow!("Environment variable {} is not set", self.name))
+    }
+}
+
+impl SecretProvider for FileSecret {
+    fn fetch(&self) -> anyhow::Result<Zeroizing<String>> {
+        check_file_permissions(&self.path)?;
+        let contents = std::fs::read_to_string(&self.path)
+            .map_err(|e| anyhow::anyhow!("Unable to read {}: {}", self.path.display(), e))?;
+        Ok(Zeroizing::new(contents))
+    }
+}
+
+impl SecretProvider for PromptSecret {
+    fn fetch(&self) -> anyhow::Result<Zeroizing<String>> {
+        let _guard = PROMPT_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let stdin = io::stdin();
+        if !stdin.is_terminal() {
+            return read_secret_line(stdin.lock())
+                .map_err(|e| anyhow::anyhow!("Unable to read '{}' from stdin: {}", self.prompt, e));
+        }
+        let value = rpassword::prompt_password(format!("{}: ", self.prompt))
+            .map_err(|e| anyhow::anyhow!("Unable to read '{}' from the terminal: {}", self.prompt, e))?;
+        Ok(Zeroizing::new(value))
+    }
+}
+
+impl SecretProvider for CommandSecret {
+    fn fetch(&self) -> anyhow::Result<Zeroizing<String>> {
+        let output = shell_command(&self.command)
+            .output()
+            .map_err(|e| anyhow::anyhow!("Unable to run secret command '{}': {}", self.command, e))?;
+
+        if !output.status.success() {
+            anyhow::bail!(
+                "Secret command '{}' failed with {}: {}",
+                self.command,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        let stdout = Zeroizing::new(output.stdout);
+        let value = std::str::from_utf8(&stdout)
+            .map_err(|_| anyhow::anyhow!("Secret command '{}' printed non UTF-8 output", self.command))?;
+        Ok(Zeroizing::new(value.trim_end_matches(['\r', '\n']).to_string()))
+    }
+}
+
+// One line without its line ending, an empty input is an error rather than an empty secret
+fn read_secret_line(mut reader: impl BufRead) -> anyhow::Result<Zeroizing<String>> {
+    let mut line = Zeroizing::new(String::new());
+    if reader.read_line(&mut line)? == 0 {
+        anyhow::bail!("no input");
+    }
+    Ok(Zeroizing::new(line.trim_end_matches(['\r', '\n']).to_string()))
+}
+
+/// Parses `env:NAME`, `file:PATH`, `prompt[:TEXT]` or `cmd:COMMAND`
+pub fn parse_secret_source(spec: &str) -> anyhow::Result<Box<dyn SecretProvider>> {
+    let (kind, value) = spec.split_once(':').unwrap_or((spec, ""));
+    let value = value.trim();
+
+    let provider: Box<dyn SecretProvider> = match kind.trim() {
+        "env" if !value.is_empty() => Box::new(EnvSecret { name: value.to_string() }),
+        "file" if !value.is_empty() => Box::new(FileSecret { path: PathBuf::from(value) }),
+        "cmd" if !value.is_empty() => Box::new(CommandSecret { command: value.to_string() }),
+        "prompt" => Box::new(PromptSecret {
+            prompt: if value.is_empty() { "Secret".to_string() } else { value.to_string() },
+        }),
+        _ => anyhow::bail!(
+            "Invalid secret source '{}', expected env:NAME, file:PATH, prompt[:TEXT] or cmd:COMMAND",
+            spec
+        ),
+    };
+    Ok(provider)
+}
+
+#[cfg(unix)]
+fn check_file_permissions(path: &std::path::Path) -> anyhow::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let metadata = std::fs::metadata(path)
+        .map_err(|e| anyhow::anyhow!("Unable to stat {}: {}", path.display(), e))?;
+    let mode = metadata.permissions().mode();
+    if mode & 0o007 != 0 {
+        anyhow::bail!(
+            "Refusing to read {}: it is accessible by other users (mode {:o}), run `chmod 600` on it",
+            path.display(),
+            mode & 0o777
+        );
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_file_permissions(_path: &std::path::Path) -> anyhow::Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn shell_command(command: &str) -> Command {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg(command);
+    cmd
+}
+
+#[cfg(not(unix))]
+fn shell_command(command: &str) -> Command {
+    let mut cmd = Command::new("cmd");
+    cmd.arg("/C").arg(command);
+    cmd
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secret_line_from_a_pipe() {
+        let secret = read_secret_line("hunter2\r\nsecond line\n".as_bytes()).unwrap();
+        assert_eq!(secret.as_str(), "hunter2");
+        assert_eq!(read_secret_line("last".as_bytes()).unwrap().as_str(), "last");
+        assert!(read_secret_line("".as_bytes()).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn file_readable_by_others_is_refused() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = env::temp_dir().join(format!("secret-{}", std::process::id()));
+        std::fs::write(&path, "hunter2").unwrap();
+        let secret = FileSecret { path: path.clone() };
+
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o604)).unwrap();
+        let err = secret.fetch().map(|_| ()).unwrap_err().to_string();
+        assert!(err.contains("accessible by other users (mode 604)"), "{}", err);
+
+        // Group access is the owner's choice
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o640)).unwrap();
+        assert_eq!(secret.fetch().unwrap().as_str(), "hunter2");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,393 @@
+use std::path::PathBuf;
+use base64::{engine::general_purpose, Engine as _};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::utils::config_util::session_var;
+
+#[cfg(not(any(feature = "native-tls", feature = "rustls")))]
+compile_error!("Enable either the `native-tls` or the `rustls` feature");
+
+// rustls takes precedence when both backends are enabled
+#[cfg(feature = "rustls")]
+pub type TlsStream<S> = tokio_rustls::client::TlsStream<S>;
+#[cfg(all(feature = "native-tls", not(feature = "rustls")))]
+pub type TlsStream<S> = tokio_native_tls::TlsStream<S>;
+
+
+#[derive(Debug, Clone, Default)]
+pub struct TlsConfig {
+    /// Extra PEM root certificates trusted on top of the backend defaults
+    pub ca_bundle: Option<PathBuf>,
+    /// SHA-256 fingerprints of the accepted leaf certificates (DER)
+    pub pinned_cert_sha256: Vec<[u8; 32]>,
+    /// SHA-256 hashes of the accepted leaf SubjectPublicKeyInfo (DER)
+    pub pinned_spki_sha256: Vec<[u8; 32]>,
+}
+
+impl TlsConfig {
+    pub fn from_env(session: &str) -> anyhow::Result<Self> {
+        let ca_bundle = session_var(session, "TLS_CA_BUNDLE").map(PathBuf::from);
+        let pinned_cert_sha256 = parse_pin_list(session_var(session, "TLS_PINNED_CERT_SHA256"))?;
+        let pinned_spki_sha256 = parse_pin_list(session_var(session, "TLS_PINNED_SPKI_SHA256"))?;
+
+        Ok(Self {
+            ca_bundle,
+            pinned_cert_sha256,
+            pinned_spki_sha256,
+        })
+    }
+
+    fn has_pins(&self) -> bool {
+        !self.pinned_cert_sha256.is_empty() || !self.pinned_spki_sha256.is_empty()
+    }
+}
+
+// Comma separated list of hex ("ab:cd:..." or "abcd...") or base64 ("sha256/...") hashes
+fn parse_pin_list(value: Option<String>) -> anyhow::Result<Vec<[u8; 32]>> {
+    let Some(value) = value else {
+        return Ok(Vec::new());
+    };
+
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|pin| !pin.is_empty())
+        .map(parse_pin)
+        .collect()
+}
+
+fn parse_pin(pin: &str) -> anyhow::Result<[u8; 32]> {
+    let pin = pin.strip_prefix("sha256/").unwrap_or(pin);
+    let hex: String = pin.chars().filter(|c| *c != ':').collect();
+
+    let bytes = if hex.len() == 64 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        (0..64)
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()?
+    } else {
+        general_purpose::STANDARD.decode(pin)?
+    };
+
+    bytes
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Pin '{}' is not a SHA-256 hash", pin))
+}
+
+pub async fn tls_handshake<S>(
+    hostname: &str,
+    stream: S,
+    config: &TlsConfig,
+) -> anyhow::Result<TlsStream<S>>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (stream, leaf_cert) = backend::connect(hostname, stream, config).await?;
+
+    if config.has_pins() {
+        let leaf_cert = leaf_cert
+            .ok_or_else(|| anyhow::anyhow!("Server {} presented no certificate", hostname))?;
+        verify_pins(&leaf_cert, config)?;
+    }
+
+    Ok(stream)
+}
+
+fn verify_pins(cert_der: &[u8], config: &TlsConfig) -> anyhow::Result<()> {
+    let cert_hash: [u8; 32] = Sha256::digest(cert_der).into();
+    if config.pinned_cert_sha256.contains(&cert_hash) {
+        return Ok(());
+    }
+
+    if !config.pinned_spki_sha256.is_empty() {
+        let spki = extract_spki(cert_der)
+            .ok_or_else(|| anyhow::anyhow!("Unable to parse SubjectPublicKeyInfo from server certificate"))?;
+        let spki_hash: [u8; 32] = Sha256::digest(spki).into();
+        if config.pinned_spki_sha256.contains(&spki_hash) {
+            return Ok(());
+        }
+        anyhow::bail!(
+            "Server certificate does not match any pin (cert sha256 {}, spki sha256/{})",
+            to_hex(&cert_hash),
+            general_purpose::STANDARD.encode(spki_hash),
+        );
+    }
+
+    anyhow::bail!(
+        "Server certificate does not match any pin (cert sha256 {})",
+        to_hex(&cert_hash),
+    )
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+struct DerElement<'a> {
+    tag: u8,
+    raw: &'a [u8],
+    contents: &'a [u8],
+}
+
+// Splits the first DER element off the input, returning it with the remaining bytes
+fn der_element(input: &[u8]) -> Option<(DerElement<'_>, &[u8])> {
+    let tag = *input.first()?;
+    let first_len = *input.get(1)? as usize;
+
+    let (len, header_len) = if first_len < 0x80 {
+        (first_len, 2)
+    } else {
+        let num_bytes = first_len & 0x7f;
+        if num_bytes == 0 || num_bytes > 4 {
+            return None;
+        }
+        let len_bytes = input.get(2..2 + num_bytes)?;
+        let len = len_bytes.iter().fold(0usize, |acc, b| (acc << 8) | *b as usize);
+        (len, 2 + num_bytes)
+    };
+
+    let end = header_len.checked_add(len)?;
+    let raw = input.get(..end)?;
+    let element = DerElement {
+        tag,
+        raw,
+        contents: &raw[header_len..],
+    };
+    Some((element, &input[end..]))
+}
+
+// Certificate ::= SEQUENCE { tbsCertificate, ... }
+// TBSCertificate ::= SEQUENCE { [0] version OPTIONAL, serial, signature, issuer, validity, subject, subjectPublicKeyInfo, ... }
+fn extract_spki(cert_der: &[u8]) -> Option<&[u8]> {
+    let (cert, _) = der_element(cert_der)?;
+    let (tbs, _) = der_element(cert.contents)?;
+
+    let mut rest = tbs.contents;
+    if rest.first() == Some(&0xa0) {
+        rest = der_element(rest)?.1;
+    }
+    // serial, signature, issuer, validity, subject
+    for _ in 0..5 {
+        rest = der_element(rest)?.1;
+    }
+
+    let (spki, _) = der_element(rest)?;
+    (spki.tag == 0x30).then_some(spki.raw)
+}
+
+#[cfg(feature = "rustls")]
+mod backend {
+    use std::sync::Arc;
+    use tokio::io::{AsyncRead, AsyncWrite};
+    use tokio_rustls::rustls::{self, pki_types::{pem::PemObject, CertificateDer, ServerName}};
+    use tokio_rustls::TlsConnector;
+
+    use super::{TlsConfig, TlsStream};
+
+    pub async fn connect<S>(
+        hostname: &str,
+        stream: S,
+        config: &TlsConfig,
+    ) -> anyhow::Result<(TlsStream<S>, Option<Vec<u8>>)>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let mut roots = rustls::RootCertStore::empty();
+        roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+
+        if let Some(path) = &config.ca_bundle {
+            for cert in CertificateDer::pem_file_iter(path)? {
+                roots.add(cert?)?;
+            }
+        }
+
+        let client_config = rustls::ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+        let connector = TlsConnector::from(Arc::new(client_config));
+        let server_name = ServerName::try_from(hostname.to_string())?;
+        let stream = connector.connect(server_name, stream).await?;
+
+        let leaf_cert = stream
+            .get_ref()
+            .1
+            .peer_certificates()
+            .and_then(|certs| certs.first())
+            .map(|cert| cert.to_vec());
+
+        Ok((stream, leaf_cert))
+    }
+}
+
+#[cfg(all(feature = "native-tls", not(feature = "rustls")))]
+mod backend {
+    use tokio::io::{AsyncRead, AsyncWrite};
+    use tokio_native_tls::TlsConnector;
+
+    use super::{TlsConfig, TlsStream};
+
+    pub async fn connect<S>(
+        hostname: &str,
+        stream: S,
+        config: &TlsConfig,
+    ) -> anyhow::Result<(TlsStream<S>, Option<Vec<u8>>)>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let mut builder = native_tls::TlsConnector::builder();
+
+        if let Some(path) = &config.ca_bundle {
+            let bundle = std::fs::read_to_string(path)?;
+            for pem in split_pem_certificates(&bundle) {
+                builder.add_root_certificate(native_tls::Certificate::from_pem(pem.as_bytes())?);
+            }
+        }
+
+        let connector = TlsConnector::from(builder.build()?);
+        let stream = connector.connect(hostname, stream).await?;
+
+        let leaf_cert = match stream.get_ref().peer_certificate()? {
+            Some(cert) => Some(cert.to_der()?),
+            None => None,
+        };
+
+        Ok((stream, leaf_cert))
+    }
+
+    // native-tls only parses one certificate per PEM buffer
+    fn split_pem_certificates(bundle: &str) -> Vec<&str> {
+        const END: &str = "-----END CERTIFICATE-----";
+
+        let mut certs = Vec::new();
+        let mut rest = bundle;
+        while let Some(start) = rest.find("-----BEGIN CERTIFICATE-----") {
+            let Some(end) = rest[start..].find(END) else {
+                break;
+            };
+            let end = start + end + END.len();
+            certs.push(&rest[start..end]);
+            rest = &rest[end..];
+        }
+        certs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::*;
+
+    const CERT_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/utils/testdata/localhost_cert.pem");
+    const CERT_PEM: &str = include_str!("testdata/localhost_cert.pem");
+    const KEY_PEM: &str = include_str!("testdata/localhost_key.pem");
+
+    fn cert_der() -> Vec<u8> {
+        let base64: String = CERT_PEM.lines().filter(|line| !line.starts_with("-----")).collect();
+        general_purpose::STANDARD.decode(base64).unwrap()
+    }
+
+    #[cfg(feature = "rustls")]
+    async fn accept(stream: TcpStream) -> anyhow::Result<()> {
+        use std::sync::Arc;
+        use tokio_rustls::rustls::{self, pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer}};
+
+        let certs = vec![CertificateDer::from_pem_slice(CERT_PEM.as_bytes())?];
+        let key = PrivateKeyDer::from_pem_slice(KEY_PEM.as_bytes())?;
+        let config = rustls::ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_single_cert(certs, key)?;
+        let mut stream = tokio_rustls::TlsAcceptor::from(Arc::new(config)).accept(stream).await?;
+        stream.write_all(b"ok").await?;
+        stream.shutdown().await?;
+        Ok(())
+    }
+
+    #[cfg(all(feature = "native-tls", not(feature = "rustls")))]
+    async fn accept(stream: TcpStream) -> anyhow::Result<()> {
+        let identity = native_tls::Identity::from_pkcs8(CERT_PEM.as_bytes(), KEY_PEM.as_bytes())?;
+        let acceptor = tokio_native_tls::TlsAcceptor::from(native_tls::TlsAcceptor::new(identity)?);
+        let mut stream = acceptor.accept(stream).await?;
+        stream.write_all(b"ok").await?;
+        stream.shutdown().await?;
+        Ok(())
+    }
+
+    // Handshakes with a loopback server presenting the self-signed localhost certificate
+    async fn handshake(config: &TlsConfig) -> anyhow::Result<String> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await?;
+            accept(stream).await
+        });
+
+        let stream = TcpStream::connect(addr).await?;
+        let result = async {
+            let mut stream = tls_handshake("localhost", stream, config).await?;
+            let mut reply = String::new();
+            stream.read_to_string(&mut reply).await?;
+            Ok(reply)
+        }
+        .await;
+        server.abort();
+        result
+    }
+
+    fn trusted() -> TlsConfig {
+        TlsConfig {
+            ca_bundle: Some(PathBuf::from(CERT_PATH)),
+            ..TlsConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn ca_bundle_is_trusted() {
+        assert_eq!(handshake(&trusted()).await.unwrap(), "ok");
+        assert!(handshake(&TlsConfig::default()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn pins_are_checked() {
+        let cert_hash: [u8; 32] = Sha256::digest(cert_der()).into();
+        let spki_hash: [u8; 32] = Sha256::digest(extract_spki(&cert_der()).unwrap()).into();
+
+        let config = TlsConfig {
+            pinned_cert_sha256: vec![cert_hash],
+            ..trusted()
+        };
+        assert_eq!(handshake(&config).await.unwrap(), "ok");
+
+        let config = TlsConfig {
+            pinned_spki_sha256: vec![spki_hash],
+            ..trusted()
+        };
+        assert_eq!(handshake(&config).await.unwrap(), "ok");
+
+        let config = TlsConfig {
+            pinned_cert_sha256: vec![[0; 32]],
+            ..trusted()
+        };
+        let err = handshake(&config).await.unwrap_err();
+        assert!(err.to_string().contains(&to_hex(&cert_hash)), "{}", err);
+    }
+
+    #[test]
+    fn pin_formats() {
+        let hex = "ab".repeat(32);
+        let colons = vec!["ab"; 32].join(":");
+        let base64 = format!("sha256/{}", general_purpose::STANDARD.encode([0xab; 32]));
+        let pins = parse_pin_list(Some(format!("{}, {},{}", hex, colons, base64))).unwrap();
+
+        assert_eq!(pins, vec![[0xab; 32]; 3]);
+        assert!(parse_pin("abcd").is_err());
+        assert!(parse_pin_list(None).unwrap().is_empty());
+    }
+}